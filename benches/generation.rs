@@ -0,0 +1,135 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use dungeons::dungeon_generation::dungeon_state::DungeonStateBuilder;
+use dungeons::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+use dungeons::dungeon_generation::room::{Collision, Room};
+use dungeons::dungeon_generation::room_outline::RoomOutline;
+
+const RECIPES: [(&str, &str); 4] = [
+    (
+        "default",
+        include_str!("../assets/recipes/default.recipe.ron"),
+    ),
+    ("bsp", include_str!("../assets/recipes/bsp.recipe.ron")),
+    ("cave", include_str!("../assets/recipes/cave.recipe.ron")),
+    ("wfc", include_str!("../assets/recipes/wfc.recipe.ron")),
+];
+
+/// A thousand rooms joined by straight corridors.
+const LARGE_FLOOR: &str = r#"(
+    params: (room_count: (base: 1000)),
+    steps: [
+        (step: "add_room"),
+        (step: "add_rooms_to_count", max_attempts: 100000),
+    ],
+)"#;
+
+fn generate_recipes(c: &mut Criterion) {
+    let registry = StepRegistry::default();
+    let mut group = c.benchmark_group("generate");
+
+    for (name, source) in RECIPES {
+        let generator = GenerationRecipe::parse(source)
+            .and_then(|recipe| recipe.to_generator(&registry))
+            .unwrap();
+        let mut seed = 0;
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                seed += 1;
+                generator.generate_with_seed(seed)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn generate_large_floor(c: &mut Criterion) {
+    let generator = GenerationRecipe::parse(LARGE_FLOOR)
+        .and_then(|recipe| recipe.to_generator(&StepRegistry::default()))
+        .unwrap();
+    let mut group = c.benchmark_group("generate");
+    group.sample_size(10);
+
+    let mut seed = 0;
+    group.bench_function("1000 rooms", |b| {
+        b.iter(|| {
+            seed += 1;
+            generator.generate_with_seed(seed)
+        })
+    });
+
+    group.finish();
+}
+
+/// What a step pays to build on a floor of a thousand rooms: adding a room to the state, against
+/// copying every room into a new `Vec` first as states did before they shared their rooms.
+fn update_large_state(c: &mut Criterion) {
+    let state = GenerationRecipe::parse(LARGE_FLOOR)
+        .and_then(|recipe| recipe.to_generator(&StepRegistry::default()))
+        .unwrap()
+        .generate_with_seed(0)
+        .unwrap();
+    let room = state.layout.rooms[0].clone();
+    let mut group = c.benchmark_group("add a room to 1000");
+
+    group.bench_function("shared state", |b| {
+        b.iter(|| {
+            DungeonStateBuilder::from_state(&state)
+                .push_room(room.clone())
+                .build()
+        })
+    });
+    group.bench_function("copied Vec", |b| {
+        b.iter(|| {
+            let mut rooms: Vec<Room> = state.layout.rooms.iter().cloned().collect();
+            rooms.push(room.clone());
+            rooms
+        })
+    });
+
+    group.finish();
+}
+
+/// Checking a new room for collisions on a floor of a thousand rooms: through the layout's
+/// spatial index, against every room and corridor in turn as steps did before the index.
+fn collide_in_large_floor(c: &mut Criterion) {
+    let state = GenerationRecipe::parse(LARGE_FLOOR)
+        .and_then(|recipe| recipe.to_generator(&StepRegistry::default()))
+        .unwrap()
+        .generate_with_seed(0)
+        .unwrap();
+    let layout = &state.layout;
+    let room = Room {
+        outline: RoomOutline::Rectangle,
+        ..layout.rooms[layout.rooms.len() / 2].clone()
+    };
+    let mut group = c.benchmark_group("collide with 1000");
+
+    group.bench_function("spatial index", |b| b.iter(|| layout.colliding(&room)));
+    group.bench_function("every shape", |b| {
+        b.iter(|| {
+            let rooms = layout
+                .rooms
+                .iter()
+                .filter(|other| other.collides_with(&room));
+            let corridors = layout
+                .corridors
+                .iter()
+                .filter(|corridor| corridor.collides_with(&room));
+
+            rooms.count() + corridors.count()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    generate_recipes,
+    generate_large_floor,
+    update_large_state,
+    collide_in_large_floor
+);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Generates a recipe's floor for many seeds without opening a window and prints how it went.
+//!
+//! ```text
+//! cargo run --release --example generation_stats -- [recipe] [runs] [depth]
+//! ```
+//!
+//! The recipe defaults to `assets/recipes/default.recipe.ron`, runs to 1000 and depth to 0.
+
+use dungeons::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+use dungeons::dungeon_generation::stats::GenerationStats;
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or("assets/recipes/default.recipe.ron");
+    let runs: u64 = args
+        .get(1)
+        .and_then(|runs| runs.parse().ok())
+        .unwrap_or(1000);
+    let depth: usize = args
+        .get(2)
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(0);
+
+    let generator = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read {path}: {err}"))
+        .and_then(|source| GenerationRecipe::parse(&source))
+        .and_then(|recipe| recipe.to_generator(&StepRegistry::default()));
+
+    let generator = match generator {
+        Ok(generator) => generator.depth(depth),
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("{path}, depth {depth}, seeds 0..{runs}\n");
+    print!("{}", GenerationStats::measure(&generator, 0..runs));
+
+    ExitCode::SUCCESS
+}
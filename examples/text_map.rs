@@ -0,0 +1,48 @@
+//! Generates a recipe's floor from one seed without opening a window and prints it as a text map.
+//!
+//! ```text
+//! cargo run --example text_map -- [recipe] [seed] [depth]
+//! ```
+//!
+//! The recipe defaults to `assets/recipes/default.recipe.ron`, seed to 0 and depth to 0.
+
+use dungeons::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+use dungeons::text_map::TextMap;
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or("assets/recipes/default.recipe.ron");
+    let seed: u64 = args.get(1).and_then(|seed| seed.parse().ok()).unwrap_or(0);
+    let depth: usize = args
+        .get(2)
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(0);
+
+    let floor = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read {path}: {err}"))
+        .and_then(|source| GenerationRecipe::parse(&source))
+        .and_then(|recipe| recipe.to_generator(&StepRegistry::default()))
+        .and_then(|generator| {
+            generator
+                .depth(depth)
+                .generate_with_seed(seed)
+                .map_err(|err| err.to_string())
+        });
+
+    match floor {
+        Ok(floor) => {
+            print!("{}", TextMap::from_state(&floor));
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
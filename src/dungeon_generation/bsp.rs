@@ -0,0 +1,294 @@
+use crate::dungeon_generation::corridor_shapes::fits;
+use crate::dungeon_generation::dungeon_state::{
+    DungeonLayout, DungeonRng, DungeonState, DungeonStateBuilder,
+};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::loops::{connections, gap};
+use crate::dungeon_generation::room::{Corridor, Rectangle, Room};
+use crate::dungeon_generation::room_outline::RoomOutline;
+use bevy::math::IVec2;
+use im::Vector;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::ops::Range;
+
+/// The area a BSP floor is split from, with its bottom left corner at the origin.
+pub const BSP_AREA: Rectangle = Rectangle {
+    width: 64,
+    height: 48,
+};
+
+/// Partitions are never cut into parts narrower than this.
+const MIN_PARTITION: u32 = 10;
+
+const MIN_ROOM: u32 = 5;
+
+/// A part of the BSP area, cut further or given a room.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Partition {
+    shape: Rectangle,
+    position: IVec2,
+}
+
+/// Generator step that lays out a whole floor by binary space partition: `BSP_AREA` is cut in
+/// two again and again until the parts are too small to cut, each part gets a room, and the two
+/// halves of every cut are joined by a corridor between their nearest rooms. The floor is a
+/// tree of rooms, so follow the step with `add_loop` for cycles. It must run on an empty layout.
+pub fn add_bsp_layout(state: &DungeonState) -> Result<DungeonState, StepError> {
+    if !state.layout.rooms.is_empty() || !state.layout.corridors.is_empty() {
+        return Err(StepError::Collision);
+    }
+
+    let mut rng = state.rng.lock().unwrap();
+
+    let mut rooms = Vec::new();
+    let mut cuts = Vec::new();
+    split(
+        Partition {
+            shape: BSP_AREA,
+            position: IVec2::ZERO,
+        },
+        &mut rng,
+        &mut rooms,
+        &mut cuts,
+    );
+
+    let mut layout = DungeonLayout::new(rooms, Vector::new());
+
+    for (first, second) in cuts {
+        let segments = join(&layout, first, second, &mut rng).ok_or(StepError::Collision)?;
+        for segment in segments {
+            layout.push_corridor(segment);
+        }
+    }
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .layout(layout)
+        .build())
+}
+
+/// Cuts `partition` until its parts are too small, pushing a room for every part. Rooms in the
+/// same part stay next to each other in `rooms`, so each cut is recorded as the two ranges of
+/// rooms on either side of it, smaller cuts before the larger ones containing them.
+fn split(
+    partition: Partition,
+    rng: &mut DungeonRng,
+    rooms: &mut Vec<Room>,
+    cuts: &mut Vec<(Range<usize>, Range<usize>)>,
+) {
+    let (first, second) = match cut(partition, rng) {
+        Some(halves) => halves,
+        None => {
+            rooms.push(room_in(partition, rng));
+            return;
+        }
+    };
+
+    let start = rooms.len();
+    split(first, rng, rooms, cuts);
+    let middle = rooms.len();
+    split(second, rng, rooms, cuts);
+
+    cuts.push((start..middle, middle..rooms.len()));
+}
+
+/// Cuts `partition` across its longer side, or returns `None` when neither side is long enough.
+fn cut(partition: Partition, rng: &mut DungeonRng) -> Option<(Partition, Partition)> {
+    let Partition { shape, position } = partition;
+    let can_cut_x = shape.width >= MIN_PARTITION * 2;
+    let can_cut_y = shape.height >= MIN_PARTITION * 2;
+
+    let cut_x = match (can_cut_x, can_cut_y) {
+        (false, false) => return None,
+        (true, false) => true,
+        (false, true) => false,
+        (true, true) if shape.width == shape.height => rng.gen_bool(0.5),
+        (true, true) => shape.width > shape.height,
+    };
+
+    if cut_x {
+        let at = rng.gen_range(MIN_PARTITION..=shape.width - MIN_PARTITION);
+        Some((
+            Partition {
+                shape: Rectangle {
+                    width: at,
+                    height: shape.height,
+                },
+                position,
+            },
+            Partition {
+                shape: Rectangle {
+                    width: shape.width - at,
+                    height: shape.height,
+                },
+                position: position + IVec2::new(at as i32, 0),
+            },
+        ))
+    } else {
+        let at = rng.gen_range(MIN_PARTITION..=shape.height - MIN_PARTITION);
+        Some((
+            Partition {
+                shape: Rectangle {
+                    width: shape.width,
+                    height: at,
+                },
+                position,
+            },
+            Partition {
+                shape: Rectangle {
+                    width: shape.width,
+                    height: shape.height - at,
+                },
+                position: position + IVec2::new(0, at as i32),
+            },
+        ))
+    }
+}
+
+/// A rectangular room filling most of `partition`, a tile away from each of its edges so rooms
+/// in neighbouring partitions never touch.
+fn room_in(partition: Partition, rng: &mut DungeonRng) -> Room {
+    let max_width = partition.shape.width - 2;
+    let max_height = partition.shape.height - 2;
+
+    let width = rng.gen_range((max_width * 2 / 3).max(MIN_ROOM)..=max_width);
+    let height = rng.gen_range((max_height * 2 / 3).max(MIN_ROOM)..=max_height);
+
+    let offset = IVec2::new(
+        rng.gen_range(1..=(max_width - width + 1) as i32),
+        rng.gen_range(1..=(max_height - height + 1) as i32),
+    );
+
+    Room {
+        shape: Rectangle { width, height },
+        position: partition.position + offset,
+        outline: RoomOutline::Rectangle,
+        role: None,
+    }
+}
+
+/// A corridor from a room in `first` to a room in `second` that fits in `layout`, trying the
+/// closest pair of rooms first.
+fn join(
+    layout: &DungeonLayout,
+    first: Range<usize>,
+    second: Range<usize>,
+    rng: &mut DungeonRng,
+) -> Option<Vec<Corridor>> {
+    let rooms = &layout.rooms;
+
+    let mut pairs: Vec<(i32, usize, usize)> = first
+        .flat_map(|a| second.clone().map(move |b| (a, b)))
+        .map(|(a, b)| (gap(&rooms[a], &rooms[b]), a, b))
+        .collect();
+    pairs.sort_unstable();
+
+    pairs.into_iter().find_map(|(_, a, b)| {
+        let mut options = connections(&rooms[a], &rooms[b], rng);
+        options.shuffle(rng);
+
+        options
+            .into_iter()
+            .find(|segments| fits(layout, segments, &[]))
+    })
+}
+
+#[cfg(test)]
+mod bsp_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::DungeonGenerator;
+    use crate::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+    use crate::dungeon_generation::room::Collision;
+    use rand::SeedableRng;
+
+    fn bsp_floor(seed: u64) -> DungeonState {
+        DungeonGenerator::with_seed(seed)
+            .add_named_step("add_bsp_layout", add_bsp_layout, 100)
+            .generate()
+            .unwrap()
+    }
+
+    #[test]
+    fn cuts_leave_no_part_too_small() {
+        let mut rng = DungeonRng::seed_from_u64(0);
+        let mut rooms = Vec::new();
+        let mut cuts = Vec::new();
+
+        split(
+            Partition {
+                shape: BSP_AREA,
+                position: IVec2::ZERO,
+            },
+            &mut rng,
+            &mut rooms,
+            &mut cuts,
+        );
+
+        assert_eq!(cuts.len(), rooms.len() - 1);
+        assert_eq!(cuts.last().unwrap().0.start, 0);
+        assert_eq!(cuts.last().unwrap().1.end, rooms.len());
+        for room in &rooms {
+            assert!(room.shape.width >= MIN_ROOM && room.shape.height >= MIN_ROOM);
+        }
+    }
+
+    #[test]
+    fn rooms_fill_the_area_without_overlapping() {
+        for seed in 0..10 {
+            let rooms = bsp_floor(seed).layout.rooms;
+
+            assert!(rooms.len() >= 6, "seed {seed}");
+            for (index, room) in rooms.iter().enumerate() {
+                assert!(room.position.x > 0 && room.position.y > 0);
+                assert!(room.position.x + (room.shape.width as i32) < BSP_AREA.width as i32);
+                assert!(room.position.y + (room.shape.height as i32) < BSP_AREA.height as i32);
+                assert!(rooms
+                    .iter()
+                    .skip(index + 1)
+                    .all(|other| !other.collides_with(room)));
+            }
+        }
+    }
+
+    #[test]
+    fn every_room_is_reachable() {
+        for seed in 0..10 {
+            let layout = bsp_floor(seed).layout;
+            let graph = layout.graph();
+
+            assert!(
+                graph.distances_from(0).iter().all(Option::is_some),
+                "seed {seed}"
+            );
+            assert_eq!(graph.loop_count(), 0, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn needs_an_empty_layout() {
+        let generator = DungeonGenerator::with_seed(0)
+            .add_named_step("add_bsp_layout", add_bsp_layout, 100)
+            .add_step(add_bsp_layout);
+
+        assert_eq!(
+            generator.generate().unwrap_err().reason,
+            StepError::Collision
+        );
+    }
+
+    #[test]
+    fn bsp_recipe_generates() {
+        let recipe =
+            GenerationRecipe::parse(include_str!("../../assets/recipes/bsp.recipe.ron")).unwrap();
+
+        for seed in 0..10 {
+            let dungeon = recipe
+                .to_generator(&StepRegistry::default())
+                .unwrap()
+                .seed(seed)
+                .generate();
+
+            assert!(dungeon.is_ok(), "seed {seed}");
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::dungeon_generation::dungeon_state::{
+    DungeonLayout, DungeonRng, DungeonState, DungeonStateBuilder,
+};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::key::KeyColour;
+use crate::dungeon_generation::room::{Collision, Rectangle, Room};
+use crate::dungeon_generation::room_outline::RoomOutline;
+use crate::dungeon_generation::solvability::flood_fill;
+use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+use crate::dungeon_generation::tile_map::{get_tile_map, TileMap, TileType};
+use bevy::math::IVec2;
+use bevy::utils::HashSet;
+use im::{vector, Vector};
+use rand::Rng;
+use std::collections::VecDeque;
+
+/// The area a cave floor is carved from, with its bottom left corner at the origin.
+pub const CAVE_AREA: Rectangle = Rectangle {
+    width: 64,
+    height: 48,
+};
+
+/// The chance each tile starts out as floor before smoothing.
+const CAVE_FILL: f64 = 0.5;
+
+const SMOOTHING_ROUNDS: usize = 5;
+
+/// Cave doors only go where they lock away at least this many floor tiles.
+const MIN_LOCKED_FLOOR: usize = 20;
+
+/// Caves whose largest area covers less than this share of `CAVE_AREA` are carved again.
+const MIN_CAVE_SHARE: f32 = 0.3;
+
+/// Generator step that carves a whole floor out of `CAVE_AREA` as one organic cave: random
+/// noise, smoothed by cellular automaton and trimmed to its largest connected area. The cave is
+/// stored as a single room with a mask outline, so `get_tile_map`, spawns and the solvability
+/// check work on it like on any other room. It must run on an empty layout.
+pub fn add_cave(state: &DungeonState) -> Result<DungeonState, StepError> {
+    if !state.layout.rooms.is_empty() || !state.layout.corridors.is_empty() {
+        return Err(StepError::Collision);
+    }
+
+    let mask = carve(CAVE_AREA, &mut state.rng.lock().unwrap());
+
+    let floor = mask.iter().filter(|floor| **floor).count();
+    if (floor as f32) < (CAVE_AREA.width * CAVE_AREA.height) as f32 * MIN_CAVE_SHARE {
+        return Err(StepError::OutOfBounds);
+    }
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .layout(DungeonLayout::new(
+            vector![Room {
+                shape: CAVE_AREA,
+                position: IVec2::ZERO,
+                outline: RoomOutline::Mask(mask),
+                role: None,
+            }],
+            Vector::new(),
+        ))
+        .build())
+}
+
+/// Generator step that puts a door in a narrow passage of a cave, choosing only passages that
+/// cut a sizeable part of the cave off from the player. Two tile wide passages are narrowed to
+/// the door by walling up the tile beside it. Follow it with `ensure_solvable` so a key left
+/// behind the door is moved back in front of it.
+pub fn add_cave_door(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let player = state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .map(|spawn| spawn.position)
+        .ok_or(StepError::NoRoom)?;
+
+    let tile_map = get_tile_map(&state.layout);
+    let occupied: Vec<IVec2> = state.spawns.iter().map(|spawn| spawn.position).collect();
+    let reachable = flood_fill(&tile_map, player, &HashSet::default()).len();
+
+    let mut gaps: Vec<(IVec2, Option<IVec2>)> = narrow_passages(&tile_map, &state.layout)
+        .into_iter()
+        .filter(|(door, walled)| {
+            !occupied.contains(door) && walled.is_none_or(|tile| !occupied.contains(&tile))
+        })
+        .filter(|(door, walled)| {
+            let blocked: HashSet<IVec2> = [Some(*door), *walled].into_iter().flatten().collect();
+            let in_front = flood_fill(&tile_map, player, &blocked).len();
+
+            in_front + blocked.len() + MIN_LOCKED_FLOOR <= reachable
+        })
+        .collect();
+
+    if gaps.is_empty() {
+        return Err(StepError::NoCorridor);
+    }
+
+    gaps.sort_unstable_by_key(|(door, walled)| {
+        (door.x, door.y, walled.map(|tile| (tile.x, tile.y)))
+    });
+    let (door, walled) = gaps[state.rng.lock().unwrap().gen_range(0..gaps.len())];
+
+    let mut rooms = state.layout.rooms.clone();
+    if let Some(tile) = walled {
+        for room in rooms.iter_mut() {
+            let inside = room.to_collision_box().contains(tile);
+            let offset = tile - room.position;
+
+            if let (true, RoomOutline::Mask(mask)) = (inside, &mut room.outline) {
+                mask[(offset.y * room.shape.width as i32 + offset.x) as usize] = false;
+            }
+        }
+    }
+
+    let mut spawns = state.spawns.clone();
+    spawns.push_back(Spawn {
+        position: door,
+        spawn_type: SpawnType::Door(KeyColour::Gold),
+    });
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .rooms(rooms)
+        .spawns(spawns)
+        .build())
+}
+
+/// A cave floor mask for `shape`, one entry per tile, row by row from the bottom. The outer
+/// ring is always left for walls.
+pub fn carve(shape: Rectangle, rng: &mut DungeonRng) -> Vec<bool> {
+    let (width, height) = (shape.width as i32, shape.height as i32);
+    let inside = |offset: IVec2| {
+        offset.x > 0 && offset.y > 0 && offset.x < width - 1 && offset.y < height - 1
+    };
+
+    let mut mask: Vec<bool> = grid(width, height)
+        .map(|offset| inside(offset) && rng.gen_bool(CAVE_FILL))
+        .collect();
+
+    for _ in 0..SMOOTHING_ROUNDS {
+        mask = smooth(&mask, width, height, inside);
+    }
+
+    let mut trimmed = vec![false; mask.len()];
+    for cell in largest_area(&mask, width, height) {
+        trimmed[cell] = true;
+    }
+
+    trimmed
+}
+
+/// Every tile of a `width` by `height` grid, row by row from the bottom.
+pub(crate) fn grid(width: i32, height: i32) -> impl Iterator<Item = IVec2> {
+    (0..height).flat_map(move |y| (0..width).map(move |x| IVec2::new(x, y)))
+}
+
+/// One round of cellular automaton: a tile becomes floor with five or more floor neighbours,
+/// stays floor with four, and turns to wall otherwise. Tiles outside `allowed` are always wall.
+pub(crate) fn smooth(
+    mask: &[bool],
+    width: i32,
+    height: i32,
+    allowed: impl Fn(IVec2) -> bool,
+) -> Vec<bool> {
+    let index = |tile: IVec2| (tile.y * width + tile.x) as usize;
+
+    grid(width, height)
+        .map(|tile| {
+            let floor_neighbours = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| tile + IVec2::new(dx, dy)))
+                .filter(|next| *next != tile)
+                .filter(|next| next.x >= 0 && next.y >= 0 && next.x < width && next.y < height)
+                .filter(|next| mask[index(*next)])
+                .count();
+
+            allowed(tile) && (floor_neighbours >= 5 || (mask[index(tile)] && floor_neighbours >= 4))
+        })
+        .collect()
+}
+
+/// The biggest group of floor cells in `mask` joined up, down, left or right.
+pub(crate) fn largest_area(mask: &[bool], width: i32, height: i32) -> Vec<usize> {
+    let mut seen = vec![false; mask.len()];
+    let mut largest = Vec::new();
+
+    for start in 0..mask.len() {
+        if !mask[start] || seen[start] {
+            continue;
+        }
+
+        let mut area = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        seen[start] = true;
+
+        while let Some(cell) = queue.pop_front() {
+            area.push(cell);
+            let (x, y) = (cell as i32 % width, cell as i32 / width);
+
+            for (nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                    continue;
+                }
+
+                let next = (ny * width + nx) as usize;
+                if mask[next] && !seen[next] {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        if area.len() > largest.len() {
+            largest = area;
+        }
+    }
+
+    largest
+}
+
+/// Places a door could close off a passage: floor tiles with floor ahead and behind and wall on
+/// both sides, or with a second floor tile beside them in a passage two tiles wide, which is
+/// returned to be walled up.
+fn narrow_passages(tile_map: &TileMap, layout: &DungeonLayout) -> Vec<(IVec2, Option<IVec2>)> {
+    let is_floor = |tile: IVec2| tile_map.get(tile) == TileType::Floor;
+    let open = |tile: IVec2, along: IVec2| is_floor(tile + along) && is_floor(tile - along);
+
+    layout
+        .rooms
+        .iter()
+        .flat_map(|room| room.floor_tiles())
+        .flat_map(|tile| {
+            [IVec2::X, IVec2::Y].into_iter().filter_map(move |along| {
+                let across = along.perp();
+
+                if !open(tile, along) || is_floor(tile - across) {
+                    None
+                } else if !is_floor(tile + across) {
+                    Some((tile, None))
+                } else if open(tile + across, along) && !is_floor(tile + across * 2) {
+                    Some((tile, Some(tile + across)))
+                } else {
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod cave_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::DungeonGenerator;
+    use crate::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+    use crate::dungeon_generation::solvability::{doors_of, is_solvable, keys_of};
+    use crate::dungeon_generation::spawn_generation::place_player_spawn;
+    use rand::SeedableRng;
+
+    /// Turns a floor mask for `shape` into floor tiles, walled in wherever they border anything
+    /// else.
+    fn cave_tile_map(shape: Rectangle, mask: &[bool]) -> TileMap {
+        get_tile_map(&DungeonLayout::new(
+            vector![Room {
+                shape,
+                position: IVec2::ZERO,
+                outline: RoomOutline::Mask(mask.to_vec()),
+                role: None,
+            }],
+            Vector::new(),
+        ))
+    }
+
+    #[test]
+    fn cave_is_one_walled_in_area() {
+        let mask = carve(CAVE_AREA, &mut DungeonRng::seed_from_u64(0));
+        let tile_map = cave_tile_map(CAVE_AREA, &mask);
+
+        let floor: Vec<IVec2> = grid(CAVE_AREA.width as i32, CAVE_AREA.height as i32)
+            .filter(|tile| tile_map.get(*tile) == TileType::Floor)
+            .collect();
+
+        assert!(!floor.is_empty());
+        assert_eq!(
+            flood_fill(&tile_map, floor[0], &HashSet::default()).len(),
+            floor.len()
+        );
+        for tile in &floor {
+            for offset in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                assert_ne!(tile_map.get(*tile + offset), TileType::Void);
+            }
+        }
+    }
+
+    #[test]
+    fn smoothing_fills_holes_and_clears_specks() {
+        let mut mask = vec![true; 25];
+        mask[12] = false;
+        let filled = smooth(&mask, 5, 5, |_| true);
+        assert!(filled[12]);
+
+        let mut speck = vec![false; 25];
+        speck[12] = true;
+        let cleared = smooth(&speck, 5, 5, |_| true);
+        assert!(!cleared[12]);
+    }
+
+    #[test]
+    fn cave_recipe_spawns_on_cave_floor() {
+        let recipe =
+            GenerationRecipe::parse(include_str!("../../assets/recipes/cave.recipe.ron")).unwrap();
+
+        for seed in 0..5 {
+            let dungeon = recipe
+                .to_generator(&StepRegistry::default())
+                .unwrap()
+                .seed(seed)
+                .generate()
+                .unwrap();
+            let tile_map = get_tile_map(&dungeon.layout);
+
+            assert!(is_solvable(&dungeon), "seed {seed}");
+            for spawn in &dungeon.spawns {
+                assert_eq!(tile_map.get(spawn.position), TileType::Floor, "seed {seed}");
+            }
+            assert_eq!(keys_of(&dungeon).len(), 1);
+        }
+    }
+
+    #[test]
+    fn door_cuts_part_of_the_cave_off() {
+        let mut doors = 0;
+
+        for seed in 0..10 {
+            let dungeon = match DungeonGenerator::with_seed(seed)
+                .add_named_step("add_cave", add_cave, 100)
+                .add_step(place_player_spawn)
+                .add_step(add_cave_door)
+                .generate()
+            {
+                Ok(dungeon) => dungeon,
+                Err(_) => continue,
+            };
+            doors += 1;
+
+            let tile_map = get_tile_map(&dungeon.layout);
+            let player = dungeon.spawns[0].position;
+            let door = doors_of(&dungeon)[0].0;
+
+            let open = flood_fill(&tile_map, player, &HashSet::default());
+            let locked = flood_fill(&tile_map, player, &HashSet::from_iter([door]));
+            assert!(locked.len() + MIN_LOCKED_FLOOR < open.len(), "seed {seed}");
+        }
+
+        assert!(doors > 0);
+    }
+
+    #[test]
+    fn needs_an_empty_layout() {
+        let generator = DungeonGenerator::with_seed(0)
+            .add_named_step("add_cave", add_cave, 100)
+            .add_step(add_cave);
+
+        assert_eq!(
+            generator.generate().unwrap_err().reason,
+            StepError::Collision
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::dungeon_generation::dungeon_state::DungeonState;
+use crate::dungeon_generation::solvability::{doors_of, flood_fill, keys_of, walking_distances};
+use crate::dungeon_generation::spawn_generation::SpawnType;
+use crate::dungeon_generation::stairs::Stairs;
+use crate::dungeon_generation::tile_map::get_tile_map;
+use bevy::math::IVec2;
+use bevy::utils::HashSet;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+
+/// Something a finished floor must have to be kept. The generator throws away floors that fail
+/// any of its constraints and generates another, so recipes can rule out floors that are too
+/// small, too sprawling or too easy.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum Constraint {
+    /// At least this many rooms.
+    MinRooms(usize),
+    /// The nearest key is at least this many steps' walk from the player spawn.
+    MinKeyDistance(usize),
+    /// The stairs down cannot be reached from the player spawn without opening a door.
+    DoorOnCriticalPath,
+    /// Every room and corridor fits in a box this wide and high.
+    MaxSize(u32, u32),
+}
+
+impl Constraint {
+    pub fn holds(&self, state: &DungeonState) -> bool {
+        match self {
+            Constraint::MinRooms(rooms) => state.layout.rooms.len() >= *rooms,
+            Constraint::MinKeyDistance(distance) => {
+                key_distance(state).is_some_and(|nearest| nearest >= *distance)
+            }
+            Constraint::DoorOnCriticalPath => door_on_critical_path(state),
+            Constraint::MaxSize(width, height) => {
+                let size = state.layout.size();
+                size.x <= *width as i32 && size.y <= *height as i32
+            }
+        }
+    }
+}
+
+/// The constraints in `constraints` that `state` does not meet, in order.
+pub fn failed_constraints<'a>(
+    constraints: &'a [Constraint],
+    state: &DungeonState,
+) -> Vec<&'a Constraint> {
+    constraints
+        .iter()
+        .filter(|constraint| !constraint.holds(state))
+        .collect()
+}
+
+pub(crate) fn player_of(state: &DungeonState) -> Option<IVec2> {
+    state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .map(|spawn| spawn.position)
+}
+
+/// How far the player has to walk to the nearest key they can reach, doors aside.
+fn key_distance(state: &DungeonState) -> Option<usize> {
+    let player = player_of(state)?;
+    let distances = walking_distances(&get_tile_map(&state.layout), player);
+
+    keys_of(state)
+        .iter()
+        .filter_map(|(key, _)| distances.get(key).copied())
+        .min()
+}
+
+fn door_on_critical_path(state: &DungeonState) -> bool {
+    let (player, stairs) = match (player_of(state), stairs_down_of(state)) {
+        (Some(player), Some(stairs)) => (player, stairs),
+        _ => return false,
+    };
+
+    let doors: HashSet<IVec2> = doors_of(state).into_iter().map(|(door, _)| door).collect();
+    !flood_fill(&get_tile_map(&state.layout), player, &doors).contains(&stairs)
+}
+
+pub(crate) fn stairs_down_of(state: &DungeonState) -> Option<IVec2> {
+    state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Stairs(Stairs::Down))
+        .map(|spawn| spawn.position)
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constraint::MinRooms(rooms) => write!(f, "at least {rooms} rooms"),
+            Constraint::MinKeyDistance(distance) => {
+                write!(f, "a key at least {distance} tiles from the player")
+            }
+            Constraint::DoorOnCriticalPath => {
+                write!(f, "a door between the player and the stairs down")
+            }
+            Constraint::MaxSize(width, height) => write!(f, "no bigger than {width}x{height}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod constraints_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::fallback_dungeon;
+    use crate::dungeon_generation::dungeon_state::DungeonStateBuilder;
+    use crate::dungeon_generation::fixtures::row_of_rooms;
+    use crate::dungeon_generation::key::KeyColour;
+    use crate::dungeon_generation::spawn_generation::Spawn;
+
+    fn spawn(x: i32, y: i32, spawn_type: SpawnType) -> Spawn {
+        Spawn {
+            position: IVec2::new(x, y),
+            spawn_type,
+        }
+    }
+
+    /// Two 5 by 5 rooms joined by a corridor along y = 2, with the player in the left room and
+    /// the stairs down in the right one.
+    fn two_rooms(mut spawns: Vec<Spawn>) -> DungeonState {
+        spawns.extend([
+            spawn(2, 2, SpawnType::Player),
+            spawn(12, 2, SpawnType::Stairs(Stairs::Down)),
+        ]);
+
+        DungeonStateBuilder::default()
+            .layout(row_of_rooms(2))
+            .spawns(spawns)
+            .build()
+    }
+
+    #[test]
+    fn counts_rooms() {
+        let state = two_rooms(Vec::new());
+
+        assert!(Constraint::MinRooms(2).holds(&state));
+        assert!(!Constraint::MinRooms(3).holds(&state));
+    }
+
+    #[test]
+    fn measures_the_walk_to_the_nearest_key() {
+        let state = two_rooms(vec![
+            spawn(12, 3, SpawnType::Key(KeyColour::Red)),
+            spawn(3, 1, SpawnType::Key(KeyColour::Blue)),
+        ]);
+
+        assert_eq!(key_distance(&state), Some(2));
+        assert!(Constraint::MinKeyDistance(2).holds(&state));
+        assert!(!Constraint::MinKeyDistance(3).holds(&state));
+    }
+
+    #[test]
+    fn floors_without_keys_fail_key_distance() {
+        assert!(!Constraint::MinKeyDistance(0).holds(&two_rooms(Vec::new())));
+    }
+
+    #[test]
+    fn finds_doors_on_the_way_to_the_stairs() {
+        let open = two_rooms(Vec::new());
+        let locked = two_rooms(vec![spawn(7, 2, SpawnType::Door(KeyColour::Red))]);
+
+        assert!(!Constraint::DoorOnCriticalPath.holds(&open));
+        assert!(Constraint::DoorOnCriticalPath.holds(&locked));
+        assert!(!Constraint::DoorOnCriticalPath.holds(&fallback_dungeon(0)));
+    }
+
+    #[test]
+    fn limits_the_floor_size() {
+        let state = two_rooms(Vec::new());
+
+        assert!(Constraint::MaxSize(15, 5).holds(&state));
+        assert!(!Constraint::MaxSize(14, 5).holds(&state));
+        assert!(!Constraint::MaxSize(15, 4).holds(&state));
+    }
+
+    #[test]
+    fn lists_what_failed() {
+        let constraints = [
+            Constraint::MinRooms(1),
+            Constraint::MinRooms(5),
+            Constraint::MaxSize(1, 1),
+        ];
+
+        assert_eq!(
+            failed_constraints(&constraints, &two_rooms(Vec::new())),
+            vec![&Constraint::MinRooms(5), &Constraint::MaxSize(1, 1)]
+        );
+    }
+
+    #[test]
+    fn parses_from_ron() {
+        let constraints: Vec<Constraint> = ron::from_str(
+            "[MinRooms(8), MinKeyDistance(30), DoorOnCriticalPath, MaxSize(120, 90)]",
+        )
+        .unwrap();
+
+        assert_eq!(constraints[3], Constraint::MaxSize(120, 90));
+        assert_eq!(
+            constraints[2].to_string(),
+            "a door between the player and the stairs down"
+        );
+    }
+}
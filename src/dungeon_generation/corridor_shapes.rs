@@ -0,0 +1,293 @@
+use crate::dungeon_generation::dungeon_generator::{add_room, wall_exit};
+use crate::dungeon_generation::dungeon_state::{DungeonLayout, DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::room::Orientation::{DOWN, LEFT, RIGHT, UP};
+use crate::dungeon_generation::room::{Collision, Corridor, IShape, Orientation};
+use crate::dungeon_generation::spatial_index::LayoutPart;
+use bevy::math::IVec2;
+use im::Vector;
+use rand::Rng;
+
+/// Adds an L or Z shaped corridor leaving the wall of a random room. Bent corridors are stored
+/// as straight segments, each starting on the last tile of the one before it.
+pub fn add_bent_corridor(state: &DungeonState) -> Result<DungeonState, StepError> {
+    if state.layout.rooms.is_empty() {
+        return Err(StepError::NoRoom);
+    }
+
+    let mut rng = state.rng.lock().unwrap();
+
+    let orientation = [UP, DOWN, LEFT, RIGHT][rng.gen_range(0..4)];
+    let room = &state.layout.rooms[rng.gen_range(0..state.layout.rooms.len())];
+
+    let exit = wall_exit(room, orientation, &mut rng).ok_or(StepError::OutOfBounds)?;
+    let turn = orientation.perpendicular()[rng.gen_range(0..2)];
+
+    let first = segment(exit, orientation, rng.gen_range(3..8));
+    // At least four tiles between the straight runs of a Z, so their walls do not touch.
+    let second = segment(first.end(), turn, rng.gen_range(4..9));
+
+    let mut segments = vec![first, second];
+    if rng.gen_bool(0.5) {
+        let third = segment(segments[1].end(), orientation, rng.gen_range(3..8));
+        segments.push(third);
+    }
+
+    if !fits(&state.layout, &segments, &[]) {
+        return Err(StepError::Collision);
+    }
+
+    let mut layout = state.layout.clone();
+    for segment in segments {
+        layout.push_corridor(segment);
+    }
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .layout(layout)
+        .build())
+}
+
+/// Adds a corridor branching sideways off the middle of an existing one, forming a T-junction.
+/// Half the time it instead opens the free side of an existing T-junction into a crossroads.
+/// The corridor branched from is split at the junction, so segments only ever meet at their ends.
+pub fn add_branching_corridor(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let mut rng = state.rng.lock().unwrap();
+    let mut layout = state.layout.clone();
+
+    let open_junctions = open_junctions(&layout.corridors);
+
+    let (junction, orientation) = if !open_junctions.is_empty() && rng.gen_bool(0.5) {
+        open_junctions[rng.gen_range(0..open_junctions.len())]
+    } else {
+        let long_enough: Vec<usize> = (0..layout.corridors.len())
+            .filter(|&index| layout.corridors[index].shape.length >= 5)
+            .collect();
+
+        if long_enough.is_empty() {
+            return Err(StepError::NoCorridor);
+        }
+
+        let index = long_enough[rng.gen_range(0..long_enough.len())];
+        let parent = layout.corridors[index].clone();
+        let along = rng.gen_range(2..parent.shape.length - 2);
+
+        let before = segment(parent.position, parent.shape.orientation, along + 1);
+        let after = segment(
+            before.end(),
+            parent.shape.orientation,
+            parent.shape.length - along,
+        );
+        let junction = before.end();
+
+        layout.set_corridor(index, before);
+        layout.push_corridor(after);
+
+        let sides = parent.shape.orientation.perpendicular();
+        (junction, sides[rng.gen_range(0..2)])
+    };
+
+    let branch = segment(
+        junction,
+        orientation,
+        rng.gen_range(state.params.corridor_length()),
+    );
+
+    let meeting: Vec<usize> = layout
+        .parts_at(junction)
+        .into_iter()
+        .filter_map(|part| match part {
+            LayoutPart::Corridor(index) => Some(index),
+            LayoutPart::Room(_) => None,
+        })
+        .collect();
+
+    if !fits(&layout, std::slice::from_ref(&branch), &meeting) {
+        return Err(StepError::Collision);
+    }
+
+    layout.push_corridor(branch);
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .layout(layout)
+        .build())
+}
+
+pub fn add_bent_corridor_then_room(state: &DungeonState) -> Result<DungeonState, StepError> {
+    add_bent_corridor(state).and_then(|res| add_room(&res))
+}
+
+pub fn add_branch_then_room(state: &DungeonState) -> Result<DungeonState, StepError> {
+    add_branching_corridor(state).and_then(|res| add_room(&res))
+}
+
+pub(crate) fn segment(position: IVec2, orientation: Orientation, length: u32) -> Corridor {
+    Corridor {
+        shape: IShape {
+            orientation,
+            length,
+        },
+        position,
+    }
+}
+
+/// Whether `segments`, joined end to end, fit in the layout without touching any room or any
+/// corridor other than those in `ignore`. Neighbouring segments share a corner, so only
+/// segments further apart are checked against each other.
+pub(crate) fn fits(layout: &DungeonLayout, segments: &[Corridor], ignore: &[usize]) -> bool {
+    segments.iter().enumerate().all(|(i, segment)| {
+        layout.colliding(segment).iter().all(|part| match part {
+            LayoutPart::Corridor(index) => ignore.contains(index),
+            LayoutPart::Room(_) => false,
+        }) && segments
+            .iter()
+            .skip(i + 2)
+            .all(|other| !other.collides_with(segment))
+    })
+}
+
+/// T-junctions whose fourth side is still free, with the orientation a branch there would take.
+fn open_junctions(corridors: &Vector<Corridor>) -> Vec<(IVec2, Orientation)> {
+    let mut junctions: Vec<(IVec2, Orientation)> = corridors
+        .iter()
+        .enumerate()
+        .filter(|&(index, branch)| {
+            let through: Vec<Orientation> = corridors
+                .iter()
+                .enumerate()
+                .filter(|&(other_index, other)| {
+                    other_index != index
+                        && (other.position == branch.position || other.end() == branch.position)
+                })
+                .map(|(_, other)| other.shape.orientation)
+                .collect();
+
+            through.len() == 2
+                && through
+                    .iter()
+                    .all(|side| branch.shape.orientation.perpendicular().contains(side))
+        })
+        .map(|(_, branch)| (branch.position, branch.shape.orientation.opposite()))
+        .collect();
+
+    junctions.sort_unstable_by_key(|(tile, _)| (tile.x, tile.y));
+    junctions
+}
+
+#[cfg(test)]
+mod corridor_shape_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::DungeonGenerator;
+    use crate::dungeon_generation::fixtures::room;
+    use crate::dungeon_generation::tile_map::{get_tile_map, TileType};
+    use im::vector;
+
+    /// Two rooms joined by a long corridor running right.
+    fn two_rooms() -> DungeonLayout {
+        DungeonLayout::new(
+            vector![room(0, 0), room(14, 0),],
+            vector![segment(IVec2::new(4, 2), RIGHT, 11)],
+        )
+    }
+
+    fn with_layout(layout: DungeonLayout) -> DungeonState {
+        DungeonStateBuilder::default().layout(layout).build()
+    }
+
+    #[test]
+    fn bent_corridor_segments_join_end_to_end() {
+        let dungeon = DungeonGenerator::with_seed(3)
+            .add_step(add_room)
+            .add_retryable_step(add_bent_corridor)
+            .generate()
+            .unwrap();
+
+        let segments: Vec<Corridor> = dungeon.layout.corridors.iter().cloned().collect();
+        assert!(segments.len() == 2 || segments.len() == 3);
+        for pair in segments.windows(2) {
+            assert_eq!(pair[1].position, pair[0].end());
+            assert!(pair[0]
+                .shape
+                .orientation
+                .perpendicular()
+                .contains(&pair[1].shape.orientation));
+        }
+    }
+
+    #[test]
+    fn branch_splits_the_corridor_it_leaves() {
+        let branched = add_branching_corridor(&with_layout(two_rooms())).unwrap();
+        let corridors = &branched.layout.corridors;
+
+        assert_eq!(corridors.len(), 3);
+        assert_eq!(corridors[0].position, IVec2::new(4, 2));
+        assert_eq!(corridors[1].position, corridors[0].end());
+        assert_eq!(corridors[1].end(), IVec2::new(14, 2));
+        assert_eq!(corridors[2].position, corridors[0].end());
+    }
+
+    #[test]
+    fn second_branch_can_make_a_crossroads() {
+        let mut layout = two_rooms();
+        layout.corridors = vector![
+            segment(IVec2::new(4, 2), RIGHT, 5),
+            segment(IVec2::new(8, 2), RIGHT, 7),
+            segment(IVec2::new(8, 2), UP, 5),
+        ];
+
+        assert_eq!(
+            open_junctions(&layout.corridors),
+            vec![(IVec2::new(8, 2), DOWN)]
+        );
+    }
+
+    #[test]
+    fn junction_is_floor_surrounded_by_walls() {
+        let mut layout = two_rooms();
+        layout.corridors = vector![
+            segment(IVec2::new(4, 2), RIGHT, 5),
+            segment(IVec2::new(8, 2), RIGHT, 7),
+            segment(IVec2::new(8, 2), UP, 5),
+        ];
+
+        let tile_map = get_tile_map(&layout);
+
+        assert_eq!(tile_map.get(IVec2::new(8, 2)), TileType::Floor);
+        assert_eq!(tile_map.get(IVec2::new(8, 3)), TileType::Floor);
+        assert_eq!(tile_map.get(IVec2::new(9, 3)), TileType::Wall);
+        assert_eq!(tile_map.get(IVec2::new(7, 3)), TileType::Wall);
+        assert_eq!(tile_map.get(IVec2::new(8, 1)), TileType::Wall);
+        assert_eq!(tile_map.get(IVec2::new(4, 2)), TileType::Floor);
+    }
+
+    #[test]
+    fn branch_connects_rooms_through_the_junction() {
+        let mut layout = two_rooms();
+        layout.corridors = vector![
+            segment(IVec2::new(4, 2), RIGHT, 5),
+            segment(IVec2::new(8, 2), RIGHT, 7),
+            segment(IVec2::new(8, 2), UP, 5),
+        ];
+        layout.rooms.push_back(room(6, 6));
+
+        let graph = layout.graph();
+
+        assert_eq!(graph.neighbours(0), vec![1, 2]);
+        assert_eq!(graph.distances_from(0), vec![Some(0), Some(1), Some(1)]);
+        assert_eq!(graph.bridges(), vec![0, 1, 2]);
+        assert_eq!(
+            graph.distances_avoiding(0, &[2]),
+            vec![Some(0), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn branching_needs_a_long_corridor() {
+        let mut layout = two_rooms();
+        layout.corridors.clear();
+
+        assert_eq!(
+            add_branching_corridor(&with_layout(layout)).unwrap_err(),
+            StepError::NoCorridor
+        );
+    }
+}
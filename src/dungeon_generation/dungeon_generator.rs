@@ -1,119 +1,591 @@
-use crate::dungeon_generation::dungeon_state::{DungeonLayout, DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::constraints::{failed_constraints, Constraint};
+use crate::dungeon_generation::dungeon_state::{
+    DungeonLayout, DungeonRng, DungeonState, DungeonStateBuilder,
+};
+use crate::dungeon_generation::generation_error::{GenerationError, StepError};
+use crate::dungeon_generation::params::GenerationParams;
+use crate::dungeon_generation::retry_policy::RetryPolicy;
 use crate::dungeon_generation::room::Orientation::{DOWN, LEFT, RIGHT, UP};
-use crate::dungeon_generation::room::{Collision, Corridor, IShape, Rectangle, Room};
+use crate::dungeon_generation::room::{Corridor, IShape, Orientation, Rectangle, Room};
+use crate::dungeon_generation::room_outline::RoomOutline;
+use crate::dungeon_generation::scoring::Score;
+use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+use crate::dungeon_generation::stairs::Stairs;
 use bevy::prelude::IVec2;
-use rand::Rng;
-use std::cell::RefCell;
-use std::rc::Rc;
+use bevy::tasks::TaskPool;
+use im::Vector;
+use rand::{Rng, SeedableRng};
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-#[derive(Default)]
 pub struct DungeonGenerator {
-    steps: Vec<BoxedStep<DungeonState>>,
+    steps: Vec<GeneratorStep<DungeonState>>,
+    seed: Option<u64>,
+    params: GenerationParams,
+    constraints: Vec<Constraint>,
+    max_candidates: u32,
+    best_of: u32,
+    score: Score,
+}
+
+type BoxedStep<T> = Box<dyn Fn(&T) -> Result<T, StepError> + Send + Sync>;
+
+/// Whether a step that builds a piece at a time has built all it should, e.g. `has_room_count`.
+pub type StepDone = fn(&DungeonState) -> bool;
+
+pub const MAX_RETRIES: u32 = 1000;
+
+/// How many floors to generate looking for one that meets the constraints, unless set.
+pub const MAX_CANDIDATES: u32 = 20;
+
+struct GeneratorStep<T> {
+    name: String,
+    run: BoxedStep<T>,
+    policy: RetryPolicy,
+    optional: bool,
+    /// Run the step again on what it built until this says it is done, set with `repeat_until`.
+    until: Option<fn(&T) -> bool>,
+}
+
+/// What happened when a single step ran: how many attempts it took and why the failed ones were
+/// rejected.
+#[derive(Clone, Debug, Default)]
+pub struct StepReport {
+    pub name: String,
+    pub attempts: u32,
+    /// How many attempts the step was allowed.
+    pub max_attempts: u32,
+    /// Each reason attempts were rejected for, in the order first seen, with how many were.
+    pub rejections: Vec<(StepError, u32)>,
+    /// An optional step gave up and the state was passed on unchanged.
+    pub skipped: bool,
+    /// A required step gave up and generation went back to replay earlier steps.
+    pub backtracked: bool,
+    pub time: Duration,
+}
+
+impl StepReport {
+    /// Each rejection reason with its count, e.g. `"collides with the existing layout x3"`.
+    pub fn rejection_summary(&self) -> Vec<String> {
+        self.rejections
+            .iter()
+            .map(|(reason, count)| format!("{reason} x{count}"))
+            .collect()
+    }
+
+    fn reject(&mut self, reason: &StepError) {
+        match self.rejections.iter_mut().find(|(r, _)| r == reason) {
+            Some((_, count)) => *count += 1,
+            None => self.rejections.push((reason.clone(), 1)),
+        }
+    }
+}
+
+/// Generation part way through the steps, run one step at a time with
+/// `DungeonGenerator::advance`.
+pub struct GenerationRun {
+    /// The state each step so far started from, ending with the state the next step starts from,
+    /// so a step that backtracks can go back to an earlier one.
+    states: Vec<DungeonState>,
+    backtracks: Vec<u32>,
 }
 
-type Step<T> = fn(&T) -> Result<T, String>;
-type BoxedStep<T> = Box<dyn Fn(&T) -> Result<T, String>>;
+impl GenerationRun {
+    /// A run of `step_count` steps starting from `state`.
+    pub fn new(state: DungeonState, step_count: usize) -> GenerationRun {
+        GenerationRun {
+            states: vec![state],
+            backtracks: vec![0; step_count],
+        }
+    }
+
+    /// The state the next step starts from, or the finished floor.
+    pub fn state(&self) -> &DungeonState {
+        &self.states[self.states.len() - 1]
+    }
+
+    pub fn next_step(&self) -> usize {
+        self.states.len() - 1
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_step() >= self.backtracks.len()
+    }
+
+    pub fn into_state(mut self) -> DungeonState {
+        self.states.pop().expect("a run always has a state")
+    }
+}
+
+impl Default for DungeonGenerator {
+    fn default() -> Self {
+        DungeonGenerator::new()
+    }
+}
 
 impl DungeonGenerator {
-    #[allow(dead_code)]
     pub fn new() -> DungeonGenerator {
-        DungeonGenerator { steps: Vec::new() }
+        DungeonGenerator {
+            steps: Vec::new(),
+            seed: None,
+            params: GenerationParams::default(),
+            constraints: Vec::new(),
+            max_candidates: MAX_CANDIDATES,
+            best_of: 1,
+            score: Score::default(),
+        }
     }
 
-    pub fn generate(&self) -> Result<DungeonState, String> {
-        let layout = DungeonLayout::new();
+    /// Creates a generator that always produces the same dungeon for the same `seed` and steps.
+    pub fn with_seed(seed: u64) -> DungeonGenerator {
+        DungeonGenerator::new().seed(seed)
+    }
 
-        let state = DungeonState {
-            layout,
-            spawns: Vec::new(),
-            rng: Rc::new(RefCell::new(rand::thread_rng())),
-        };
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
 
-        let new_state: Result<DungeonState, String> = self
-            .steps
-            .iter()
-            .try_fold(state, |result, step| step(&result));
+    /// The numbers the steps generate from, replacing the defaults.
+    pub fn params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Generates the floor `depth` floors below the first, for steps that scale with depth.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.params.depth = depth;
+        self
+    }
+
+    /// Only keeps floors that meet `constraint`, generating others until one does.
+    pub fn constraint(mut self, constraint: Constraint) -> Self {
+        self.constraints.push(constraint);
+        self
+    }
 
-        return new_state;
+    /// Gives up on the constraints after generating `max_candidates` floors that fail them.
+    pub fn max_candidates(mut self, max_candidates: u32) -> Self {
+        self.max_candidates = max_candidates.max(1);
+        self
     }
 
-    pub fn add_step(mut self, step: Step<DungeonState>) -> Self {
-        self.steps.push(Box::new(step));
+    /// Makes `generate_best_of` keep the floor `score` rates highest out of `best_of`.
+    pub fn best_of(mut self, best_of: u32, score: Score) -> Self {
+        self.best_of = best_of.max(1);
+        self.score = score;
         self
     }
 
-    pub fn add_retryable_step(mut self, step: Step<DungeonState>) -> Self {
-        let max_retries = 1000;
-        let retry_step_until_ok = move |s: &DungeonState| -> Result<DungeonState, String> {
-            let mut retry_count = 0;
-            let mut attempt: Result<DungeonState, String> = Err("First attempt".to_string());
-            while attempt.is_err() && retry_count < max_retries {
-                attempt = step(s);
-                retry_count += 1;
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    /// Runs every step in order. Without a fixed seed a random one is picked and recorded on the
+    /// resulting `DungeonState`, so any dungeon can be regenerated later.
+    pub fn generate(&self) -> Result<DungeonState, GenerationError> {
+        self.generate_with_seed(self.seed.unwrap_or_else(|| rand::thread_rng().gen()))
+    }
+
+    /// Runs every step in order starting from `seed`, whatever seed the generator was given.
+    pub fn generate_with_seed(&self, seed: u64) -> Result<DungeonState, GenerationError> {
+        self.generate_with_reports(seed, |_, _| {})
+    }
+
+    /// Like `generate_with_seed`, calling `on_step` with the index and report of every step run,
+    /// including the ones replayed after backtracking and the ones for floors the constraints
+    /// rejected.
+    ///
+    /// Floors failing the constraints are thrown away and the steps run again, drawing on from
+    /// the same RNG, so every floor differs and the floor kept is still the same for the same
+    /// seed.
+    pub fn generate_with_reports(
+        &self,
+        seed: u64,
+        mut on_step: impl FnMut(usize, &StepReport),
+    ) -> Result<DungeonState, GenerationError> {
+        let first = self.initial_state_with_seed(seed);
+        let rng = Arc::clone(&first.rng);
+        let mut candidate = first;
+        let mut failures: Vec<(Constraint, u32)> = Vec::new();
+        let mut candidates = 0;
+
+        loop {
+            candidates += 1;
+            let state = self.run_steps(candidate, &mut on_step)?;
+            let failed = failed_constraints(&self.constraints, &state);
+
+            if failed.is_empty() {
+                return Ok(state);
+            }
+
+            for constraint in failed {
+                match failures.iter_mut().find(|(c, _)| c == constraint) {
+                    Some((_, floors)) => *floors += 1,
+                    None => failures.push((constraint.clone(), 1)),
+                }
+            }
+
+            if candidates >= self.max_candidates {
+                return Err(GenerationError {
+                    step: "constraints".to_string(),
+                    step_index: self.step_count(),
+                    attempts: candidates,
+                    reason: StepError::Unacceptable,
+                    last_good_layout: Some(Box::new(state.layout)),
+                    failed_constraints: failures,
+                });
+            }
+
+            candidate = DungeonState {
+                rng: Arc::clone(&rng),
+                ..self.initial_state_with_seed(seed)
+            };
+        }
+    }
+
+    /// Generates `candidates` floors at once on `pool` and keeps the one `score` rates highest,
+    /// the earliest on a tie, failing only if every candidate fails. The functions in `scoring`
+    /// rate floors by compactness, loops and path length.
+    ///
+    /// Each candidate gets its own seed, drawn in turn from an RNG seeded with `seed`, so the same
+    /// seed keeps the same floor however the pool schedules the work, and the floor kept can be
+    /// generated again on its own from the seed recorded on it.
+    pub fn generate_best(
+        &self,
+        seed: u64,
+        candidates: u32,
+        pool: &TaskPool,
+        score: impl Fn(&DungeonState) -> f64 + Sync,
+    ) -> Result<DungeonState, GenerationError> {
+        let mut seeds = DungeonRng::seed_from_u64(seed);
+        let seeds: Vec<u64> = (0..candidates.max(1)).map(|_| seeds.gen()).collect();
+        let score = &score;
+
+        let results = pool.scope(|scope| {
+            for seed in seeds {
+                scope.spawn(async move {
+                    self.generate_with_seed(seed).map(|state| {
+                        let rating = score(&state);
+                        (state, rating)
+                    })
+                });
+            }
+        });
+
+        let mut best: Option<(DungeonState, f64)> = None;
+        let mut first_error = None;
+
+        for result in results {
+            match result {
+                Ok((state, rating)) => {
+                    if best.as_ref().is_none_or(|(_, best)| rating > *best) {
+                        best = Some((state, rating));
+                    }
+                }
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+
+        match best {
+            Some((state, _)) => Ok(state),
+            None => Err(first_error.expect("at least one candidate is generated")),
+        }
+    }
+
+    /// Generates the best of the floors set with `best_of` on `pool`, or a single floor as
+    /// `generate` does when there is only one. As with `generate_best`, the floor kept records its
+    /// own candidate's seed, which `generate_with_seed` builds again alone.
+    pub fn generate_best_of(&self, pool: &TaskPool) -> Result<DungeonState, GenerationError> {
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        if self.best_of == 1 {
+            return self.generate_with_seed(seed);
+        }
+
+        let score = self.score;
+        self.generate_best(seed, self.best_of, pool, |state| score.rate(state))
+    }
+
+    /// Runs the steps in order, going back to replay earlier steps when a step that allows it
+    /// gives up.
+    fn run_steps(
+        &self,
+        state: DungeonState,
+        mut on_step: impl FnMut(usize, &StepReport),
+    ) -> Result<DungeonState, GenerationError> {
+        let mut run = GenerationRun::new(state, self.step_count());
+
+        while !run.is_finished() {
+            let index = run.next_step();
+            let (result, report) = self.advance(&mut run);
+
+            on_step(index, &report);
+            result?;
+        }
+
+        Ok(run.into_state())
+    }
+
+    /// Runs the next step of `run`. When the step gives up and its policy allows it, `run` goes
+    /// back to replay earlier steps, which the report marks as `backtracked`; otherwise the
+    /// step's error is returned and `run` is left as it was.
+    pub fn advance(&self, run: &mut GenerationRun) -> (Result<(), GenerationError>, StepReport) {
+        let index = run.next_step();
+        let (result, mut report) = self.run_step(index, run.state());
+
+        match result {
+            Ok(next) => {
+                run.states.push(next);
+                (Ok(()), report)
+            }
+            Err(error) => {
+                let policy = &self.steps[index].policy;
+                if !policy.can_backtrack(run.backtracks[index]) {
+                    return (Err(error), report);
+                }
+
+                report.backtracked = true;
+                run.backtracks[index] += 1;
+                run.states
+                    .truncate(index.saturating_sub(policy.backtrack) + 1);
+                (Ok(()), report)
             }
-            attempt.map_err(|err| err + " and exceeded maximum retries")
+        }
+    }
+
+    /// The empty state the first step runs on.
+    pub fn initial_state(&self) -> DungeonState {
+        self.initial_state_with_seed(self.seed.unwrap_or_else(|| rand::thread_rng().gen()))
+    }
+
+    /// The empty state the first step runs on when generating from `seed`, whatever seed the
+    /// generator was given.
+    pub fn initial_state_with_seed(&self, seed: u64) -> DungeonState {
+        DungeonState {
+            layout: DungeonLayout::default(),
+            spawns: Vector::new(),
+            prefab_doors: Vector::new(),
+            seed,
+            params: self.params.clone(),
+            rng: Arc::new(Mutex::new(DungeonRng::seed_from_u64(seed))),
+        }
+    }
+
+    pub fn step_count(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn retry_policy(&self, index: usize) -> RetryPolicy {
+        self.steps[index].policy
+    }
+
+    /// Runs only the step at `index`, retrying it as its policy allows, and reports how it went.
+    /// A step set to `repeat_until` is run again on each state it builds until it is done, every
+    /// run counted as an attempt. Backtracking is left to the caller.
+    pub fn run_step(
+        &self,
+        index: usize,
+        state: &DungeonState,
+    ) -> (Result<DungeonState, GenerationError>, StepReport) {
+        let step = &self.steps[index];
+        let mut report = StepReport {
+            name: step.name.clone(),
+            max_attempts: step.policy.max_attempts,
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let mut last_rejection = None;
+        let mut built = None;
+
+        while step.policy.keeps_trying(report.attempts, started.elapsed()) {
+            report.attempts += 1;
+
+            match (step.run)(built.as_ref().unwrap_or(state)) {
+                Ok(new_state) if step.until.is_some_and(|done| !done(&new_state)) => {
+                    built = Some(new_state);
+                }
+                Ok(new_state) => {
+                    report.time = started.elapsed();
+                    return (Ok(new_state), report);
+                }
+                Err(reason) => {
+                    report.reject(&reason);
+                    last_rejection = Some(reason);
+                }
+            }
+        }
+        report.time = started.elapsed();
+
+        if step.optional {
+            report.skipped = true;
+            return (Ok(DungeonStateBuilder::from_state(state).build()), report);
+        }
+
+        let error = GenerationError {
+            step: step.name.clone(),
+            step_index: index,
+            attempts: report.attempts,
+            reason: last_rejection.expect("a step is attempted at least once"),
+            last_good_layout: if state.layout == DungeonLayout::default() {
+                None
+            } else {
+                Some(Box::new(state.layout.clone()))
+            },
+            failed_constraints: Vec::new(),
         };
-        self.steps.push(Box::new(retry_step_until_ok));
+
+        (Err(error), report)
+    }
+
+    pub fn add_step<F>(self, step: F) -> Self
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.push_step(step, 1)
+    }
+
+    pub fn add_retryable_step<F>(self, step: F) -> Self
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.push_step(step, MAX_RETRIES)
+    }
+
+    /// Adds a step under an explicit name, attempting it at most `max_attempts` times.
+    pub fn add_named_step<F>(self, name: &str, step: F, max_attempts: u32) -> Self
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.add_step_with_policy(name, step, RetryPolicy::attempts(max_attempts))
+    }
+
+    /// Like `add_named_step`, but when every attempt fails generation carries on with the state
+    /// from before the step instead of failing.
+    pub fn add_optional_named_step<F>(self, name: &str, step: F, max_attempts: u32) -> Self
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.add_optional_step_with_policy(name, step, RetryPolicy::attempts(max_attempts))
+    }
+
+    /// Adds a step under an explicit name, retried and backtracked from as `policy` says.
+    pub fn add_step_with_policy<F>(mut self, name: &str, step: F, policy: RetryPolicy) -> Self
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.steps.push(GeneratorStep {
+            name: name.to_string(),
+            run: Box::new(step),
+            policy: RetryPolicy {
+                max_attempts: policy.max_attempts.max(1),
+                ..policy
+            },
+            optional: false,
+            until: None,
+        });
+        self
+    }
+
+    /// Makes the last step added build a piece at a time: each successful attempt runs it again
+    /// on what it built, until `done` says there is enough. Its policy's attempts and time budget
+    /// cover every attempt, so it gives up like any other step.
+    pub fn repeat_until(mut self, done: StepDone) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.until = Some(done);
+        }
         self
     }
-}
 
-impl DungeonLayout {
-    fn new() -> Self {
-        DungeonLayout {
-            rooms: Vec::new(),
-            corridors: Vec::new(),
+    /// Like `add_step_with_policy`, but when the step gives up generation carries on with the
+    /// state from before the step. An optional step never backtracks.
+    pub fn add_optional_step_with_policy<F>(self, name: &str, step: F, policy: RetryPolicy) -> Self
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        let mut generator = self.add_step_with_policy(name, step, policy);
+        if let Some(step) = generator.steps.last_mut() {
+            step.optional = true;
         }
+        generator
+    }
+
+    fn push_step<F>(self, step: F, max_attempts: u32) -> Self
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.add_named_step(&step_name::<F>(), step, max_attempts)
     }
 }
 
-pub fn add_room(state: &DungeonState) -> Result<DungeonState, String> {
-    let mut rng = state.rng.borrow_mut();
+/// Names a step after its function, e.g. `add_corridor_then_room`.
+fn step_name<F>() -> String {
+    let full_name = std::any::type_name::<F>();
+    full_name
+        .rsplit("::")
+        .next()
+        .unwrap_or(full_name)
+        .to_string()
+}
 
-    let width = rng.gen_range(6..16);
-    let height = rng.gen_range(6..16);
+pub fn add_room(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let params = &state.params;
+    let mut rng = state.rng.lock().unwrap();
+
+    let width = rng.gen_range(params.room_size());
+    let height = rng.gen_range(params.room_size());
+    let shape = Rectangle { width, height };
+    let outline = RoomOutline::random(shape, &mut rng);
 
     let position = if let Some(corridor) = state.layout.corridors.last() {
-        let joining_corridor_pos = corridor.position
-            + IVec2::from(corridor.shape.orientation) * (corridor.shape.length as i32 - 1);
-
-        let (x, y) = match corridor.shape.orientation {
-            DOWN => (rng.gen_range(1..(width - 1)), height - 1),
-            UP => (rng.gen_range(1..(width - 1)), 0),
-            LEFT => (width - 1, rng.gen_range(1..(height - 1))),
-            RIGHT => (0, rng.gen_range(1..(height - 1))),
+        let joining_corridor_pos = corridor.end();
+
+        // The wall the corridor enters through faces back along it.
+        let unplaced = Room {
+            shape,
+            position: IVec2::ZERO,
+            outline: outline.clone(),
+            role: None,
         };
+        let openings = unplaced.wall_openings(corridor.shape.orientation.opposite());
 
-        joining_corridor_pos - IVec2::new(x as i32, y as i32)
+        if openings.is_empty() {
+            return Err(StepError::OutOfBounds);
+        }
+
+        joining_corridor_pos - openings[rng.gen_range(0..openings.len())]
     } else {
-        IVec2::new(rng.gen_range(20..25), rng.gen_range(20..25))
+        IVec2::new(
+            rng.gen_range(params.first_room_origin()),
+            rng.gen_range(params.first_room_origin()),
+        )
     };
 
     let room = Room {
-        shape: Rectangle { width, height },
+        shape,
         position,
+        outline,
+        role: None,
     };
 
-    let mut rooms = state.layout.rooms.clone();
-
-    let disjoint_rooms = rooms.iter().all(|r| !r.collides_with(&room));
-    let disjoint_from_corridors = state
-        .layout
-        .corridors
-        .iter()
-        .all(|c| !c.collides_with(&room));
-
-    if disjoint_rooms && disjoint_from_corridors {
-        rooms.push(room);
-
-        return Ok(DungeonStateBuilder::from_state(state).rooms(rooms).build());
+    if room_fits(&state.layout, &room) {
+        return Ok(DungeonStateBuilder::from_state(state)
+            .push_room(room)
+            .build());
     }
 
-    Err("Failed to add room".to_string())
+    Err(StepError::Collision)
 }
 
-pub fn add_corridor(state: &DungeonState) -> Result<DungeonState, String> {
-    let mut rng = state.rng.borrow_mut();
+/// Whether `room` can be added to `layout` without overlapping any room or corridor in it.
+pub(crate) fn room_fits(layout: &DungeonLayout, room: &Room) -> bool {
+    layout.colliding(room).is_empty()
+}
+
+pub fn add_corridor(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let mut rng = state.rng.lock().unwrap();
 
     let num = rng.gen_range(0..4);
 
@@ -125,21 +597,15 @@ pub fn add_corridor(state: &DungeonState) -> Result<DungeonState, String> {
         _ => UP,
     };
 
-    let index = rng.gen_range(0..state.layout.rooms.len());
-    let random_room = state.layout.rooms.get(index);
+    let random_room = if state.layout.rooms.is_empty() {
+        None
+    } else {
+        let index = rng.gen_range(0..state.layout.rooms.len());
+        state.layout.rooms.get(index)
+    };
 
     let position = if let Some(room) = random_room {
-        let width = room.shape.width - 1;
-        let height = room.shape.height - 1;
-
-        let offset = match orientation {
-            UP => IVec2::new(rng.gen_range(1..width) as i32, height as i32),
-            DOWN => IVec2::new(rng.gen_range(1..width) as i32, 0),
-            RIGHT => IVec2::new(width as i32, rng.gen_range(1..height) as i32),
-            LEFT => IVec2::new(0, rng.gen_range(1..height) as i32),
-        };
-
-        offset + room.position
+        wall_exit(room, orientation, &mut rng).ok_or(StepError::OutOfBounds)?
     } else {
         IVec2::new(0, 0)
     };
@@ -147,38 +613,92 @@ pub fn add_corridor(state: &DungeonState) -> Result<DungeonState, String> {
     let corridor = Corridor {
         shape: IShape {
             orientation,
-            length: rng.gen_range(3..12),
+            length: rng.gen_range(state.params.corridor_length()),
         },
         position,
     };
 
-    let mut corridors = state.layout.corridors.clone();
+    if state.layout.colliding(&corridor).is_empty() {
+        return Ok(DungeonStateBuilder::from_state(state)
+            .push_corridor(corridor)
+            .build());
+    }
 
-    let disjoint_from_corridors = corridors.iter().all(|c| !c.collides_with(&corridor));
-    let disjoint_from_rooms = state
-        .layout
-        .rooms
-        .iter()
-        .all(|r| !r.collides_with(&corridor));
+    Err(StepError::Collision)
+}
 
-    if disjoint_from_corridors && disjoint_from_rooms {
-        corridors.push(corridor);
+/// A random tile on the wall of `room` facing `orientation`, corners excluded, where a corridor
+/// can leave the room.
+pub(crate) fn wall_exit(
+    room: &Room,
+    orientation: Orientation,
+    rng: &mut DungeonRng,
+) -> Option<IVec2> {
+    let openings = room.wall_openings(orientation);
 
-        return Ok(DungeonStateBuilder::from_state(state)
-            .corridors(corridors)
-            .build());
+    if openings.is_empty() {
+        return None;
     }
 
-    Err("Failed to add corridor".to_string())
+    Some(openings[rng.gen_range(0..openings.len())])
 }
 
-pub fn add_corridor_then_room(state: &DungeonState) -> Result<DungeonState, String> {
+pub fn add_corridor_then_room(state: &DungeonState) -> Result<DungeonState, StepError> {
     return add_corridor(state).and_then(|res| add_room(&res));
 }
 
+/// `add_corridor_then_room`, unless the floor already has `room_count` rooms. Added with
+/// `repeat_until(has_room_count)` it grows the floor a room per attempt up to the count, so run it
+/// after the steps that add rooms of their own.
+pub fn add_rooms_to_count(state: &DungeonState) -> Result<DungeonState, StepError> {
+    if has_room_count(state) {
+        return Ok(DungeonStateBuilder::from_state(state).build());
+    }
+
+    add_corridor_then_room(state)
+}
+
+/// Whether the floor has the `room_count` rooms `add_rooms_to_count` grows it to.
+pub fn has_room_count(state: &DungeonState) -> bool {
+    state.layout.rooms.len() >= state.params.room_count()
+}
+
+/// A single room with the player in the middle and the stairs down beside them, used when
+/// generation keeps failing.
+pub fn fallback_dungeon(seed: u64) -> DungeonState {
+    let room = Room {
+        shape: Rectangle {
+            width: 12,
+            height: 12,
+        },
+        position: IVec2::new(20, 20),
+        outline: RoomOutline::Rectangle,
+        role: None,
+    };
+
+    let player = Spawn {
+        position: room.position + IVec2::new(6, 6),
+        spawn_type: SpawnType::Player,
+    };
+    let stairs = Spawn {
+        position: room.position + IVec2::new(6, 9),
+        spawn_type: SpawnType::Stairs(Stairs::Down),
+    };
+
+    DungeonStateBuilder::default()
+        .seed(seed)
+        .rooms(vec![room])
+        .spawns(vec![player, stairs])
+        .build()
+}
+
 #[cfg(test)]
 mod dungeon_builder_tests {
     use super::*;
+    use crate::dungeon_generation::door::add_door;
+    use crate::dungeon_generation::key::add_key;
+    use crate::dungeon_generation::params::Curve;
+    use crate::dungeon_generation::scoring::compactness;
 
     #[test]
     fn add_room_works() {
@@ -252,4 +772,425 @@ mod dungeon_builder_tests {
         assert_eq!(layout.rooms.len(), 1);
         assert_eq!(layout.corridors.len(), 1);
     }
+
+    #[test]
+    fn same_seed_generates_same_layout() {
+        let generator = || {
+            DungeonGenerator::with_seed(42)
+                .add_step(add_room)
+                .add_retryable_step(add_corridor_then_room)
+                .add_retryable_step(add_corridor_then_room)
+        };
+
+        let first = generator().generate().unwrap();
+        let second = generator().generate().unwrap();
+
+        assert_eq!(first.seed, 42);
+        assert_eq!(first.layout, second.layout);
+    }
+
+    #[test]
+    fn different_seeds_generate_different_layouts() {
+        let first = DungeonGenerator::with_seed(1).add_step(add_room);
+        let second = DungeonGenerator::with_seed(2).add_step(add_room);
+
+        assert_ne!(
+            first.generate().unwrap().layout,
+            second.generate().unwrap().layout
+        );
+    }
+
+    #[test]
+    fn unseeded_generator_records_its_seed() {
+        let dungeon = DungeonGenerator::new()
+            .add_step(add_room)
+            .generate()
+            .unwrap();
+
+        let replayed = DungeonGenerator::with_seed(dungeon.seed)
+            .add_step(add_room)
+            .generate()
+            .unwrap();
+
+        assert_eq!(dungeon.layout, replayed.layout);
+    }
+
+    #[test]
+    fn run_step_reports_step_name() {
+        let generator = DungeonGenerator::with_seed(3).add_step(add_room);
+        let state = generator.initial_state();
+
+        let (result, report) = generator.run_step(0, &state);
+
+        assert!(result.is_ok());
+        assert_eq!(report.name, "add_room");
+        assert_eq!(report.attempts, 1);
+        assert!(report.rejections.is_empty());
+    }
+
+    #[test]
+    fn run_step_records_rejections() {
+        let generator = DungeonGenerator::with_seed(3)
+            .add_step(add_room)
+            .add_retryable_step(add_room);
+        let state = generator.run_step(0, &generator.initial_state()).0.unwrap();
+
+        let (result, report) = generator.run_step(1, &state);
+
+        assert!(result.is_err());
+        assert_eq!(report.attempts, MAX_RETRIES);
+        assert_eq!(report.rejections, vec![(StepError::Collision, MAX_RETRIES)]);
+        assert_eq!(
+            report.rejection_summary(),
+            vec![format!("collides with the existing layout x{MAX_RETRIES}")]
+        );
+    }
+
+    #[test]
+    fn failed_generation_reports_step_attempts_and_reason() {
+        let generator = DungeonGenerator::with_seed(3)
+            .add_step(add_room)
+            .add_retryable_step(add_room);
+
+        let error = generator.generate().unwrap_err();
+
+        assert_eq!(error.step, "add_room");
+        assert_eq!(error.step_index, 1);
+        assert_eq!(error.attempts, MAX_RETRIES);
+        assert_eq!(error.reason, StepError::Collision);
+        assert_eq!(error.last_good_layout.unwrap().rooms.len(), 1);
+    }
+
+    #[test]
+    fn first_step_failure_has_no_last_good_layout() {
+        let generator = DungeonGenerator::with_seed(3).add_step(add_key);
+
+        let error = generator.generate().unwrap_err();
+
+        assert_eq!(error.reason, StepError::NoRoom);
+        assert_eq!(error.last_good_layout, None);
+    }
+
+    #[test]
+    fn add_door_without_corridor_is_an_error_not_a_panic() {
+        let generator = DungeonGenerator::with_seed(3)
+            .add_step(add_room)
+            .add_step(add_door);
+
+        assert_eq!(
+            generator.generate().unwrap_err().reason,
+            StepError::NoCorridor
+        );
+    }
+
+    /// Only succeeds on floors whose first room is at least 14 tiles wide, which `add_room` makes
+    /// a fifth of the time.
+    fn needs_a_wide_room(state: &DungeonState) -> Result<DungeonState, StepError> {
+        match state.layout.rooms.front() {
+            Some(room) if room.shape.width >= 14 => {
+                Ok(DungeonStateBuilder::from_state(state).build())
+            }
+            _ => Err(StepError::OutOfBounds),
+        }
+    }
+
+    fn wide_room_generator(policy: RetryPolicy) -> DungeonGenerator {
+        DungeonGenerator::new()
+            .add_step(add_room)
+            .add_step_with_policy("needs_a_wide_room", needs_a_wide_room, policy)
+    }
+
+    #[test]
+    fn backtracking_replays_earlier_steps() {
+        let without = wide_room_generator(RetryPolicy::attempts(10));
+        let with = wide_room_generator(RetryPolicy::attempts(10).backtrack(1, 100));
+
+        let failing: Vec<u64> = (0..20)
+            .filter(|&seed| without.generate_with_seed(seed).is_err())
+            .collect();
+        assert!(!failing.is_empty());
+
+        for seed in failing {
+            let dungeon = with.generate_with_seed(seed).unwrap();
+            assert!(dungeon.layout.rooms[0].shape.width >= 14, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn backtracking_gives_the_same_floor_for_the_same_seed() {
+        let generator = wide_room_generator(RetryPolicy::attempts(1).backtrack(1, 100));
+
+        for seed in 0..10 {
+            assert_eq!(
+                generator.generate_with_seed(seed).unwrap().layout,
+                generator.generate_with_seed(seed).unwrap().layout
+            );
+        }
+    }
+
+    #[test]
+    fn reports_every_backtrack() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .add_step_with_policy(
+                "needs_a_wide_room",
+                |_: &DungeonState| Err(StepError::OutOfBounds),
+                RetryPolicy::attempts(2).backtrack(1, 3),
+            );
+        let mut reports = Vec::new();
+
+        let error = generator
+            .generate_with_reports(0, |index, report| reports.push((index, report.backtracked)))
+            .unwrap_err();
+
+        assert_eq!(error.attempts, 2);
+        assert_eq!(
+            reports,
+            vec![
+                (0, false),
+                (1, true),
+                (0, false),
+                (1, true),
+                (0, false),
+                (1, true),
+                (0, false),
+                (1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn time_budget_cuts_retries_short() {
+        let generator = DungeonGenerator::new().add_step_with_policy(
+            "slow",
+            |_: &DungeonState| {
+                std::thread::sleep(Duration::from_millis(5));
+                Err(StepError::Collision)
+            },
+            RetryPolicy::attempts(1000).time_budget(Duration::from_millis(20)),
+        );
+
+        let (result, report) = generator.run_step(0, &generator.initial_state());
+
+        assert!(result.is_err());
+        assert!(report.attempts < 10, "{} attempts", report.attempts);
+    }
+
+    #[test]
+    fn keeps_generating_until_the_constraints_hold() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .constraint(Constraint::MaxSize(8, 8))
+            .max_candidates(1000);
+
+        for seed in 0..10 {
+            let size = generator.generate_with_seed(seed).unwrap().layout.size();
+            assert!(size.x <= 8 && size.y <= 8, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn constrained_floors_are_the_same_for_the_same_seed() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .constraint(Constraint::MaxSize(8, 8))
+            .max_candidates(1000);
+
+        let first = generator.generate_with_seed(5).unwrap();
+        let second = generator.generate_with_seed(5).unwrap();
+
+        assert_eq!(first.seed, 5);
+        assert_eq!(first.layout, second.layout);
+    }
+
+    #[test]
+    fn reports_which_constraints_failed() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .constraint(Constraint::MinRooms(1))
+            .constraint(Constraint::MinRooms(2))
+            .constraint(Constraint::MaxSize(100, 100))
+            .constraint(Constraint::DoorOnCriticalPath)
+            .max_candidates(4);
+
+        let error = generator.generate_with_seed(0).unwrap_err();
+
+        assert_eq!(error.reason, StepError::Unacceptable);
+        assert_eq!(error.step_index, 1);
+        assert_eq!(error.attempts, 4);
+        assert_eq!(
+            error.failed_constraints,
+            vec![
+                (Constraint::MinRooms(2), 4),
+                (Constraint::DoorOnCriticalPath, 4)
+            ]
+        );
+        assert_eq!(error.last_good_layout.unwrap().rooms.len(), 1);
+    }
+
+    #[test]
+    fn step_failures_are_not_retried_as_new_floors() {
+        let mut runs = 0;
+        let generator = DungeonGenerator::new()
+            .add_step(add_key)
+            .constraint(Constraint::MinRooms(1));
+
+        let error = generator
+            .generate_with_reports(0, |_, _| runs += 1)
+            .unwrap_err();
+
+        assert_eq!(error.reason, StepError::NoRoom);
+        assert_eq!(runs, 1);
+    }
+
+    fn room_width(state: &DungeonState) -> f64 {
+        state.layout.rooms[0].shape.width as f64
+    }
+
+    #[test]
+    fn keeps_the_best_candidate() {
+        let generator = DungeonGenerator::new().add_step(add_room);
+        let pool = TaskPool::new();
+
+        let best = generator.generate_best(3, 16, &pool, room_width).unwrap();
+        let fewer = generator.generate_best(3, 4, &pool, room_width).unwrap();
+
+        // The first four candidates are the same either way, so more can only do better.
+        assert!(room_width(&best) >= room_width(&fewer));
+        assert_eq!(
+            generator
+                .generate_best(3, 16, &pool, room_width)
+                .unwrap()
+                .layout,
+            best.layout
+        );
+        assert_eq!(
+            generator.generate_with_seed(best.seed).unwrap().layout,
+            best.layout
+        );
+    }
+
+    #[test]
+    fn best_of_keeps_the_seed_of_the_floor_kept() {
+        let generator = DungeonGenerator::with_seed(3)
+            .add_step(add_room)
+            .add_retryable_step(add_corridor_then_room)
+            .best_of(8, Score::Compactness);
+        let pool = TaskPool::new();
+
+        let best = generator.generate_best_of(&pool).unwrap();
+
+        assert_eq!(
+            generator.generate_with_seed(best.seed).unwrap().layout,
+            best.layout
+        );
+        assert_eq!(
+            generator.generate_best_of(&pool).unwrap().layout,
+            best.layout
+        );
+        assert_eq!(
+            generator
+                .generate_best(3, 8, &pool, compactness)
+                .unwrap()
+                .layout,
+            best.layout
+        );
+    }
+
+    #[test]
+    fn best_candidate_fails_only_when_every_candidate_does() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .add_step(add_room);
+
+        let error = generator
+            .generate_best(0, 4, &TaskPool::new(), room_width)
+            .unwrap_err();
+
+        assert_eq!(error.step_index, 1);
+    }
+
+    #[test]
+    fn states_can_move_between_threads() {
+        let state = DungeonGenerator::new()
+            .add_step(add_room)
+            .generate_with_seed(0)
+            .unwrap();
+
+        let state = std::thread::spawn(move || add_corridor_then_room(&state))
+            .join()
+            .unwrap();
+
+        assert!(state.is_ok());
+    }
+
+    #[test]
+    fn fallback_dungeon_has_a_room_player_and_stairs() {
+        let dungeon = fallback_dungeon(9);
+
+        assert_eq!(dungeon.seed, 9);
+        assert_eq!(dungeon.layout.rooms.len(), 1);
+        assert_eq!(dungeon.spawns[0].spawn_type, SpawnType::Player);
+        assert_eq!(
+            dungeon.spawns[1].spawn_type,
+            SpawnType::Stairs(Stairs::Down)
+        );
+        assert!(dungeon.layout.rooms[0].contains(dungeon.spawns[1].position));
+    }
+
+    #[test]
+    fn rooms_are_added_up_to_the_count_for_the_depth() {
+        let generator = |depth| {
+            DungeonGenerator::with_seed(4)
+                .depth(depth)
+                .add_step(add_room)
+                .add_named_step("add_rooms_to_count", add_rooms_to_count, 1000)
+                .repeat_until(has_room_count)
+        };
+
+        assert_eq!(generator(0).generate().unwrap().layout.rooms.len(), 8);
+        assert_eq!(generator(2).generate().unwrap().layout.rooms.len(), 10);
+    }
+
+    #[test]
+    fn every_room_added_to_the_count_is_an_attempt() {
+        let generator = |max_attempts| {
+            DungeonGenerator::with_seed(4)
+                .add_step(add_room)
+                .add_named_step("add_rooms_to_count", add_rooms_to_count, max_attempts)
+                .repeat_until(has_room_count)
+        };
+        let first_room = generator(1).run_step(0, &generator(1).initial_state()).0;
+        let first_room = first_room.unwrap();
+
+        let (result, report) = generator(1000).run_step(1, &first_room);
+        assert_eq!(result.unwrap().layout.rooms.len(), 8);
+        assert!(report.attempts >= 7);
+
+        let (result, report) = generator(3).run_step(1, &first_room);
+        assert!(result.is_err());
+        assert_eq!(report.attempts, 3);
+    }
+
+    #[test]
+    fn params_reach_the_steps() {
+        let params = GenerationParams {
+            min_room_size: Curve::flat(7.0),
+            max_room_size: Curve::flat(7.0),
+            first_room_origin: 0..1,
+            ..Default::default()
+        };
+
+        let room = &DungeonGenerator::with_seed(1)
+            .params(params)
+            .add_step(add_room)
+            .generate()
+            .unwrap()
+            .layout
+            .rooms[0];
+
+        assert_eq!(room.position, IVec2::ZERO);
+        assert_eq!(room.shape.width, 7);
+        assert_eq!(room.shape.height, 7);
+    }
 }
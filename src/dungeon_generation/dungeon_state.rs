@@ -1,52 +1,166 @@
+use crate::dungeon_generation::params::GenerationParams;
 use crate::dungeon_generation::room::{Corridor, Room};
+use crate::dungeon_generation::spatial_index::SpatialIndex;
 use crate::dungeon_generation::spawn_generation::Spawn;
-use rand::prelude::ThreadRng;
-use std::cell::RefCell;
-use std::rc::Rc;
+use bevy::math::IVec2;
+use im::Vector;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::borrow::Cow;
 
+use std::sync::{Arc, Mutex};
+
+pub type DungeonRng = StdRng;
+
+/// A floor part way through generation. Rooms, corridors and spawns are kept in persistent
+/// vectors, which share everything they have in common with the state they were built from, so
+/// a step building on a state copies only what it changes however big the floor gets, and the
+/// state it started from stays as it was for retries and backtracking.
+#[derive(Debug)]
 pub struct DungeonState {
     pub layout: DungeonLayout,
-    pub spawns: Vec<Spawn>,
-    pub(crate) rng: Rc<RefCell<ThreadRng>>,
+    pub spawns: Vector<Spawn>,
+    /// Where prefabs put doors of their own, which stand in the prefab's doorways rather than in
+    /// corridors.
+    pub prefab_doors: Vector<IVec2>,
+    pub seed: u64,
+    pub params: GenerationParams,
+    pub(crate) rng: Arc<Mutex<DungeonRng>>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct DungeonLayout {
-    pub rooms: Vec<Room>,
-    pub corridors: Vec<Corridor>,
+    pub rooms: Vector<Room>,
+    pub corridors: Vector<Corridor>,
+    /// Kept up to date by `push_room`, `push_corridor` and `set_corridor`. After `rooms` or
+    /// `corridors` are changed directly it is rebuilt for every lookup until `reindex` is called.
+    index: SpatialIndex,
 }
 
-#[derive(Default)]
 pub struct DungeonStateBuilder {
     layout: DungeonLayout,
-    spawns: Vec<Spawn>,
-    rng: Rc<RefCell<ThreadRng>>,
+    spawns: Vector<Spawn>,
+    prefab_doors: Vector<IVec2>,
+    seed: u64,
+    params: GenerationParams,
+    rng: Arc<Mutex<DungeonRng>>,
+}
+
+impl Default for DungeonStateBuilder {
+    fn default() -> Self {
+        DungeonStateBuilder::new(
+            DungeonLayout::default(),
+            Vector::new(),
+            0,
+            Arc::new(Mutex::new(DungeonRng::seed_from_u64(0))),
+        )
+    }
+}
+
+impl DungeonLayout {
+    pub fn new(
+        rooms: impl Into<Vector<Room>>,
+        corridors: impl Into<Vector<Corridor>>,
+    ) -> DungeonLayout {
+        let mut layout = DungeonLayout {
+            rooms: rooms.into(),
+            corridors: corridors.into(),
+            index: SpatialIndex::default(),
+        };
+        layout.reindex();
+        layout
+    }
+
+    pub fn push_room(&mut self, room: Room) {
+        self.reindex();
+        self.index.push_room(room);
+        self.rooms = self.index.rooms().clone();
+    }
+
+    pub fn push_corridor(&mut self, corridor: Corridor) {
+        self.reindex();
+        self.index.push_corridor(corridor);
+        self.corridors = self.index.corridors().clone();
+    }
+
+    /// Puts `corridor` in place of the corridor at `index`.
+    pub fn set_corridor(&mut self, index: usize, corridor: Corridor) {
+        self.reindex();
+        self.index.set_corridor(index, corridor);
+        self.corridors = self.index.corridors().clone();
+    }
+
+    /// Where the rooms and corridors are, built afresh if they were changed directly.
+    pub fn spatial_index(&self) -> Cow<'_, SpatialIndex> {
+        if self.index.indexes(&self.rooms, &self.corridors) {
+            Cow::Borrowed(&self.index)
+        } else {
+            Cow::Owned(SpatialIndex::new(&self.rooms, &self.corridors))
+        }
+    }
+
+    /// Rebuilds the spatial index if `rooms` or `corridors` were changed directly.
+    pub fn reindex(&mut self) {
+        if !self.index.indexes(&self.rooms, &self.corridors) {
+            self.index = SpatialIndex::new(&self.rooms, &self.corridors);
+        }
+    }
+
+    /// The width and height of the smallest box around every room and corridor tile.
+    pub fn size(&self) -> IVec2 {
+        let rooms = self.rooms.iter().flat_map(|room| {
+            let size = IVec2::new(room.shape.width as i32, room.shape.height as i32);
+            [room.position, room.position + size - IVec2::ONE]
+        });
+        let corridors = self
+            .corridors
+            .iter()
+            .flat_map(|corridor| [corridor.position, corridor.end()]);
+
+        let (min, max) = rooms
+            .chain(corridors)
+            .fold(None, |bounds: Option<(IVec2, IVec2)>, tile| match bounds {
+                Some((min, max)) => Some((min.min(tile), max.max(tile))),
+                None => Some((tile, tile)),
+            })
+            .unwrap_or((IVec2::ZERO, -IVec2::ONE));
+
+        max - min + IVec2::ONE
+    }
 }
 
-impl DungeonState {
-    #[allow(dead_code)]
-    fn builder() -> DungeonStateBuilder {
-        DungeonStateBuilder::default()
+impl PartialEq for DungeonLayout {
+    fn eq(&self, other: &Self) -> bool {
+        self.rooms == other.rooms && self.corridors == other.corridors
     }
 }
 
 impl DungeonStateBuilder {
     pub fn new(
         layout: DungeonLayout,
-        spawns: Vec<Spawn>,
-        rng: Rc<RefCell<ThreadRng>>,
+        spawns: impl Into<Vector<Spawn>>,
+        seed: u64,
+        rng: Arc<Mutex<DungeonRng>>,
     ) -> DungeonStateBuilder {
         DungeonStateBuilder {
             layout,
-            spawns,
+            spawns: spawns.into(),
+            prefab_doors: Vector::new(),
+            seed,
+            params: GenerationParams::default(),
             rng,
         }
     }
 
-    pub fn build(self) -> DungeonState {
+    pub fn build(mut self) -> DungeonState {
+        self.layout.reindex();
+
         DungeonState {
             layout: self.layout,
             spawns: self.spawns,
+            prefab_doors: self.prefab_doors,
+            seed: self.seed,
+            params: self.params,
             rng: self.rng,
         }
     }
@@ -55,41 +169,62 @@ impl DungeonStateBuilder {
         DungeonStateBuilder::new(
             state.layout.clone(),
             state.spawns.clone(),
-            Rc::clone(&state.rng),
+            state.seed,
+            Arc::clone(&state.rng),
         )
+        .params(state.params.clone())
+        .prefab_doors(state.prefab_doors.clone())
     }
 
-    #[allow(dead_code)]
     pub fn layout(mut self, layout: DungeonLayout) -> DungeonStateBuilder {
         self.layout = layout;
         self
     }
 
-    #[allow(dead_code)]
-    pub fn spawns(mut self, spawns: Vec<Spawn>) -> DungeonStateBuilder {
-        self.spawns = spawns;
+    pub fn spawns(mut self, spawns: impl Into<Vector<Spawn>>) -> DungeonStateBuilder {
+        self.spawns = spawns.into();
         self
     }
 
-    #[allow(dead_code)]
-    pub fn rng(mut self, rng: Rc<RefCell<ThreadRng>>) -> DungeonStateBuilder {
-        self.rng = rng;
+    pub fn push_spawn(mut self, spawn: Spawn) -> DungeonStateBuilder {
+        self.spawns.push_back(spawn);
         self
     }
 
-    pub fn rooms(mut self, rooms: Vec<Room>) -> DungeonStateBuilder {
-        self.layout = DungeonLayout {
-            rooms,
-            corridors: self.layout.corridors,
-        };
+    pub fn prefab_doors(mut self, doors: impl Into<Vector<IVec2>>) -> DungeonStateBuilder {
+        self.prefab_doors = doors.into();
         self
     }
 
-    pub fn corridors(mut self, corridors: Vec<Corridor>) -> DungeonStateBuilder {
-        self.layout = DungeonLayout {
-            rooms: self.layout.rooms,
-            corridors,
-        };
+    /// Reseeds the builder, replacing its RNG with a fresh one seeded from `seed`.
+    pub fn seed(mut self, seed: u64) -> DungeonStateBuilder {
+        self.seed = seed;
+        self.rng = Arc::new(Mutex::new(DungeonRng::seed_from_u64(seed)));
+        self
+    }
+
+    pub fn params(mut self, params: GenerationParams) -> DungeonStateBuilder {
+        self.params = params;
+        self
+    }
+
+    pub fn rooms(mut self, rooms: impl Into<Vector<Room>>) -> DungeonStateBuilder {
+        self.layout.rooms = rooms.into();
+        self
+    }
+
+    pub fn corridors(mut self, corridors: impl Into<Vector<Corridor>>) -> DungeonStateBuilder {
+        self.layout.corridors = corridors.into();
+        self
+    }
+
+    pub fn push_room(mut self, room: Room) -> DungeonStateBuilder {
+        self.layout.push_room(room);
+        self
+    }
+
+    pub fn push_corridor(mut self, corridor: Corridor) -> DungeonStateBuilder {
+        self.layout.push_corridor(corridor);
         self
     }
 }
@@ -0,0 +1,47 @@
+//! Small hand-built floors for tests.
+
+use crate::dungeon_generation::corridor_shapes::segment;
+use crate::dungeon_generation::dungeon_state::DungeonLayout;
+use crate::dungeon_generation::room::Orientation::{RIGHT, UP};
+use crate::dungeon_generation::room::{Rectangle, Room};
+use crate::dungeon_generation::room_outline::RoomOutline;
+use bevy::math::IVec2;
+use im::{vector, Vector};
+
+/// A 5×5 room with its bottom left corner at `(x, y)`.
+pub fn room(x: i32, y: i32) -> Room {
+    Room {
+        shape: Rectangle {
+            width: 5,
+            height: 5,
+        },
+        position: IVec2::new(x, y),
+        outline: RoomOutline::Rectangle,
+        role: None,
+    }
+}
+
+/// `rooms` rooms 10 tiles apart along the x axis, each joined to the next by a corridor of
+/// length 7 along y = 2, so room `i` spans x `10 * i` to `10 * i + 4`.
+pub fn row_of_rooms(rooms: i32) -> DungeonLayout {
+    DungeonLayout::new(
+        (0..rooms).map(|i| room(i * 10, 0)).collect::<Vector<_>>(),
+        (0..rooms - 1)
+            .map(|i| segment(IVec2::new(i * 10 + 4, 2), RIGHT, 7))
+            .collect::<Vector<_>>(),
+    )
+}
+
+/// Four rooms in a square joined into a ring, 0 - 1 - 3 - 2 - 0, each corridor of length 7 two
+/// tiles in from the rooms' edge.
+pub fn ring_of_rooms() -> DungeonLayout {
+    DungeonLayout::new(
+        vector![room(0, 0), room(10, 0), room(0, 10), room(10, 10)],
+        vector![
+            segment(IVec2::new(4, 2), RIGHT, 7),
+            segment(IVec2::new(2, 4), UP, 7),
+            segment(IVec2::new(12, 4), UP, 7),
+            segment(IVec2::new(4, 12), RIGHT, 7),
+        ],
+    )
+}
@@ -0,0 +1,90 @@
+use crate::dungeon_generation::constraints::Constraint;
+use crate::dungeon_generation::dungeon_state::DungeonLayout;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Why a single attempt of a generator step was rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StepError {
+    /// The step needs a room to build from or place something in, and there is none.
+    NoRoom,
+    /// The step needs a corridor to build from or place something in, and there is none.
+    NoCorridor,
+    /// The new shape overlaps a room or corridor already in the layout.
+    Collision,
+    /// The chosen room or corridor is too small to fit what the step wanted to place.
+    OutOfBounds,
+    /// A key is locked behind its own door and could not be moved somewhere reachable.
+    Unsolvable,
+    /// Every key colour already has a door on this floor.
+    NoKeyColour,
+    /// Wave function collapse left a cell with no pattern that fits its neighbours.
+    Contradiction,
+    /// Every floor generated failed the generator's constraints.
+    Unacceptable,
+}
+
+/// A generator step that failed every attempt it was allowed, or, with `reason`
+/// `StepError::Unacceptable`, no floor meeting the generator's constraints after `attempts`
+/// floors. That is reported as a step after the last one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenerationError {
+    pub step: String,
+    pub step_index: usize,
+    pub attempts: u32,
+    pub reason: StepError,
+    /// The layout the failing step started from, if anything had been built yet, or the last
+    /// floor rejected by the constraints.
+    pub last_good_layout: Option<Box<DungeonLayout>>,
+    /// Each constraint floors failed and on how many of them, when no floor met them.
+    pub failed_constraints: Vec<(Constraint, u32)>,
+}
+
+impl Display for StepError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            StepError::NoRoom => "no room to build from",
+            StepError::NoCorridor => "no corridor to build from",
+            StepError::Collision => "collides with the existing layout",
+            StepError::OutOfBounds => "does not fit inside the chosen shape",
+            StepError::Unsolvable => "a key is locked behind its own door",
+            StepError::NoKeyColour => "every key colour is already in use",
+            StepError::Contradiction => "no tile fits between its neighbours",
+            StepError::Unacceptable => "the floor does not meet its constraints",
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl Error for StepError {}
+
+impl Display for GenerationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.reason == StepError::Unacceptable {
+            let failed: Vec<String> = self
+                .failed_constraints
+                .iter()
+                .map(|(constraint, floors)| format!("{constraint} (failed {floors})"))
+                .collect();
+
+            return write!(
+                f,
+                "no floor met the constraints after {attempts} floor(s): {failed}",
+                attempts = self.attempts,
+                failed = failed.join(", ")
+            );
+        }
+
+        write!(
+            f,
+            "step {index} ({step}) failed after {attempts} attempt(s): {reason}",
+            index = self.step_index + 1,
+            step = self.step,
+            attempts = self.attempts,
+            reason = self.reason
+        )
+    }
+}
+
+impl Error for GenerationError {}
@@ -0,0 +1,323 @@
+use crate::dungeon_generation::dungeon_state::DungeonLayout;
+use bevy::math::IVec2;
+use std::collections::VecDeque;
+
+/// Which rooms each corridor joins. Rebuilt from the layout's geometry, so it works for any step
+/// that adds rooms and corridors. Each corridor is a straight segment whose ends either open into
+/// a room or meet other segments at a junction, which is how bent and branching corridors join.
+/// Junctions are nodes of the graph numbered after the rooms, but only rooms are ever returned.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DungeonGraph {
+    room_count: usize,
+    edges: Vec<Edge>,
+    adjacency: Vec<Vec<usize>>,
+}
+
+/// A corridor joining two nodes: rooms, numbered as in the layout, or junctions after them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Edge {
+    ends: (usize, usize),
+    corridor: usize,
+}
+
+impl DungeonGraph {
+    pub fn from_layout(layout: &DungeonLayout) -> DungeonGraph {
+        let room_count = layout.rooms.len();
+        let mut junctions: Vec<IVec2> = Vec::new();
+
+        let mut node_at = |tile: IVec2| {
+            if let Some(room) = layout.room_at(tile) {
+                return room;
+            }
+
+            let junction = junctions
+                .iter()
+                .position(|&junction| junction == tile)
+                .unwrap_or_else(|| {
+                    junctions.push(tile);
+                    junctions.len() - 1
+                });
+
+            room_count + junction
+        };
+
+        let ends: Vec<(usize, usize)> = layout
+            .corridors
+            .iter()
+            .map(|corridor| (node_at(corridor.position), node_at(corridor.end())))
+            .collect();
+
+        let mut edges = Vec::new();
+        let mut adjacency = vec![Vec::new(); room_count + junctions.len()];
+
+        for (corridor, &(a, b)) in ends.iter().enumerate() {
+            if a != b {
+                adjacency[a].push(edges.len());
+                adjacency[b].push(edges.len());
+                edges.push(Edge {
+                    ends: (a, b),
+                    corridor,
+                });
+            }
+        }
+
+        DungeonGraph {
+            room_count,
+            edges,
+            adjacency,
+        }
+    }
+
+    pub fn room_count(&self) -> usize {
+        self.room_count
+    }
+
+    /// Rooms reachable from `room` without passing through another room, in ascending order.
+    pub fn neighbours(&self, room: usize) -> Vec<usize> {
+        let mut neighbours = Vec::new();
+        let mut visited = vec![false; self.adjacency.len()];
+        let mut stack = vec![room];
+        visited[room] = true;
+
+        while let Some(current) = stack.pop() {
+            for &edge in &self.adjacency[current] {
+                let next = self.other_end(edge, current);
+
+                if visited[next] {
+                    continue;
+                }
+                visited[next] = true;
+
+                if self.is_room(next) {
+                    neighbours.push(next);
+                } else {
+                    stack.push(next);
+                }
+            }
+        }
+
+        neighbours.sort_unstable();
+        neighbours
+    }
+
+    /// Rooms walked into on the way from `room` to every room, or `None` for rooms it cannot
+    /// reach.
+    pub fn distances_from(&self, room: usize) -> Vec<Option<usize>> {
+        self.distances_avoiding(room, &[])
+    }
+
+    /// Like `distances_from`, but without walking through the corridors in `blocked`.
+    pub fn distances_avoiding(&self, room: usize, blocked: &[usize]) -> Vec<Option<usize>> {
+        let mut distances = self.search(room, blocked);
+        distances.truncate(self.room_count);
+        distances
+    }
+
+    /// Dead ends: rooms with exactly one neighbouring room.
+    pub fn leaf_rooms(&self) -> Vec<usize> {
+        (0..self.room_count)
+            .filter(|&room| self.neighbours(room).len() == 1)
+            .collect()
+    }
+
+    /// Corridors whose removal would split the dungeon in two, i.e. those not on any cycle.
+    pub fn bridges(&self) -> Vec<usize> {
+        let mut bridges = self.depth_first_search();
+        bridges.sort_unstable();
+        bridges.dedup();
+        bridges
+    }
+
+    /// How many separate loops the dungeon has: how many corridors could be taken away, one after
+    /// another, without cutting any part of it off.
+    pub fn loop_count(&self) -> usize {
+        let mut reached = vec![false; self.adjacency.len()];
+        let mut components = 0;
+
+        for node in 0..self.adjacency.len() {
+            if reached[node] {
+                continue;
+            }
+            components += 1;
+
+            let distances = self.search(node, &[]);
+            for (other, distance) in distances.into_iter().enumerate() {
+                reached[other] |= distance.is_some();
+            }
+        }
+
+        self.edges.len() + components - self.adjacency.len()
+    }
+
+    fn is_room(&self, node: usize) -> bool {
+        node < self.room_count
+    }
+
+    fn other_end(&self, edge: usize, node: usize) -> usize {
+        let (a, b) = self.edges[edge].ends;
+        if a == node {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// Breadth first search that only counts steps into rooms, so a bent or branching corridor
+    /// is as far as a straight one. Returns the distance to every node.
+    fn search(&self, from: usize, blocked: &[usize]) -> Vec<Option<usize>> {
+        let mut distances = vec![None; self.adjacency.len()];
+        let mut queue = VecDeque::from([from]);
+        distances[from] = Some(0);
+
+        while let Some(current) = queue.pop_front() {
+            let distance = distances[current].unwrap_or(0);
+
+            for &edge in &self.adjacency[current] {
+                if blocked.contains(&self.edges[edge].corridor) {
+                    continue;
+                }
+
+                let next = self.other_end(edge, current);
+                let step = usize::from(self.is_room(next));
+
+                if distances[next].is_none_or(|known| distance + step < known) {
+                    distances[next] = Some(distance + step);
+
+                    if step == 0 {
+                        queue.push_front(next);
+                    } else {
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        distances
+    }
+
+    /// Tarjan's low-link search over every component, done iteratively so large floors cannot
+    /// overflow the stack. Returns the corridors of every bridge found.
+    fn depth_first_search(&self) -> Vec<usize> {
+        let node_count = self.adjacency.len();
+        let mut order = vec![usize::MAX; node_count];
+        let mut low = vec![0; node_count];
+        let mut bridges = Vec::new();
+        let mut counter = 0;
+
+        for root in 0..node_count {
+            if order[root] != usize::MAX {
+                continue;
+            }
+
+            order[root] = counter;
+            low[root] = counter;
+            counter += 1;
+
+            // (room, edge used to enter it, next adjacency index to look at)
+            let mut stack: Vec<(usize, Option<usize>, usize)> = vec![(root, None, 0)];
+
+            while let Some(&mut (room, parent_edge, ref mut next)) = stack.last_mut() {
+                if let Some(&edge) = self.adjacency[room].get(*next) {
+                    *next += 1;
+
+                    if Some(edge) == parent_edge {
+                        continue;
+                    }
+
+                    let neighbour = self.other_end(edge, room);
+
+                    if order[neighbour] == usize::MAX {
+                        order[neighbour] = counter;
+                        low[neighbour] = counter;
+                        counter += 1;
+
+                        stack.push((neighbour, Some(edge), 0));
+                    } else {
+                        low[room] = low[room].min(order[neighbour]);
+                    }
+                } else {
+                    stack.pop();
+
+                    if let (Some(edge), Some(&(parent, _, _))) = (parent_edge, stack.last()) {
+                        low[parent] = low[parent].min(low[room]);
+
+                        if low[room] > order[parent] {
+                            bridges.push(self.edges[edge].corridor);
+                        }
+                    }
+                }
+            }
+        }
+
+        bridges
+    }
+}
+
+impl DungeonLayout {
+    pub fn graph(&self) -> DungeonGraph {
+        DungeonGraph::from_layout(self)
+    }
+}
+
+#[cfg(test)]
+mod dungeon_graph_tests {
+    use crate::dungeon_generation::dungeon_generator::{
+        add_corridor_then_room, add_room, DungeonGenerator,
+    };
+    use crate::dungeon_generation::fixtures::{ring_of_rooms, row_of_rooms};
+
+    #[test]
+    fn neighbours_of_middle_room() {
+        assert_eq!(row_of_rooms(3).graph().neighbours(1), vec![0, 2]);
+    }
+
+    #[test]
+    fn leaf_rooms_are_dead_ends() {
+        assert_eq!(row_of_rooms(3).graph().leaf_rooms(), vec![0, 2]);
+        assert!(ring_of_rooms().graph().leaf_rooms().is_empty());
+    }
+
+    #[test]
+    fn chain_has_no_cycle() {
+        let graph = row_of_rooms(3).graph();
+
+        assert_eq!(graph.bridges(), vec![0, 1]);
+        assert_eq!(graph.loop_count(), 0);
+    }
+
+    #[test]
+    fn ring_has_cycle() {
+        let graph = ring_of_rooms().graph();
+
+        assert!(graph.bridges().is_empty());
+        assert_eq!(graph.loop_count(), 1);
+    }
+
+    #[test]
+    fn distances_avoiding_blocked_corridor() {
+        let graph = row_of_rooms(3).graph();
+
+        assert_eq!(graph.distances_from(0), vec![Some(0), Some(1), Some(2)]);
+        assert_eq!(
+            graph.distances_avoiding(0, &[1]),
+            vec![Some(0), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn generated_dungeon_is_a_connected_tree() {
+        let dungeon = DungeonGenerator::with_seed(11)
+            .add_step(add_room)
+            .add_retryable_step(add_corridor_then_room)
+            .add_retryable_step(add_corridor_then_room)
+            .add_retryable_step(add_corridor_then_room)
+            .generate()
+            .unwrap();
+
+        let graph = dungeon.layout.graph();
+
+        assert_eq!(graph.bridges(), vec![0, 1, 2]);
+        assert!(graph.distances_from(0).iter().all(Option::is_some));
+        assert_eq!(graph.loop_count(), 0);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::dungeon_generation::dungeon_state::{DungeonLayout, DungeonState};
+use crate::dungeon_generation::room::{Collision, Corridor};
+use crate::dungeon_generation::solvability::{doors_of, flood_fill};
+use crate::dungeon_generation::spawn_generation::SpawnType;
+use crate::dungeon_generation::tile_map::{get_tile_map, TileType};
+use bevy::math::IVec2;
+use bevy::utils::HashSet;
+
+/// Checks what every floor should hold at every step, naming the first thing that does not:
+///
+/// - no two rooms overlap, no corridor runs over a room's floor and corridors only meet at a
+///   single tile, where they bend, branch or cross
+/// - every spawn stands on floor
+pub fn check_layout(state: &DungeonState) -> Result<(), String> {
+    let layout = &state.layout;
+
+    for (index, room) in layout.rooms.iter().enumerate() {
+        if let Some(other) = layout
+            .rooms
+            .iter()
+            .skip(index + 1)
+            .position(|other| other.collides_with(room))
+        {
+            return Err(format!("rooms {index} and {} overlap", index + 1 + other));
+        }
+
+        let floor: HashSet<IVec2> = room.floor_tiles().into_iter().collect();
+        if let Some(corridor) = layout
+            .corridors
+            .iter()
+            .position(|corridor| tiles_of(corridor).any(|tile| floor.contains(&tile)))
+        {
+            return Err(format!("corridor {corridor} runs over room {index}"));
+        }
+    }
+
+    for (index, corridor) in layout.corridors.iter().enumerate() {
+        if let Some(other) = layout.corridors.iter().skip(index + 1).position(|other| {
+            tiles_of(corridor)
+                .filter(|tile| other.contains(*tile))
+                .count()
+                > 1
+        }) {
+            return Err(format!(
+                "corridors {index} and {} overlap",
+                index + 1 + other
+            ));
+        }
+    }
+
+    let tile_map = get_tile_map(layout);
+
+    for spawn in &state.spawns {
+        if tile_map.get(spawn.position) != TileType::Floor {
+            return Err(format!(
+                "{:?} at {} is not on floor",
+                spawn.spawn_type, spawn.position
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that every floor tile can be walked to from the player spawn, ignoring doors, once the
+/// player has been placed.
+pub fn check_reachable(state: &DungeonState) -> Result<(), String> {
+    let layout = &state.layout;
+    let tile_map = get_tile_map(layout);
+
+    let player = state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .ok_or("no player spawn")?;
+
+    let reachable = flood_fill(&tile_map, player.position, &HashSet::default());
+    let mut unreachable: Vec<IVec2> = floor_tiles(layout)
+        .into_iter()
+        .filter(|tile| !reachable.contains(tile))
+        .collect();
+    unreachable.sort_by_key(|tile| (tile.x, tile.y));
+
+    if let Some(tile) = unreachable.first() {
+        return Err(format!(
+            "{count} floor tiles cannot be reached from the player, e.g. {tile}",
+            count = unreachable.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that every door sits on a corridor, off every room's floor, for floors built from
+/// rooms and corridors. Doors placed by prefabs stand in the prefab's own doorways instead, so
+/// they are left out.
+pub fn check_doors_in_corridors(state: &DungeonState) -> Result<(), String> {
+    let layout = &state.layout;
+
+    for (door, _) in doors_of(state) {
+        if state.prefab_doors.contains(&door) {
+            continue;
+        }
+
+        let in_corridor = layout
+            .corridors
+            .iter()
+            .any(|corridor| corridor.contains(door));
+        let in_room = layout
+            .rooms
+            .iter()
+            .any(|room| room.floor_tiles().contains(&door));
+
+        if !in_corridor || in_room {
+            return Err(format!("door at {door} is not inside a corridor"));
+        }
+    }
+
+    Ok(())
+}
+
+fn tiles_of(corridor: &Corridor) -> impl Iterator<Item = IVec2> + '_ {
+    let dir = IVec2::from(corridor.shape.orientation);
+    (0..corridor.shape.length as i32).map(move |step| corridor.position + dir * step)
+}
+
+fn floor_tiles(layout: &DungeonLayout) -> HashSet<IVec2> {
+    layout
+        .rooms
+        .iter()
+        .flat_map(|room| room.floor_tiles())
+        .chain(layout.corridors.iter().flat_map(tiles_of))
+        .collect()
+}
+
+#[cfg(test)]
+mod generation_invariants_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::{fallback_dungeon, DungeonGenerator};
+    use crate::dungeon_generation::fixtures::room;
+    use crate::dungeon_generation::key::KeyColour;
+    use crate::dungeon_generation::recipe::{GenerationRecipe, RecipeStep, StepRegistry};
+    use crate::dungeon_generation::spawn_generation::Spawn;
+    use proptest::prelude::*;
+
+    /// The first `steps` steps of `source`, so a failing floor shrinks to the step that broke it
+    /// as well as to a small seed. The recipe's constraints are left out, since they are meant for
+    /// finished floors.
+    fn generator(source: &str, steps: usize) -> DungeonGenerator {
+        let recipe = GenerationRecipe::parse(source).unwrap();
+        let steps: Vec<RecipeStep> = recipe.steps.into_iter().take(steps).collect();
+
+        GenerationRecipe {
+            steps,
+            constraints: Vec::new(),
+            ..recipe
+        }
+        .to_generator(&StepRegistry::default())
+        .unwrap()
+    }
+
+    fn step_count(source: &str) -> usize {
+        GenerationRecipe::parse(source).unwrap().steps.len()
+    }
+
+    /// Generates `steps` steps of `source` from `seed` and checks the floor. A seed the recipe
+    /// fails on fails the case too, since every recipe should build every seed.
+    fn check(
+        source: &str,
+        seed: u64,
+        steps: usize,
+        depth: usize,
+        doors_in_corridors: bool,
+    ) -> Result<(), TestCaseError> {
+        let state = match generator(source, steps)
+            .depth(depth)
+            .generate_with_seed(seed)
+        {
+            Ok(state) => state,
+            Err(err) => return Err(TestCaseError::fail(format!("generation failed: {err}"))),
+        };
+
+        check_layout(&state).map_err(TestCaseError::fail)?;
+        // Steps before the player is placed have nobody to reach the floor from.
+        if state
+            .spawns
+            .iter()
+            .any(|spawn| spawn.spawn_type == SpawnType::Player)
+        {
+            check_reachable(&state).map_err(TestCaseError::fail)?;
+        }
+        if doors_in_corridors {
+            check_doors_in_corridors(&state).map_err(TestCaseError::fail)?;
+        }
+
+        Ok(())
+    }
+
+    const DEFAULT: &str = include_str!("../../assets/recipes/default.recipe.ron");
+    const BSP: &str = include_str!("../../assets/recipes/bsp.recipe.ron");
+    const CAVE: &str = include_str!("../../assets/recipes/cave.recipe.ron");
+    const WFC: &str = include_str!("../../assets/recipes/wfc.recipe.ron");
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn default_floors_hold(
+            seed in any::<u64>(),
+            steps in 1..=step_count(DEFAULT),
+            depth in 0..6usize,
+        ) {
+            check(DEFAULT, seed, steps, depth, true)?;
+        }
+
+        #[test]
+        fn bsp_floors_hold(seed in any::<u64>(), steps in 1..=step_count(BSP)) {
+            check(BSP, seed, steps, 0, true)?;
+        }
+
+        #[test]
+        fn cave_floors_hold(seed in any::<u64>(), steps in 1..=step_count(CAVE)) {
+            check(CAVE, seed, steps, 0, false)?;
+        }
+    }
+
+    proptest! {
+        // Wave function collapse is slow without optimisations.
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn wfc_floors_hold(seed in any::<u64>(), steps in 1..=step_count(WFC)) {
+            check(WFC, seed, steps, 0, false)?;
+        }
+    }
+
+    #[test]
+    fn catches_a_spawn_in_a_wall() {
+        let mut state = fallback_dungeon(0);
+        let room = &state.layout.rooms[0];
+        state.spawns[0].position = room.position;
+
+        assert!(check_layout(&state).unwrap_err().contains("not on floor"));
+    }
+
+    #[test]
+    fn catches_overlapping_rooms() {
+        let mut state = fallback_dungeon(0);
+        state.layout.rooms.push_back(state.layout.rooms[0].clone());
+
+        assert_eq!(check_layout(&state).unwrap_err(), "rooms 0 and 1 overlap");
+    }
+
+    #[test]
+    fn catches_overlapping_rooms_before_the_player_is_placed() {
+        let mut state = fallback_dungeon(0);
+        state.spawns.clear();
+        state.layout.rooms.push_back(state.layout.rooms[0].clone());
+
+        assert_eq!(check_layout(&state).unwrap_err(), "rooms 0 and 1 overlap");
+    }
+
+    #[test]
+    fn catches_a_room_cut_off_from_the_player() {
+        let mut state = fallback_dungeon(0);
+        state.layout.rooms.push_back(room(60, 60));
+
+        assert!(check_layout(&state).is_ok());
+        assert!(check_reachable(&state)
+            .unwrap_err()
+            .contains("cannot be reached from the player"));
+    }
+
+    #[test]
+    fn catches_a_door_in_the_middle_of_a_room() {
+        let mut state = fallback_dungeon(0);
+        state.spawns.push_back(Spawn {
+            position: IVec2::new(25, 25),
+            spawn_type: SpawnType::Door(KeyColour::Gold),
+        });
+
+        assert!(check_layout(&state).is_ok());
+        assert_eq!(
+            check_doors_in_corridors(&state).unwrap_err(),
+            "door at [25, 25] is not inside a corridor"
+        );
+    }
+
+    #[test]
+    fn lets_prefab_doors_stand_in_a_room() {
+        let mut state = fallback_dungeon(0);
+        state.spawns.push_back(Spawn {
+            position: IVec2::new(25, 25),
+            spawn_type: SpawnType::Door(KeyColour::Gold),
+        });
+        state.prefab_doors.push_back(IVec2::new(25, 25));
+
+        assert!(check_doors_in_corridors(&state).is_ok());
+    }
+}
@@ -1,37 +1,97 @@
 use crate::dungeon_generation::dungeon_state::{DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::room_roles::RoomRole;
 use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
-use bevy::prelude::{Component, IVec2};
+use bevy::prelude::{Color, Component};
 use rand::Rng;
 
 #[derive(Component, Debug, Default)]
-pub struct Key;
+pub struct Key {
+    pub colour: KeyColour,
+}
+
+/// Which doors a key opens. Each colour is its own inventory item.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum KeyColour {
+    Red,
+    Blue,
+    Green,
+    #[default]
+    Gold,
+}
+
+impl KeyColour {
+    /// Every colour, in the order lock chains hand them out.
+    pub const ALL: [KeyColour; 4] = [
+        KeyColour::Red,
+        KeyColour::Blue,
+        KeyColour::Green,
+        KeyColour::Gold,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyColour::Red => "red",
+            KeyColour::Blue => "blue",
+            KeyColour::Green => "green",
+            KeyColour::Gold => "gold",
+        }
+    }
+
+    /// The inventory item picked up from, and spent on, this colour.
+    pub fn item_name(self) -> String {
+        format!("{} key", self.name())
+    }
+
+    pub fn key_sprite_colour(self) -> Color {
+        match self {
+            KeyColour::Red => Color::rgb(1.0, 0.3, 0.3),
+            KeyColour::Blue => Color::rgb(0.3, 0.5, 1.0),
+            KeyColour::Green => Color::rgb(0.3, 0.9, 0.4),
+            KeyColour::Gold => Color::rgb(1.0, 1.0, 0.4),
+        }
+    }
 
-pub fn add_key(state: &DungeonState) -> Result<DungeonState, String> {
+    pub fn door_sprite_colour(self) -> Color {
+        match self {
+            KeyColour::Red => Color::rgb(0.4, 0.05, 0.05),
+            KeyColour::Blue => Color::rgb(0.0, 0.05, 0.4),
+            KeyColour::Green => Color::rgb(0.0, 0.3, 0.05),
+            KeyColour::Gold => Color::rgb(0.4, 0.35, 0.0),
+        }
+    }
+}
+
+pub fn add_key(state: &DungeonState) -> Result<DungeonState, StepError> {
     if state.layout.rooms.len() == 0 {
-        return Err("No room to place key in".to_string());
+        return Err(StepError::NoRoom);
     }
 
-    let mut rng = state.rng.borrow_mut();
+    let mut rng = state.rng.lock().unwrap();
 
-    let index = rng.gen_range(0..state.layout.rooms.len());
+    // Treasure rooms, once roles are assigned, are where keys belong.
+    let treasure = state.layout.rooms_with_role(RoomRole::Treasure);
+    let index = if treasure.is_empty() {
+        rng.gen_range(0..state.layout.rooms.len())
+    } else {
+        treasure[rng.gen_range(0..treasure.len())]
+    };
     let random_room = state.layout.rooms.get(index);
 
     if let Some(room) = random_room {
-        let x = rng.gen_range(1..(room.shape.width - 1)) as i32;
-        let y = rng.gen_range(1..(room.shape.height - 1)) as i32;
-
-        let mut spawns = state.spawns.clone();
-        spawns.push(Spawn {
-            position: IVec2::new(x, y) + room.position,
-            spawn_type: SpawnType::Key,
-        });
+        let position = room
+            .random_floor_tile(&mut rng)
+            .ok_or(StepError::OutOfBounds)?;
 
         return Ok(DungeonStateBuilder::from_state(state)
-            .spawns(spawns)
+            .push_spawn(Spawn {
+                position,
+                spawn_type: SpawnType::Key(KeyColour::Gold),
+            })
             .build());
     }
 
-    return Err("Failed to place key".to_string());
+    Err(StepError::NoRoom)
 }
 
 #[cfg(test)]
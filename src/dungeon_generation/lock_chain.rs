@@ -0,0 +1,255 @@
+use crate::dungeon_generation::dungeon_state::{DungeonLayout, DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::key::KeyColour;
+use crate::dungeon_generation::solvability::{doors_of, is_solvable, keys_of};
+use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+use bevy::math::IVec2;
+use rand::Rng;
+
+/// Generator step that adds the next lock of a key-and-door chain, in a colour not used on the
+/// floor yet. The key goes in the region the previous door unlocks, and the door on a corridor
+/// that cuts off part of the floor without cutting off that key or any earlier one, so each key
+/// sits behind the door placed before it. Repeat the step in a recipe for a longer chain.
+pub fn add_chained_lock(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let colour = next_colour(state).ok_or(StepError::NoKeyColour)?;
+
+    let player = state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .ok_or(StepError::NoRoom)?;
+
+    let start = state
+        .layout
+        .room_at(player.position)
+        .ok_or(StepError::NoRoom)?;
+
+    let graph = state.layout.graph();
+    let door_corridors: Vec<usize> = doors_of(state)
+        .into_iter()
+        .filter_map(|(door, _)| corridor_at(&state.layout, door))
+        .collect();
+
+    let unlocked = reached(&graph.distances_from(start));
+    let unlocked_count = unlocked.len();
+    let region: Vec<usize> = match door_corridors.last() {
+        Some(&last_door) => {
+            let in_front = reached(&graph.distances_avoiding(start, &[last_door]));
+            unlocked
+                .into_iter()
+                .filter(|room| !in_front.contains(room))
+                .collect()
+        }
+        None => unlocked,
+    };
+
+    let key_rooms_so_far: Vec<usize> = keys_of(state)
+        .into_iter()
+        .filter_map(|(key, _)| state.layout.room_at(key))
+        .collect();
+
+    let candidates: Vec<(usize, Vec<usize>)> = graph
+        .bridges()
+        .into_iter()
+        .filter(|corridor| !door_corridors.contains(corridor))
+        .filter_map(|corridor| {
+            let in_front = reached(&graph.distances_avoiding(start, &[corridor]));
+
+            if in_front.len() == unlocked_count
+                || key_rooms_so_far.iter().any(|room| !in_front.contains(room))
+            {
+                return None;
+            }
+
+            let key_rooms: Vec<usize> = region
+                .iter()
+                .copied()
+                .filter(|room| in_front.contains(room))
+                .collect();
+
+            (!key_rooms.is_empty()).then_some((corridor, key_rooms))
+        })
+        .collect();
+
+    if candidates.is_empty() {
+        return Err(StepError::NoCorridor);
+    }
+
+    let occupied: Vec<IVec2> = state.spawns.iter().map(|spawn| spawn.position).collect();
+    let mut rng = state.rng.lock().unwrap();
+
+    let (corridor_index, key_rooms) = &candidates[rng.gen_range(0..candidates.len())];
+    let corridor = &state.layout.corridors[*corridor_index];
+    let key_room = &state.layout.rooms[key_rooms[rng.gen_range(0..key_rooms.len())]];
+
+    let dir: IVec2 = corridor.shape.orientation.into();
+    let door_tiles: Vec<IVec2> = (1..corridor.shape.length as i32)
+        .map(|step| corridor.position + dir * step)
+        .filter(|tile| !occupied.contains(tile))
+        // Not on a junction, where the door would also block the corridors meeting there.
+        .filter(|tile| {
+            state
+                .layout
+                .corridors
+                .iter()
+                .filter(|other| other.contains(*tile))
+                .count()
+                == 1
+        })
+        .collect();
+
+    let key_tiles: Vec<IVec2> = key_room
+        .floor_tiles()
+        .into_iter()
+        .filter(|tile| !occupied.contains(tile))
+        .collect();
+
+    if door_tiles.is_empty() || key_tiles.is_empty() {
+        return Err(StepError::OutOfBounds);
+    }
+
+    let mut spawns = state.spawns.clone();
+    spawns.push_back(Spawn {
+        position: key_tiles[rng.gen_range(0..key_tiles.len())],
+        spawn_type: SpawnType::Key(colour),
+    });
+    spawns.push_back(Spawn {
+        position: door_tiles[rng.gen_range(0..door_tiles.len())],
+        spawn_type: SpawnType::Door(colour),
+    });
+    drop(rng);
+
+    let locked = DungeonStateBuilder::from_state(state)
+        .spawns(spawns)
+        .build();
+
+    if is_solvable(&locked) {
+        Ok(locked)
+    } else {
+        Err(StepError::Unsolvable)
+    }
+}
+
+/// The first colour with neither a key nor a door on the floor.
+fn next_colour(state: &DungeonState) -> Option<KeyColour> {
+    KeyColour::ALL.into_iter().find(|colour| {
+        !state.spawns.iter().any(|spawn| {
+            spawn.spawn_type == SpawnType::Key(*colour)
+                || spawn.spawn_type == SpawnType::Door(*colour)
+        })
+    })
+}
+
+fn corridor_at(layout: &DungeonLayout, tile: IVec2) -> Option<usize> {
+    layout
+        .corridors
+        .iter()
+        .position(|corridor| corridor.contains(tile))
+}
+
+fn reached(distances: &[Option<usize>]) -> Vec<usize> {
+    distances
+        .iter()
+        .enumerate()
+        .filter(|(_, distance)| distance.is_some())
+        .map(|(room, _)| room)
+        .collect()
+}
+
+#[cfg(test)]
+mod lock_chain_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::DungeonGenerator;
+    use crate::dungeon_generation::fixtures::row_of_rooms;
+    use crate::dungeon_generation::retry_policy::RetryPolicy;
+    use crate::dungeon_generation::solvability::flood_fill;
+    use crate::dungeon_generation::tile_map::get_tile_map;
+    use bevy::utils::HashSet;
+
+    /// Four rooms in a row, joined left to right, with the player in the first.
+    fn four_rooms() -> DungeonState {
+        DungeonStateBuilder::default()
+            .layout(row_of_rooms(4))
+            .spawns(vec![Spawn {
+                position: IVec2::new(2, 2),
+                spawn_type: SpawnType::Player,
+            }])
+            .build()
+    }
+
+    /// A lock on the last corridor leaves nowhere behind it for the next key, so each lock goes
+    /// back to move the one before it when it finds no corridor.
+    fn generator(locks: usize, seed: u64) -> DungeonGenerator {
+        (0..locks).fold(
+            DungeonGenerator::with_seed(seed).add_step(|state: &DungeonState| {
+                let row = four_rooms();
+                Ok(DungeonStateBuilder::from_state(state)
+                    .layout(row.layout)
+                    .spawns(row.spawns)
+                    .build())
+            }),
+            |generator, _| {
+                generator.add_step_with_policy(
+                    "add_chained_lock",
+                    add_chained_lock,
+                    RetryPolicy::attempts(100).backtrack(1, 20),
+                )
+            },
+        )
+    }
+
+    #[test]
+    fn first_lock_uses_the_first_colour() {
+        let locked = add_chained_lock(&four_rooms()).unwrap();
+
+        assert_eq!(doors_of(&locked)[0].1, KeyColour::Red);
+        assert_eq!(keys_of(&locked)[0].1, KeyColour::Red);
+        assert!(is_solvable(&locked));
+    }
+
+    #[test]
+    fn each_key_sits_behind_the_previous_door() {
+        for seed in 0..20 {
+            let dungeon = generator(2, seed)
+                .generate()
+                .unwrap_or_else(|error| panic!("seed {seed}: {error}"));
+
+            let doors = doors_of(&dungeon);
+            let keys = keys_of(&dungeon);
+            assert_eq!(doors[1].1, KeyColour::Blue);
+            assert!(is_solvable(&dungeon), "seed {seed}");
+
+            let tile_map = get_tile_map(&dungeon.layout);
+            let first_door = HashSet::from_iter([doors[0].0]);
+            let in_front = flood_fill(&tile_map, IVec2::new(2, 2), &first_door);
+            assert!(!in_front.contains(&keys[1].0), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn runs_out_of_colours() {
+        let mut dungeon = four_rooms();
+        for colour in KeyColour::ALL {
+            dungeon.spawns.push_back(Spawn {
+                position: IVec2::new(2, 3),
+                spawn_type: SpawnType::Key(colour),
+            });
+        }
+
+        assert_eq!(
+            add_chained_lock(&dungeon).unwrap_err(),
+            StepError::NoKeyColour
+        );
+    }
+
+    #[test]
+    fn fails_without_a_corridor_to_lock() {
+        let mut dungeon = four_rooms();
+        dungeon.layout.corridors.clear();
+
+        assert_eq!(
+            add_chained_lock(&dungeon).unwrap_err(),
+            StepError::NoCorridor
+        );
+    }
+}
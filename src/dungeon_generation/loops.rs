@@ -0,0 +1,257 @@
+use crate::dungeon_generation::corridor_shapes::{fits, segment};
+use crate::dungeon_generation::dungeon_state::{DungeonRng, DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::room::Orientation::{DOWN, LEFT, RIGHT, UP};
+use crate::dungeon_generation::room::{Corridor, Orientation, Room};
+use bevy::math::IVec2;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Rooms further apart than this, in tiles between their walls, are never joined by a loop.
+pub const MAX_LOOP_GAP: i32 = 16;
+
+/// Generator step that joins two nearby rooms that no corridor joins yet, with a straight or an
+/// L shaped corridor, so the floor gets a cycle. Rooms are tried nearest first. Repeat the step
+/// in a recipe to set how many loops a floor should get, and mark it `optional` to accept fewer
+/// when the layout has no room for more.
+pub fn add_loop(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let rooms = &state.layout.rooms;
+    let graph = state.layout.graph();
+
+    let mut pairs: Vec<(i32, usize, usize)> = (0..rooms.len())
+        .flat_map(|a| (a + 1..rooms.len()).map(move |b| (a, b)))
+        .filter(|&(a, b)| !graph.neighbours(a).contains(&b))
+        .map(|(a, b)| (gap(&rooms[a], &rooms[b]), a, b))
+        .filter(|&(gap, _, _)| gap <= MAX_LOOP_GAP)
+        .collect();
+
+    if pairs.is_empty() {
+        return Err(StepError::NoRoom);
+    }
+
+    pairs.sort_unstable();
+
+    let mut rng = state.rng.lock().unwrap();
+
+    for (_, a, b) in pairs {
+        let mut options = connections(&rooms[a], &rooms[b], &mut rng);
+        options.shuffle(&mut *rng);
+
+        if let Some(segments) = options
+            .into_iter()
+            .find(|segments| fits(&state.layout, segments, &[]))
+        {
+            let mut layout = state.layout.clone();
+            for segment in segments {
+                layout.push_corridor(segment);
+            }
+
+            return Ok(DungeonStateBuilder::from_state(state)
+                .layout(layout)
+                .build());
+        }
+    }
+
+    Err(StepError::Collision)
+}
+
+/// Tiles between the walls of two rooms, counted along both axes.
+pub(crate) fn gap(a: &Room, b: &Room) -> i32 {
+    let (a_left, a_bottom, a_right, a_top) = walls(a);
+    let (b_left, b_bottom, b_right, b_top) = walls(b);
+
+    let x = (b_left - a_right).max(a_left - b_right).max(0);
+    let y = (b_bottom - a_top).max(a_bottom - b_top).max(0);
+
+    x + y
+}
+
+/// Every way of joining `from` and `to` this step knows: straight across where their floors
+/// face each other, and an L leaving either room sideways and turning into the other.
+pub(crate) fn connections(from: &Room, to: &Room, rng: &mut DungeonRng) -> Vec<Vec<Corridor>> {
+    [
+        straight_across(from, to, rng),
+        straight_across(to, from, rng),
+        bent_across(from, to, rng),
+        bent_across(to, from, rng),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// A straight corridor from a wall opening of `from` to a facing opening of `to`, when the two
+/// line up.
+fn straight_across(from: &Room, to: &Room, rng: &mut DungeonRng) -> Option<Vec<Corridor>> {
+    for orientation in [RIGHT, UP] {
+        let dir: IVec2 = orientation.into();
+        let entries = to.wall_openings(orientation.opposite());
+
+        let pairs: Vec<(IVec2, IVec2)> = from
+            .wall_openings(orientation)
+            .into_iter()
+            .flat_map(|exit| entries.iter().map(move |entry| (exit, *entry)))
+            .filter(|(exit, entry)| {
+                let apart = *entry - *exit;
+                apart.perp_dot(dir) == 0 && apart.dot(dir) >= 2
+            })
+            .collect();
+
+        if pairs.is_empty() {
+            continue;
+        }
+
+        let (exit, entry) = pairs[rng.gen_range(0..pairs.len())];
+        let length = (entry - exit).dot(dir) as u32 + 1;
+
+        return Some(vec![segment(exit, orientation, length)]);
+    }
+
+    None
+}
+
+/// An L shaped corridor leaving the side of `from` that faces `to`, then turning to enter `to`
+/// through an opening in its top or bottom wall.
+fn bent_across(from: &Room, to: &Room, rng: &mut DungeonRng) -> Option<Vec<Corridor>> {
+    let mut options: Vec<(IVec2, Orientation, IVec2, Orientation)> = Vec::new();
+
+    for across in [RIGHT, LEFT] {
+        for turn in [UP, DOWN] {
+            let (across_dir, turn_dir): (IVec2, IVec2) = (across.into(), turn.into());
+            let entries = to.wall_openings(turn.opposite());
+
+            options.extend(
+                from.wall_openings(across)
+                    .into_iter()
+                    .flat_map(|exit| entries.iter().map(move |entry| (exit, *entry)))
+                    .filter(|(exit, entry)| {
+                        let apart = *entry - *exit;
+                        apart.dot(across_dir) >= 2 && apart.dot(turn_dir) >= 2
+                    })
+                    .map(|(exit, entry)| (exit, across, entry, turn)),
+            );
+        }
+    }
+
+    if options.is_empty() {
+        return None;
+    }
+
+    let (exit, across, entry, turn) = options[rng.gen_range(0..options.len())];
+
+    let first = segment(exit, across, (entry.x - exit.x).unsigned_abs() + 1);
+    let second = segment(first.end(), turn, (entry.y - exit.y).unsigned_abs() + 1);
+
+    Some(vec![first, second])
+}
+
+/// The x of the left and right walls and y of the bottom and top walls of `room`.
+fn walls(room: &Room) -> (i32, i32, i32, i32) {
+    (
+        room.position.x,
+        room.position.y,
+        room.position.x + room.shape.width as i32 - 1,
+        room.position.y + room.shape.height as i32 - 1,
+    )
+}
+
+#[cfg(test)]
+mod loop_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::{add_room, DungeonGenerator};
+    use crate::dungeon_generation::dungeon_state::DungeonLayout;
+    use crate::dungeon_generation::fixtures::room;
+    use crate::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+    use im::vector;
+    use rand::SeedableRng;
+
+    /// Rooms 0 - 1 - 2 joined in an L around the corner, with rooms 0 and 2 not joined yet.
+    fn corner() -> DungeonState {
+        DungeonStateBuilder::default()
+            .layout(DungeonLayout::new(
+                vector![room(0, 0), room(10, 0), room(10, 10)],
+                vector![
+                    segment(IVec2::new(4, 2), RIGHT, 7),
+                    segment(IVec2::new(12, 4), UP, 7),
+                ],
+            ))
+            .build()
+    }
+
+    #[test]
+    fn joins_the_unconnected_rooms() {
+        let looped = add_loop(&corner()).unwrap();
+
+        let graph = looped.layout.graph();
+        assert_eq!(graph.loop_count(), 1);
+        assert_eq!(graph.neighbours(0), vec![1, 2]);
+    }
+
+    #[test]
+    fn straight_corridor_between_facing_rooms() {
+        let corridor =
+            straight_across(&room(0, 0), &room(10, 0), &mut DungeonRng::seed_from_u64(0))
+                .unwrap()
+                .remove(0);
+
+        assert_eq!(corridor.position.x, 4);
+        assert_eq!(corridor.end().x, 10);
+        assert_eq!(corridor.shape.orientation, RIGHT);
+    }
+
+    #[test]
+    fn bent_corridor_turns_into_the_other_room() {
+        let segments = bent_across(
+            &room(0, 0),
+            &room(10, 10),
+            &mut DungeonRng::seed_from_u64(0),
+        )
+        .unwrap();
+
+        assert_eq!(segments[0].position.x, 4);
+        assert_eq!(segments[1].position, segments[0].end());
+        assert_eq!(segments[1].end().y, 10);
+        assert!(room(10, 10).contains(segments[1].end()));
+    }
+
+    #[test]
+    fn fails_when_every_room_is_already_joined() {
+        let mut dungeon = corner();
+        dungeon.layout.rooms.pop_back();
+        dungeon.layout.corridors.pop_back();
+
+        assert_eq!(add_loop(&dungeon).unwrap_err(), StepError::NoRoom);
+    }
+
+    #[test]
+    fn optional_loops_never_fail_generation() {
+        let generator = DungeonGenerator::with_seed(2)
+            .add_step(add_room)
+            .add_optional_named_step("add_loop", add_loop, 10);
+
+        let one_room = generator.run_step(0, &generator.initial_state()).0.unwrap();
+        let (result, report) = generator.run_step(1, &one_room);
+
+        assert!(result.is_ok());
+        assert!(report.skipped);
+        assert_eq!(report.attempts, 10);
+        assert!(generator.generate().is_ok());
+    }
+
+    #[test]
+    fn default_recipe_makes_cycles() {
+        let looped = (0..10)
+            .filter_map(|seed| {
+                GenerationRecipe::default()
+                    .to_generator(&StepRegistry::default())
+                    .unwrap()
+                    .seed(seed)
+                    .generate()
+                    .ok()
+            })
+            .filter(|dungeon| dungeon.layout.graph().loop_count() > 0)
+            .count();
+
+        assert!(looped > 0);
+    }
+}
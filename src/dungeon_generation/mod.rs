@@ -1,6 +1,30 @@
+pub mod bsp;
+pub mod cave;
+pub mod constraints;
+pub mod corridor_shapes;
 pub mod door;
 pub mod dungeon_generator;
 pub mod dungeon_state;
+#[cfg(test)]
+pub mod fixtures;
+pub mod generation_error;
+pub mod graph;
+pub mod invariants;
 pub mod key;
+pub mod lock_chain;
+pub mod loops;
+pub mod params;
+pub mod prefab;
+pub mod recipe;
+pub mod retry_policy;
 pub mod room;
+pub mod room_outline;
+pub mod room_roles;
+pub mod scoring;
+pub mod solvability;
+pub mod spatial_index;
 pub mod spawn_generation;
+pub mod stairs;
+pub mod stats;
+pub mod tile_map;
+pub mod wfc;
@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A number that grows with depth: `base` on floor 0 and `per_floor` more on each floor below
+/// it, never past `max`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Curve {
+    pub base: f32,
+    #[serde(default)]
+    pub per_floor: f32,
+    #[serde(default = "no_limit")]
+    pub max: f32,
+}
+
+fn no_limit() -> f32 {
+    f32::INFINITY
+}
+
+impl Curve {
+    /// The same value on every floor.
+    pub const fn flat(value: f32) -> Curve {
+        Curve {
+            base: value,
+            per_floor: 0.0,
+            max: value,
+        }
+    }
+
+    pub fn at(&self, depth: usize) -> f32 {
+        (self.base + self.per_floor * depth as f32).min(self.max)
+    }
+}
+
+/// The numbers the built-in steps generate from, for the floor at `depth`. Every field but the
+/// depth can be set in a recipe's `params`, so each recipe can tune them for its own kind of
+/// floor; fields left out keep their defaults.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct GenerationParams {
+    /// How many floors below the first the floor is. Set by the game, not by recipes.
+    #[serde(skip)]
+    pub depth: usize,
+    /// Smallest width and height of a room made by `add_room`.
+    pub min_room_size: Curve,
+    /// Largest width and height of a room made by `add_room`, exclusive.
+    pub max_room_size: Curve,
+    pub min_corridor_length: Curve,
+    /// Longest straight corridor, exclusive.
+    pub max_corridor_length: Curve,
+    /// Where the first room's bottom left corner may go, on both axes.
+    pub first_room_origin: Range<i32>,
+    /// How many rooms `add_rooms_to_count` grows the floor to.
+    pub room_count: Curve,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        GenerationParams {
+            depth: 0,
+            min_room_size: Curve::flat(6.0),
+            max_room_size: Curve {
+                base: 16.0,
+                per_floor: 1.0,
+                max: 22.0,
+            },
+            min_corridor_length: Curve::flat(3.0),
+            max_corridor_length: Curve {
+                base: 12.0,
+                per_floor: 1.0,
+                max: 16.0,
+            },
+            first_room_origin: 20..25,
+            room_count: Curve {
+                base: 8.0,
+                per_floor: 1.0,
+                max: 15.0,
+            },
+        }
+    }
+}
+
+impl GenerationParams {
+    pub fn room_size(&self) -> Range<u32> {
+        self.range(self.min_room_size, self.max_room_size)
+    }
+
+    pub fn corridor_length(&self) -> Range<u32> {
+        self.range(self.min_corridor_length, self.max_corridor_length)
+    }
+
+    pub fn room_count(&self) -> usize {
+        self.room_count.at(self.depth) as usize
+    }
+
+    /// `first_room_origin`, widened to hold at least one value.
+    pub fn first_room_origin(&self) -> Range<i32> {
+        let Range { start, end } = self.first_room_origin;
+
+        start..end.max(start + 1)
+    }
+
+    /// Rejects curves that go below zero on some floor, since sizes and counts cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        let curves = [
+            ("min_room_size", self.min_room_size),
+            ("max_room_size", self.max_room_size),
+            ("min_corridor_length", self.min_corridor_length),
+            ("max_corridor_length", self.max_corridor_length),
+            ("room_count", self.room_count),
+        ];
+
+        match curves
+            .iter()
+            .find(|(_, curve)| curve.base < 0.0 || curve.per_floor < 0.0 || curve.max < 0.0)
+        {
+            Some((name, _)) => Err(format!("{name} goes below zero")),
+            None => Ok(()),
+        }
+    }
+
+    /// `min..max` at the current depth, widened to hold at least one value.
+    fn range(&self, min: Curve, max: Curve) -> Range<u32> {
+        let min = min.at(self.depth) as u32;
+        let max = max.at(self.depth) as u32;
+
+        min..max.max(min + 1)
+    }
+}
+
+#[cfg(test)]
+mod generation_params_tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_the_first_floor() {
+        let params = GenerationParams::default();
+
+        assert_eq!(params.room_size(), 6..16);
+        assert_eq!(params.corridor_length(), 3..12);
+        assert_eq!(params.room_count(), 8);
+    }
+
+    #[test]
+    fn deeper_floors_grow_up_to_the_limit() {
+        let deeper = |depth| GenerationParams {
+            depth,
+            ..Default::default()
+        };
+
+        assert_eq!(deeper(2).room_size(), 6..18);
+        assert_eq!(deeper(3).room_count(), 11);
+        assert_eq!(deeper(50).room_size(), 6..22);
+        assert_eq!(deeper(50).corridor_length(), 3..16);
+    }
+
+    #[test]
+    fn ranges_are_never_empty() {
+        let params = GenerationParams {
+            min_room_size: Curve::flat(10.0),
+            max_room_size: Curve::flat(4.0),
+            ..Default::default()
+        };
+
+        assert_eq!(params.room_size(), 10..11);
+
+        let params = GenerationParams {
+            first_room_origin: 5..5,
+            ..Default::default()
+        };
+
+        assert_eq!(params.first_room_origin(), 5..6);
+    }
+
+    #[test]
+    fn negative_curves_are_rejected() {
+        let params = GenerationParams {
+            max_corridor_length: Curve {
+                base: 4.0,
+                per_floor: -1.0,
+                max: 4.0,
+            },
+            ..Default::default()
+        };
+
+        assert!(GenerationParams::default().validate().is_ok());
+        assert_eq!(
+            params.validate().unwrap_err(),
+            "max_corridor_length goes below zero"
+        );
+    }
+
+    #[test]
+    fn parses_with_defaults_for_missing_fields() {
+        let params: GenerationParams = ron::from_str(
+            "(room_count: (base: 4, per_floor: 2), first_room_origin: (start: 0, end: 1))",
+        )
+        .unwrap();
+
+        assert_eq!(params.room_count(), 4);
+        assert_eq!(params.first_room_origin, 0..1);
+        assert_eq!(params.room_size(), 6..16);
+        assert_eq!(
+            GenerationParams {
+                depth: 10,
+                ..params
+            }
+            .room_count(),
+            24
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use crate::dungeon_generation::dungeon_generator::{add_corridor, room_fits};
+use crate::dungeon_generation::dungeon_state::{DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::key::KeyColour;
+use crate::dungeon_generation::room::{Rectangle, Room};
+use crate::dungeon_generation::room_outline::RoomOutline;
+use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+use bevy::math::IVec2;
+use rand::Rng;
+use std::fs;
+use std::path::Path;
+
+/// A hand-authored room read from an ASCII template, top row first:
+///
+/// - `#` wall and ` ` (space) nothing, outside the room
+/// - `.` floor
+/// - `+` a socket in the wall where a corridor may enter
+/// - `P`, `K` and `D` floor with a player, key or door spawn on it
+///
+/// Walls are drawn around the floor like for any other room, so the outer ring of the template
+/// must not hold any floor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prefab {
+    shape: Rectangle,
+    /// One entry per tile, row by row from the bottom.
+    floor: Vec<bool>,
+    sockets: Vec<IVec2>,
+    spawns: Vec<(IVec2, SpawnType)>,
+}
+
+impl Prefab {
+    pub fn parse(source: &str) -> Result<Prefab, String> {
+        let rows: Vec<Vec<char>> = source
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.chars().collect())
+            .rev()
+            .collect();
+
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let height = rows.len();
+
+        let mut floor = vec![false; width * height];
+        let mut sockets = Vec::new();
+        let mut spawns = Vec::new();
+
+        for (y, row) in rows.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let offset = IVec2::new(x as i32, y as i32);
+
+                match tile {
+                    '#' | ' ' => continue,
+                    '+' => {
+                        sockets.push(offset);
+                        continue;
+                    }
+                    '.' => {}
+                    'P' => spawns.push((offset, SpawnType::Player)),
+                    'K' => spawns.push((offset, SpawnType::Key(KeyColour::Gold))),
+                    'D' => spawns.push((offset, SpawnType::Door(KeyColour::Gold))),
+                    other => return Err(format!("Unknown prefab tile '{other}'")),
+                }
+
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                    return Err(format!("Prefab floor at ({x}, {y}) is on its edge"));
+                }
+
+                floor[y * width + x] = true;
+            }
+        }
+
+        if sockets.is_empty() {
+            return Err("Prefab needs at least one '+' socket".to_string());
+        }
+
+        Ok(Prefab {
+            shape: Rectangle {
+                width: width as u32,
+                height: height as u32,
+            },
+            floor,
+            sockets,
+            spawns,
+        })
+    }
+
+    /// The prefab as a room with its bottom left corner at `position`.
+    pub fn to_room(&self, position: IVec2) -> Room {
+        Room {
+            shape: self.shape,
+            position,
+            outline: RoomOutline::Mask(self.floor.clone()),
+            role: None,
+        }
+    }
+}
+
+/// Every `.prefab` template in `dir`, in file name order so the same seed picks the same prefab.
+pub fn load_prefabs(dir: &Path) -> Result<Vec<Prefab>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("could not read {}: {err}", dir.display()))?;
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "prefab")
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let source = fs::read_to_string(path)
+                .map_err(|err| format!("could not read {}: {err}", path.display()))?;
+            Prefab::parse(&source).map_err(|err| format!("{}: {err}", path.display()))
+        })
+        .collect()
+}
+
+/// Generator step that places a random prefab from `prefabs` at the end of the last corridor,
+/// entered through one of its sockets, under the same collision rules as `add_room`. The
+/// prefab's spawns are added with it, except for a player spawn when the floor already has one.
+/// Registered as `add_prefab`, choosing from the templates in `assets/prefabs/` or the folder a
+/// recipe names.
+pub fn add_prefab_from(
+    state: &DungeonState,
+    prefabs: &[Prefab],
+) -> Result<DungeonState, StepError> {
+    let corridor = state.layout.corridors.last().ok_or(StepError::NoCorridor)?;
+
+    if prefabs.is_empty() {
+        return Err(StepError::NoRoom);
+    }
+
+    let mut rng = state.rng.lock().unwrap();
+    let prefab = &prefabs[rng.gen_range(0..prefabs.len())];
+
+    // The wall the corridor enters through faces back along it.
+    let openings: Vec<IVec2> = prefab
+        .to_room(IVec2::ZERO)
+        .wall_openings(corridor.shape.orientation.opposite())
+        .into_iter()
+        .filter(|opening| prefab.sockets.contains(opening))
+        .collect();
+
+    if openings.is_empty() {
+        return Err(StepError::OutOfBounds);
+    }
+
+    let position = corridor.end() - openings[rng.gen_range(0..openings.len())];
+    let room = prefab.to_room(position);
+
+    if !room_fits(&state.layout, &room) {
+        return Err(StepError::Collision);
+    }
+
+    let has_player = state
+        .spawns
+        .iter()
+        .any(|spawn| spawn.spawn_type == SpawnType::Player);
+
+    let mut spawns = state.spawns.clone();
+    spawns.extend(
+        prefab
+            .spawns
+            .iter()
+            .filter(|(_, spawn_type)| !(has_player && *spawn_type == SpawnType::Player))
+            .map(|(offset, spawn_type)| Spawn {
+                position: position + *offset,
+                spawn_type: spawn_type.clone(),
+            }),
+    );
+    let mut prefab_doors = state.prefab_doors.clone();
+    prefab_doors.extend(
+        prefab
+            .spawns
+            .iter()
+            .filter(|(_, spawn_type)| matches!(spawn_type, SpawnType::Door(_)))
+            .map(|(offset, _)| position + *offset),
+    );
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .push_room(room)
+        .spawns(spawns)
+        .prefab_doors(prefab_doors)
+        .build())
+}
+
+pub fn add_corridor_then_prefab_from(
+    state: &DungeonState,
+    prefabs: &[Prefab],
+) -> Result<DungeonState, StepError> {
+    add_corridor(state).and_then(|res| add_prefab_from(&res, prefabs))
+}
+
+#[cfg(test)]
+mod prefab_tests {
+    use super::*;
+    use crate::dungeon_generation::corridor_shapes::segment;
+    use crate::dungeon_generation::dungeon_state::DungeonLayout;
+    use crate::dungeon_generation::fixtures::room;
+    use crate::dungeon_generation::recipe::{asset_path, GenerationRecipe, StepRegistry};
+    use crate::dungeon_generation::room::Orientation::{LEFT, RIGHT};
+    use crate::dungeon_generation::solvability::is_solvable;
+    use crate::dungeon_generation::tile_map::{get_tile_map, TileType};
+    use im::vector;
+
+    const CLOSET: &str = "
+#####
+#.P.#
++.K.#
+#####
+";
+
+    /// A room with a corridor running right out of it.
+    fn corridor_east() -> DungeonState {
+        DungeonStateBuilder::default()
+            .layout(DungeonLayout::new(
+                vector![room(0, 0)],
+                vector![segment(IVec2::new(4, 2), RIGHT, 6)],
+            ))
+            .build()
+    }
+
+    #[test]
+    fn parses_floor_sockets_and_spawns() {
+        let prefab = Prefab::parse(CLOSET).unwrap();
+
+        assert_eq!(
+            prefab.shape,
+            Rectangle {
+                width: 5,
+                height: 4
+            }
+        );
+        assert_eq!(prefab.sockets, vec![IVec2::new(0, 1)]);
+        assert_eq!(
+            prefab.spawns,
+            vec![
+                (IVec2::new(2, 1), SpawnType::Key(KeyColour::Gold)),
+                (IVec2::new(2, 2), SpawnType::Player),
+            ]
+        );
+        assert_eq!(prefab.to_room(IVec2::ZERO).floor_tiles().len(), 6);
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(Prefab::parse("###\n#.#\n###").is_err());
+        assert!(Prefab::parse("#+#\n..#\n###").is_err());
+        assert!(Prefab::parse("#+#\n#?#\n###").is_err());
+    }
+
+    #[test]
+    fn loads_every_template_in_the_folder() {
+        let prefabs = load_prefabs(&asset_path("prefabs")).unwrap();
+
+        assert_eq!(prefabs.len(), 3);
+        assert_eq!(
+            prefabs[0],
+            Prefab::parse(include_str!("../../assets/prefabs/pillar_hall.prefab")).unwrap()
+        );
+        assert!(load_prefabs(&asset_path("missing")).is_err());
+    }
+
+    #[test]
+    fn placed_through_a_socket_at_the_corridor_end() {
+        let prefab = Prefab::parse(CLOSET).unwrap();
+
+        let placed = add_prefab_from(&corridor_east(), &[prefab]).unwrap();
+
+        let room = &placed.layout.rooms[1];
+        assert_eq!(room.position, IVec2::new(9, 1));
+        assert_eq!(
+            placed.spawns,
+            vector![
+                Spawn {
+                    position: IVec2::new(11, 2),
+                    spawn_type: SpawnType::Key(KeyColour::Gold),
+                },
+                Spawn {
+                    position: IVec2::new(11, 3),
+                    spawn_type: SpawnType::Player,
+                },
+            ]
+        );
+
+        let tile_map = get_tile_map(&placed.layout);
+        assert_eq!(tile_map.get(IVec2::new(9, 2)), TileType::Floor);
+        assert_eq!(tile_map.get(IVec2::new(9, 3)), TileType::Wall);
+    }
+
+    #[test]
+    fn remembers_the_doors_it_places() {
+        let prefab = Prefab::parse(&CLOSET.replace('K', "D")).unwrap();
+
+        let placed = add_prefab_from(&corridor_east(), &[prefab]).unwrap();
+
+        assert_eq!(placed.prefab_doors, vector![IVec2::new(11, 2)]);
+    }
+
+    #[test]
+    fn needs_a_socket_facing_the_corridor() {
+        let prefab = Prefab::parse(CLOSET).unwrap();
+        let mut state = corridor_east();
+        state.layout.corridors = vector![segment(IVec2::new(0, 2), LEFT, 6)];
+
+        assert_eq!(
+            add_prefab_from(&state, &[prefab]).unwrap_err(),
+            StepError::OutOfBounds
+        );
+    }
+
+    #[test]
+    fn collides_like_a_room() {
+        let prefab = Prefab::parse(CLOSET).unwrap();
+        let mut state = corridor_east();
+        state.layout.rooms.push_back(room(11, 0));
+
+        assert_eq!(
+            add_prefab_from(&state, &[prefab]).unwrap_err(),
+            StepError::Collision
+        );
+    }
+
+    #[test]
+    fn existing_player_spawn_is_kept() {
+        let prefab = Prefab::parse(CLOSET).unwrap();
+        let mut state = corridor_east();
+        state.spawns.push_back(Spawn {
+            position: IVec2::new(2, 2),
+            spawn_type: SpawnType::Player,
+        });
+
+        let placed = add_prefab_from(&state, &[prefab]).unwrap();
+
+        assert_eq!(placed.spawns.len(), 2);
+        assert_eq!(placed.spawns[0].position, IVec2::new(2, 2));
+    }
+
+    #[test]
+    fn default_recipe_mixes_in_prefabs() {
+        let prefabs: Vec<RoomOutline> = load_prefabs(&asset_path("prefabs"))
+            .unwrap()
+            .into_iter()
+            .map(|prefab| RoomOutline::Mask(prefab.floor))
+            .collect();
+        let mut with_prefab = 0;
+
+        for seed in 0..10 {
+            let dungeon = GenerationRecipe::default()
+                .to_generator(&StepRegistry::default())
+                .unwrap()
+                .seed(seed)
+                .generate()
+                .unwrap();
+
+            assert!(is_solvable(&dungeon), "seed {seed}");
+            if dungeon
+                .layout
+                .rooms
+                .iter()
+                .any(|room| prefabs.contains(&room.outline))
+            {
+                with_prefab += 1;
+            }
+        }
+
+        assert!(with_prefab > 0);
+    }
+}
@@ -0,0 +1,547 @@
+use crate::dungeon_generation::bsp::add_bsp_layout;
+use crate::dungeon_generation::cave::{add_cave, add_cave_door};
+use crate::dungeon_generation::constraints::Constraint;
+use crate::dungeon_generation::corridor_shapes::{
+    add_bent_corridor, add_bent_corridor_then_room, add_branch_then_room, add_branching_corridor,
+};
+use crate::dungeon_generation::door::add_door;
+use crate::dungeon_generation::dungeon_generator::{
+    add_corridor, add_corridor_then_room, add_room, add_rooms_to_count, has_room_count,
+    DungeonGenerator, StepDone, MAX_CANDIDATES,
+};
+use crate::dungeon_generation::dungeon_state::DungeonState;
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::key::add_key;
+use crate::dungeon_generation::lock_chain::add_chained_lock;
+use crate::dungeon_generation::loops::add_loop;
+use crate::dungeon_generation::params::GenerationParams;
+use crate::dungeon_generation::prefab::{
+    add_corridor_then_prefab_from, add_prefab_from, load_prefabs,
+};
+use crate::dungeon_generation::retry_policy::RetryPolicy;
+use crate::dungeon_generation::room_roles::assign_room_roles;
+use crate::dungeon_generation::scoring::Score;
+use crate::dungeon_generation::solvability::ensure_solvable;
+use crate::dungeon_generation::spawn_generation::place_player_spawn;
+use crate::dungeon_generation::stairs::place_stairs;
+use crate::dungeon_generation::wfc::{add_wfc_layout_with, WfcRules};
+use bevy::asset::{AssetLoader, BoxedFuture, FileAssetIo, LoadContext, LoadedAsset};
+use bevy::prelude::Resource;
+use bevy::reflect::TypeUuid;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The recipe compiled into the game, used until `assets/recipes/default.recipe.ron` has loaded.
+pub const DEFAULT_RECIPE: &str = include_str!("../../assets/recipes/default.recipe.ron");
+
+pub type RegisteredStep =
+    Arc<dyn Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync>;
+
+/// Reads the file or folder at a path and builds a step generating from what is in it.
+pub type StepLoader = Arc<dyn Fn(&Path) -> Result<RegisteredStep, String> + Send + Sync>;
+
+/// An ordered list of generator steps, loaded from a `.recipe.ron` file.
+#[derive(Clone, Debug, Deserialize, PartialEq, TypeUuid)]
+#[uuid = "5a3b6f0e-8d0c-4c0e-9a59-1f3c7c1f8e21"]
+pub struct GenerationRecipe {
+    /// Overrides for the numbers the steps generate from; see `GenerationParams`.
+    #[serde(default)]
+    pub params: GenerationParams,
+    pub steps: Vec<RecipeStep>,
+    /// What a finished floor must have to be kept; see `Constraint`.
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    /// How many floors to generate looking for one that meets the constraints.
+    #[serde(default = "max_candidates")]
+    pub max_candidates: u32,
+    /// How many floors the game generates at once, keeping the one `score` rates highest.
+    #[serde(default = "one")]
+    pub best_of: u32,
+    #[serde(default)]
+    pub score: Score,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RecipeStep {
+    pub step: String,
+    #[serde(default = "one")]
+    pub repeat: u32,
+    #[serde(default = "one")]
+    pub max_attempts: u32,
+    /// Stop retrying after this many milliseconds, even with attempts left.
+    #[serde(default)]
+    pub time_budget_ms: Option<u64>,
+    /// Skip the step instead of failing generation when every attempt is rejected.
+    #[serde(default)]
+    pub optional: bool,
+    /// Go back this many steps and replay them instead of failing generation when every attempt
+    /// is rejected.
+    #[serde(default)]
+    pub backtrack: usize,
+    /// How many times the step may backtrack in one generation.
+    #[serde(default = "default_max_backtracks")]
+    pub max_backtracks: u32,
+    /// The file or folder under `assets/` a step that generates from one reads, such as the
+    /// rule file of `add_wfc_layout`, instead of the step's own.
+    #[serde(default)]
+    pub asset: Option<String>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn max_candidates() -> u32 {
+    MAX_CANDIDATES
+}
+
+fn default_max_backtracks() -> u32 {
+    10
+}
+
+impl RecipeStep {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let policy =
+            RetryPolicy::attempts(self.max_attempts).backtrack(self.backtrack, self.max_backtracks);
+
+        match self.time_budget_ms {
+            Some(ms) => policy.time_budget(Duration::from_millis(ms)),
+            None => policy,
+        }
+    }
+}
+
+/// Maps the step names used in recipes to the functions that run them.
+#[derive(Resource)]
+pub struct StepRegistry {
+    steps: HashMap<String, RegisteredStep>,
+    /// Steps built from an asset each time a generator is, with the asset used unless a recipe
+    /// names another.
+    loaded_steps: HashMap<String, (String, StepLoader)>,
+    /// Steps that build a piece per attempt, with what says they are done.
+    repeated: HashMap<String, StepDone>,
+}
+
+#[derive(Default)]
+pub struct RecipeLoader;
+
+impl GenerationRecipe {
+    pub fn parse(source: &str) -> Result<GenerationRecipe, String> {
+        let recipe: GenerationRecipe =
+            ron::from_str(source).map_err(|err| format!("Invalid recipe: {err}"))?;
+        recipe
+            .params
+            .validate()
+            .map_err(|err| format!("Invalid recipe: {err}"))?;
+
+        Ok(recipe)
+    }
+
+    pub fn to_generator(&self, registry: &StepRegistry) -> Result<DungeonGenerator, String> {
+        let mut generator = self.constraints.iter().fold(
+            DungeonGenerator::new()
+                .params(self.params.clone())
+                .max_candidates(self.max_candidates)
+                .best_of(self.best_of, self.score),
+            |generator, constraint| generator.constraint(constraint.clone()),
+        );
+
+        for recipe_step in &self.steps {
+            // Steps built from an asset read it once here, however often they run.
+            let step = registry.build(&recipe_step.step, recipe_step.asset.as_deref())?;
+
+            for _ in 0..recipe_step.repeat {
+                let step = Arc::clone(&step);
+                let run = move |state: &DungeonState| step(state);
+
+                let policy = recipe_step.retry_policy();
+
+                generator = if recipe_step.optional {
+                    generator.add_optional_step_with_policy(&recipe_step.step, run, policy)
+                } else {
+                    generator.add_step_with_policy(&recipe_step.step, run, policy)
+                };
+
+                if let Some(done) = registry.repeated_until(&recipe_step.step) {
+                    generator = generator.repeat_until(done);
+                }
+            }
+        }
+
+        Ok(generator)
+    }
+}
+
+impl Default for GenerationRecipe {
+    fn default() -> Self {
+        GenerationRecipe::parse(DEFAULT_RECIPE).expect("built-in recipe is valid")
+    }
+}
+
+impl StepRegistry {
+    pub fn empty() -> StepRegistry {
+        StepRegistry {
+            steps: HashMap::default(),
+            loaded_steps: HashMap::default(),
+            repeated: HashMap::default(),
+        }
+    }
+
+    pub fn register<F>(&mut self, name: &str, step: F)
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.steps.insert(name.to_string(), Arc::new(step));
+        self.repeated.remove(name);
+    }
+
+    /// Registers a step that generates from the file or folder at `default_asset` under
+    /// `assets/`, or at the one a recipe names, read by `load` whenever a generator is built.
+    pub fn register_loaded<F>(&mut self, name: &str, default_asset: &str, load: F)
+    where
+        F: Fn(&Path) -> Result<RegisteredStep, String> + Send + Sync + 'static,
+    {
+        self.loaded_steps.insert(
+            name.to_string(),
+            (default_asset.to_string(), Arc::new(load)),
+        );
+    }
+
+    /// Registers a step that recipes run again on what it built until `done`, as
+    /// `DungeonGenerator::repeat_until` does.
+    pub fn register_repeated<F>(&mut self, name: &str, step: F, done: StepDone)
+    where
+        F: Fn(&DungeonState) -> Result<DungeonState, StepError> + Send + Sync + 'static,
+    {
+        self.register(name, step);
+        self.repeated.insert(name.to_string(), done);
+    }
+
+    /// What says the step called `name` is done, if it builds a piece per attempt.
+    pub fn repeated_until(&self, name: &str) -> Option<StepDone> {
+        self.repeated.get(name).copied()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredStep> {
+        self.steps.get(name)
+    }
+
+    /// The step called `name`, reading `asset` if it is built from one.
+    pub fn build(&self, name: &str, asset: Option<&str>) -> Result<RegisteredStep, String> {
+        if let Some((default_asset, load)) = self.loaded_steps.get(name) {
+            return load(&asset_path(asset.unwrap_or(default_asset)));
+        }
+
+        match (self.get(name), asset) {
+            (Some(_), Some(asset)) => Err(format!("Step '{name}' reads no asset, not '{asset}'")),
+            (Some(step), None) => Ok(Arc::clone(step)),
+            (None, _) => Err(format!("Unknown generator step '{name}'")),
+        }
+    }
+}
+
+/// Where the game loads the asset at `path` from.
+pub fn asset_path(path: &str) -> PathBuf {
+    FileAssetIo::get_base_path().join("assets").join(path)
+}
+
+impl Default for StepRegistry {
+    fn default() -> Self {
+        let mut registry = StepRegistry::empty();
+        registry.register("add_room", add_room);
+        registry.register("add_corridor", add_corridor);
+        registry.register("add_corridor_then_room", add_corridor_then_room);
+        registry.register_repeated("add_rooms_to_count", add_rooms_to_count, has_room_count);
+        registry.register("add_bent_corridor", add_bent_corridor);
+        registry.register("add_bent_corridor_then_room", add_bent_corridor_then_room);
+        registry.register("add_branching_corridor", add_branching_corridor);
+        registry.register("add_branch_then_room", add_branch_then_room);
+        registry.register_loaded("add_prefab", "prefabs", |path| {
+            let prefabs = load_prefabs(path)?;
+            Ok(Arc::new(move |state: &DungeonState| {
+                add_prefab_from(state, &prefabs)
+            }))
+        });
+        registry.register_loaded("add_corridor_then_prefab", "prefabs", |path| {
+            let prefabs = load_prefabs(path)?;
+            Ok(Arc::new(move |state: &DungeonState| {
+                add_corridor_then_prefab_from(state, &prefabs)
+            }))
+        });
+        registry.register("add_bsp_layout", add_bsp_layout);
+        registry.register("add_cave", add_cave);
+        registry.register_loaded("add_wfc_layout", "wfc/default.wfc.ron", |path| {
+            let rules = WfcRules::load(path)?;
+            Ok(Arc::new(move |state: &DungeonState| {
+                add_wfc_layout_with(state, &rules)
+            }))
+        });
+        registry.register("place_player_spawn", place_player_spawn);
+        registry.register("add_loop", add_loop);
+        registry.register("assign_room_roles", assign_room_roles);
+        registry.register("place_stairs", place_stairs);
+        registry.register("add_key", add_key);
+        registry.register("add_door", add_door);
+        registry.register("add_cave_door", add_cave_door);
+        registry.register("add_chained_lock", add_chained_lock);
+        registry.register("ensure_solvable", ensure_solvable);
+        registry
+    }
+}
+
+impl RecipeLoader {
+    /// Reads a recipe file the way `GenerationRecipe::parse` reads a string, params checked.
+    fn read(bytes: &[u8]) -> Result<GenerationRecipe, bevy::asset::Error> {
+        GenerationRecipe::parse(std::str::from_utf8(bytes)?).map_err(bevy::asset::Error::msg)
+    }
+}
+
+impl AssetLoader for RecipeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let recipe = RecipeLoader::read(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(recipe));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["recipe.ron"]
+    }
+}
+
+#[cfg(test)]
+mod recipe_tests {
+    use super::*;
+
+    #[test]
+    fn default_recipe_parses() {
+        let recipe = GenerationRecipe::default();
+
+        assert_eq!(recipe.steps[0].step, "add_room");
+        assert_eq!(recipe.steps[0].repeat, 1);
+        assert_eq!(recipe.steps[0].max_attempts, 1);
+        assert!(!recipe.steps[0].optional);
+    }
+
+    #[test]
+    fn optional_step_is_skipped_when_it_keeps_failing() {
+        let recipe = GenerationRecipe::parse(
+            r#"(steps: [(step: "add_room"), (step: "add_door", optional: true)])"#,
+        )
+        .unwrap();
+
+        let dungeon = recipe
+            .to_generator(&StepRegistry::default())
+            .unwrap()
+            .seed(4)
+            .generate()
+            .unwrap();
+
+        assert_eq!(dungeon.layout.rooms.len(), 1);
+        assert!(dungeon.spawns.is_empty());
+    }
+
+    #[test]
+    fn repeat_adds_the_step_several_times() {
+        let recipe = GenerationRecipe::parse(
+            r#"(steps: [(step: "add_room"), (step: "add_corridor_then_room", repeat: 3, max_attempts: 1000)])"#,
+        )
+        .unwrap();
+
+        let generator = recipe
+            .to_generator(&StepRegistry::default())
+            .unwrap()
+            .seed(5);
+
+        assert_eq!(generator.step_count(), 4);
+        assert_eq!(generator.generate().unwrap().layout.rooms.len(), 4);
+    }
+
+    #[test]
+    fn retry_policy_fields_parse() {
+        let recipe = GenerationRecipe::parse(
+            r#"(steps: [
+                (step: "add_room"),
+                (step: "add_door", max_attempts: 50, time_budget_ms: Some(20), backtrack: 3, max_backtracks: 4),
+            ])"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            recipe.steps[0].retry_policy(),
+            RetryPolicy::attempts(1).backtrack(0, 10)
+        );
+        assert_eq!(
+            recipe.steps[1].retry_policy(),
+            RetryPolicy::attempts(50)
+                .time_budget(Duration::from_millis(20))
+                .backtrack(3, 4)
+        );
+    }
+
+    #[test]
+    fn backtracking_steps_fail_less_often() {
+        let successes = |policy: &str| {
+            let recipe = GenerationRecipe::parse(&format!(
+                r#"(steps: [
+                    (step: "add_room"),
+                    (step: "add_corridor_then_room", repeat: 12, max_attempts: 5{policy}),
+                ])"#
+            ))
+            .unwrap();
+            let generator = recipe.to_generator(&StepRegistry::default()).unwrap();
+
+            (0..20)
+                .filter(|&seed| generator.generate_with_seed(seed).is_ok())
+                .count()
+        };
+
+        let without = successes("");
+        let with = successes(", backtrack: 2, max_backtracks: 20");
+        assert!(
+            with > without,
+            "{with} with backtracking, {without} without"
+        );
+    }
+
+    #[test]
+    fn best_of_and_score_parse() {
+        let recipe = GenerationRecipe::parse(
+            r#"(steps: [(step: "add_room")], best_of: 4, score: PathLength)"#,
+        )
+        .unwrap();
+
+        assert_eq!(recipe.best_of, 4);
+        assert_eq!(recipe.score, Score::PathLength);
+        assert_eq!(GenerationRecipe::default().score, Score::Compactness);
+    }
+
+    #[test]
+    fn constraints_reject_floors_that_fail_them() {
+        let recipe = GenerationRecipe::parse(
+            r#"(
+                steps: [(step: "add_room")],
+                constraints: [MinRooms(2)],
+                max_candidates: 5,
+            )"#,
+        )
+        .unwrap();
+
+        let error = recipe
+            .to_generator(&StepRegistry::default())
+            .unwrap()
+            .generate()
+            .unwrap_err();
+
+        assert_eq!(error.reason, StepError::Unacceptable);
+        assert_eq!(error.attempts, 5);
+        assert_eq!(
+            error.to_string(),
+            "no floor met the constraints after 5 floor(s): at least 2 rooms (failed 5)"
+        );
+    }
+
+    #[test]
+    fn unknown_step_is_an_error() {
+        let recipe = GenerationRecipe::parse(r#"(steps: [(step: "add_dragon")])"#).unwrap();
+
+        let result = recipe.to_generator(&StepRegistry::default());
+
+        assert_eq!(
+            result.err(),
+            Some("Unknown generator step 'add_dragon'".to_string())
+        );
+    }
+
+    #[test]
+    fn steps_can_read_another_asset() {
+        let recipe = GenerationRecipe::parse(
+            r#"(steps: [(step: "add_wfc_layout", asset: Some("wfc/missing.wfc.ron"))])"#,
+        )
+        .unwrap();
+
+        let error = recipe.to_generator(&StepRegistry::default()).err().unwrap();
+
+        assert!(error.contains("missing.wfc.ron"), "{error}");
+    }
+
+    #[test]
+    fn only_loaded_steps_take_an_asset() {
+        let recipe =
+            GenerationRecipe::parse(r#"(steps: [(step: "add_room", asset: Some("rooms"))])"#)
+                .unwrap();
+
+        assert_eq!(
+            recipe.to_generator(&StepRegistry::default()).err(),
+            Some("Step 'add_room' reads no asset, not 'rooms'".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_recipe_is_an_error() {
+        assert!(GenerationRecipe::parse("(steps: [(repeat: 2)])").is_err());
+    }
+
+    #[test]
+    fn negative_params_are_an_error() {
+        let result = GenerationRecipe::parse(
+            r#"(params: (min_room_size: (base: -2)), steps: [(step: "add_room")])"#,
+        );
+
+        assert_eq!(
+            result.err(),
+            Some("Invalid recipe: min_room_size goes below zero".to_string())
+        );
+    }
+
+    #[test]
+    fn loaded_recipes_are_checked() {
+        let result = RecipeLoader::read(
+            br#"(params: (room_count: (base: 4, per_floor: -1)), steps: [(step: "add_room")])"#,
+        );
+
+        assert_eq!(
+            result.map_err(|err| err.to_string()).err(),
+            Some("Invalid recipe: room_count goes below zero".to_string())
+        );
+    }
+
+    #[test]
+    fn rooms_to_count_runs_until_the_count() {
+        let recipe = GenerationRecipe::parse(
+            r#"(
+                params: (room_count: (base: 5)),
+                steps: [(step: "add_room"), (step: "add_rooms_to_count", max_attempts: 1000)],
+            )"#,
+        )
+        .unwrap();
+
+        let generator = recipe.to_generator(&StepRegistry::default()).unwrap();
+
+        assert_eq!(
+            generator.generate_with_seed(2).unwrap().layout.rooms.len(),
+            5
+        );
+    }
+
+    #[test]
+    fn registered_steps_can_be_used() {
+        let mut registry = StepRegistry::empty();
+        registry.register("twice", |state: &DungeonState| {
+            add_room(state).and_then(|state| add_corridor_then_room(&state))
+        });
+        let recipe = GenerationRecipe::parse(r#"(steps: [(step: "twice")])"#).unwrap();
+
+        let generator = recipe.to_generator(&registry).unwrap().seed(1);
+
+        assert!(generator.generate().is_ok());
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+/// How hard a generator step tries before giving up, and what the generator does then.
+///
+/// A step is attempted until it succeeds, runs out of attempts or runs past its time budget.
+/// When a required step gives up and its policy allows backtracking, the generator goes back
+/// `backtrack` steps and replays from there instead of failing, since the layout those steps
+/// built may be what left no room for this one. The replayed steps draw new numbers from the
+/// floor's RNG, so they build something different, and the floor is still the same for the same
+/// seed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Stop retrying once the step has spent this long, even with attempts left. How far a step
+    /// gets in the time depends on the machine, so a step with a budget can give different floors
+    /// for the same seed.
+    pub time_budget: Option<Duration>,
+    /// How many steps to go back when every attempt fails. 0 fails generation straight away.
+    pub backtrack: usize,
+    /// How many times the step may backtrack in one generation, so a step that can never succeed
+    /// still fails in the end.
+    pub max_backtracks: u32,
+}
+
+impl RetryPolicy {
+    /// Tries up to `max_attempts` times and never backtracks.
+    pub const fn attempts(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            time_budget: None,
+            backtrack: 0,
+            max_backtracks: 0,
+        }
+    }
+
+    pub fn time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Goes back `steps` steps when every attempt fails, at most `times` times per generation.
+    pub fn backtrack(mut self, steps: usize, times: u32) -> Self {
+        self.backtrack = steps;
+        self.max_backtracks = times;
+        self
+    }
+
+    /// Whether to make another attempt after `attempts` attempts taking `elapsed` in total. The
+    /// first attempt is always made.
+    pub fn keeps_trying(&self, attempts: u32, elapsed: Duration) -> bool {
+        attempts == 0
+            || (attempts < self.max_attempts
+                && self.time_budget.is_none_or(|budget| elapsed < budget))
+    }
+
+    /// Whether a step that has already backtracked `backtracks` times may do so again.
+    pub fn can_backtrack(&self, backtracks: u32) -> bool {
+        self.backtrack > 0 && backtracks < self.max_backtracks
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::attempts(1)
+    }
+}
+
+#[cfg(test)]
+mod retry_policy_tests {
+    use super::*;
+
+    #[test]
+    fn stops_after_max_attempts() {
+        let policy = RetryPolicy::attempts(3);
+
+        assert!(policy.keeps_trying(2, Duration::ZERO));
+        assert!(!policy.keeps_trying(3, Duration::ZERO));
+    }
+
+    #[test]
+    fn stops_when_out_of_time() {
+        let policy = RetryPolicy::attempts(100).time_budget(Duration::from_millis(10));
+
+        assert!(policy.keeps_trying(5, Duration::from_millis(9)));
+        assert!(!policy.keeps_trying(5, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn always_makes_the_first_attempt() {
+        let policy = RetryPolicy::attempts(0).time_budget(Duration::ZERO);
+
+        assert!(policy.keeps_trying(0, Duration::from_secs(1)));
+        assert!(!policy.keeps_trying(1, Duration::ZERO));
+    }
+
+    #[test]
+    fn backtracks_only_as_often_as_allowed() {
+        assert!(!RetryPolicy::attempts(5).can_backtrack(0));
+
+        let policy = RetryPolicy::attempts(5).backtrack(2, 3);
+        assert!(policy.can_backtrack(2));
+        assert!(!policy.can_backtrack(3));
+    }
+}
@@ -1,7 +1,12 @@
+use crate::dungeon_generation::dungeon_state::DungeonRng;
 use crate::dungeon_generation::room::Orientation::{DOWN, LEFT, RIGHT, UP};
+use crate::dungeon_generation::room_outline::RoomOutline;
+use crate::dungeon_generation::room_roles::RoomRole;
 use bevy::math::IVec2;
+use bevy::utils::HashSet;
+use rand::Rng;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Rectangle {
     pub width: u32,
     pub height: u32,
@@ -13,10 +18,14 @@ pub struct CollisionBox {
     pub position: IVec2,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Room {
+    /// The bounding rectangle, walls included.
     pub shape: Rectangle,
     pub position: IVec2,
+    pub outline: RoomOutline,
+    /// What the room is for, once `assign_room_roles` has run.
+    pub role: Option<RoomRole>,
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -33,13 +42,13 @@ pub struct Line {
     pub end: i32,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct IShape {
     pub orientation: Orientation,
     pub length: u32,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Corridor {
     pub shape: IShape,
     pub position: IVec2,
@@ -51,6 +60,118 @@ impl Line {
     }
 }
 
+impl Room {
+    /// Whether `tile` is part of the room, walls included.
+    pub fn contains(&self, tile: IVec2) -> bool {
+        if self.outline == RoomOutline::Rectangle {
+            return self.to_collision_box().contains(tile);
+        }
+
+        let offset = tile - self.position;
+
+        self.is_floor_at(offset)
+            || NEIGHBOURHOOD
+                .iter()
+                .any(|neighbour| self.is_floor_at(offset + *neighbour))
+    }
+
+    /// The room's floor tiles, ordered by x and then y.
+    pub fn floor_tiles(&self) -> Vec<IVec2> {
+        self.box_tiles()
+            .filter(|tile| self.is_floor_at(*tile - self.position))
+            .collect()
+    }
+
+    /// A random floor tile, or `None` if the room is too small to have any floor.
+    pub fn random_floor_tile(&self, rng: &mut DungeonRng) -> Option<IVec2> {
+        let floor = self.floor_tiles();
+
+        if floor.is_empty() {
+            return None;
+        }
+
+        Some(floor[rng.gen_range(0..floor.len())])
+    }
+
+    /// The tiles walling the floor in, ordered by x and then y.
+    pub fn wall_tiles(&self) -> Vec<IVec2> {
+        self.box_tiles()
+            .filter(|tile| self.contains(*tile) && !self.is_floor_at(*tile - self.position))
+            .collect()
+    }
+
+    /// Wall tiles a corridor heading `orientation` can leave through: floor behind them, nothing
+    /// of the room in front and wall on both sides, so the opening is never on a corner.
+    pub fn wall_openings(&self, orientation: Orientation) -> Vec<IVec2> {
+        let dir: IVec2 = orientation.into();
+        let side = dir.perp();
+        let is_wall = |tile: IVec2| self.contains(tile) && !self.is_floor_at(tile - self.position);
+
+        self.wall_tiles()
+            .into_iter()
+            .filter(|tile| {
+                self.is_floor_at(*tile - dir - self.position)
+                    && !self.contains(*tile + dir)
+                    && is_wall(*tile + side)
+                    && is_wall(*tile - side)
+            })
+            .collect()
+    }
+
+    fn is_floor_at(&self, offset: IVec2) -> bool {
+        self.outline.is_floor(self.shape, offset)
+    }
+
+    fn box_tiles(&self) -> impl Iterator<Item = IVec2> + '_ {
+        (0..self.shape.width as i32).flat_map(move |x| {
+            (0..self.shape.height as i32).map(move |y| self.position + IVec2::new(x, y))
+        })
+    }
+}
+
+const NEIGHBOURHOOD: [IVec2; 8] = [
+    IVec2::new(-1, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(-1, 1),
+    IVec2::new(0, -1),
+    IVec2::new(0, 1),
+    IVec2::new(1, -1),
+    IVec2::new(1, 0),
+    IVec2::new(1, 1),
+];
+
+impl Orientation {
+    pub fn opposite(self) -> Orientation {
+        match self {
+            UP => DOWN,
+            DOWN => UP,
+            LEFT => RIGHT,
+            RIGHT => LEFT,
+        }
+    }
+
+    /// The two orientations at right angles to this one.
+    pub fn perpendicular(self) -> [Orientation; 2] {
+        match self {
+            UP | DOWN => [LEFT, RIGHT],
+            LEFT | RIGHT => [UP, DOWN],
+        }
+    }
+}
+
+impl Corridor {
+    /// The last floor tile of the corridor, opposite `position`.
+    pub fn end(&self) -> IVec2 {
+        self.position + IVec2::from(self.shape.orientation) * (self.shape.length as i32 - 1)
+    }
+
+    /// Whether `tile` is one of the corridor's floor tiles.
+    pub fn contains(&self, tile: IVec2) -> bool {
+        let dir = IVec2::from(self.shape.orientation);
+        (0..self.shape.length as i32).any(|step| self.position + dir * step == tile)
+    }
+}
+
 pub trait Collision {
     fn collides_with(&self, rhs: &impl Collision) -> bool {
         let left_box = self.to_collision_box();
@@ -79,10 +200,38 @@ pub trait Collision {
         let x_line_intersect = lines_intersect(&left_x_line, &right_x_line);
         let y_line_intersect = lines_intersect(&left_y_line, &right_y_line);
 
-        return x_line_intersect && y_line_intersect;
+        if !(x_line_intersect && y_line_intersect) {
+            return false;
+        }
+
+        match (self.collision_mask(), rhs.collision_mask()) {
+            (None, None) => true,
+            (Some(mask), None) => mask.iter().any(|tile| right_box.contains(*tile)),
+            (None, Some(mask)) => mask.iter().any(|tile| left_box.contains(*tile)),
+            (Some(left_mask), Some(right_mask)) => {
+                let right_mask: HashSet<IVec2> = right_mask.into_iter().collect();
+                left_mask.iter().any(|tile| right_mask.contains(tile))
+            }
+        }
     }
 
     fn to_collision_box(&self) -> CollisionBox;
+
+    /// The tiles actually taken up, for shapes that do not fill their collision box.
+    fn collision_mask(&self) -> Option<Vec<IVec2>> {
+        None
+    }
+}
+
+impl CollisionBox {
+    pub fn contains(&self, tile: IVec2) -> bool {
+        let offset = tile - self.position;
+
+        offset.x >= 0
+            && offset.y >= 0
+            && offset.x < self.shape.width as i32
+            && offset.y < self.shape.height as i32
+    }
 }
 
 fn lines_intersect(lhs: &Line, rhs: &Line) -> bool {
@@ -98,6 +247,17 @@ impl Collision for Room {
             position: self.position,
         }
     }
+
+    fn collision_mask(&self) -> Option<Vec<IVec2>> {
+        match self.outline {
+            RoomOutline::Rectangle => None,
+            _ => Some(
+                self.box_tiles()
+                    .filter(|tile| self.contains(*tile))
+                    .collect(),
+            ),
+        }
+    }
 }
 
 impl Collision for Corridor {
@@ -123,9 +283,9 @@ impl Collision for Corridor {
 
         let flipped_position = match self.shape.orientation {
             UP => self.position,
-            DOWN => (self.shape.length as i32 - 1) * direction,
+            DOWN => self.position + (self.shape.length as i32 - 1) * direction,
             RIGHT => self.position,
-            LEFT => (self.shape.length as i32 - 1) * direction,
+            LEFT => self.position + (self.shape.length as i32 - 1) * direction,
         };
 
         let dir: IVec2 = orientation.clone().into();
@@ -230,6 +390,8 @@ mod collision_tests {
                 height: 10,
             },
             position: IVec2::new(0, 0),
+            outline: RoomOutline::Rectangle,
+            role: None,
         };
 
         assert_eq!(room.collides_with(&room), true)
@@ -243,6 +405,8 @@ mod collision_tests {
                 height: 5,
             },
             position: IVec2::new(0, 0),
+            outline: RoomOutline::Rectangle,
+            role: None,
         };
 
         let rhs = Room {
@@ -251,6 +415,8 @@ mod collision_tests {
                 height: 5,
             },
             position: IVec2::new(10, 10),
+            outline: RoomOutline::Rectangle,
+            role: None,
         };
 
         assert_eq!(lhs.collides_with(&rhs), false)
@@ -417,6 +583,32 @@ mod collision_tests {
         assert_eq!(lhs.collides_with(&rhs), true);
     }
 
+    #[test]
+    fn reversed_corridor_has_the_same_collision_box() {
+        let right = Corridor {
+            shape: IShape {
+                length: 6,
+                orientation: RIGHT,
+            },
+            position: IVec2::new(10, 4),
+        };
+
+        let left = Corridor {
+            shape: IShape {
+                length: 6,
+                orientation: LEFT,
+            },
+            position: right.end(),
+        };
+
+        let right_box = right.to_collision_box();
+        let left_box = left.to_collision_box();
+
+        assert_eq!(left_box.position, right_box.position);
+        assert_eq!(left_box.shape, right_box.shape);
+        assert_eq!(left_box.position, IVec2::new(11, 3));
+    }
+
     #[test]
     fn intersecting_room_and_corridor_collide() {
         let corridor = Corridor {
@@ -433,6 +625,8 @@ mod collision_tests {
                 height: 10,
             },
             position: IVec2::new(0, 0),
+            outline: RoomOutline::Rectangle,
+            role: None,
         };
 
         assert_eq!(corridor.collides_with(&room), true);
@@ -454,8 +648,85 @@ mod collision_tests {
                 height: 5,
             },
             position: IVec2::new(0, 0),
+            outline: RoomOutline::Rectangle,
+            role: None,
         };
 
         assert_eq!(corridor.collides_with(&room), false);
     }
+
+    #[test]
+    fn rectangle_openings_skip_the_corners() {
+        let room = Room {
+            shape: Rectangle {
+                width: 5,
+                height: 6,
+            },
+            position: IVec2::new(10, 0),
+            outline: RoomOutline::Rectangle,
+            role: None,
+        };
+
+        assert_eq!(
+            room.wall_openings(RIGHT),
+            vec![
+                IVec2::new(14, 1),
+                IVec2::new(14, 2),
+                IVec2::new(14, 3),
+                IVec2::new(14, 4)
+            ]
+        );
+        assert_eq!(room.wall_openings(DOWN).len(), 3);
+    }
+
+    #[test]
+    fn circle_in_the_corner_of_a_box_does_not_collide() {
+        let circle = Room {
+            shape: Rectangle {
+                width: 9,
+                height: 9,
+            },
+            position: IVec2::new(0, 0),
+            outline: RoomOutline::Circle,
+            role: None,
+        };
+        let corridor = Corridor {
+            shape: IShape {
+                length: 4,
+                orientation: UP,
+            },
+            position: IVec2::new(0, 7),
+        };
+
+        assert!(!circle.contains(IVec2::new(0, 0)));
+        assert!(circle.to_collision_box().contains(IVec2::new(0, 8)));
+        assert!(!circle.collides_with(&corridor));
+        assert!(circle.collides_with(&circle.clone()));
+    }
+
+    #[test]
+    fn circle_walls_surround_its_floor() {
+        let circle = Room {
+            shape: Rectangle {
+                width: 9,
+                height: 7,
+            },
+            position: IVec2::new(3, 3),
+            outline: RoomOutline::Circle,
+            role: None,
+        };
+
+        let floor = circle.floor_tiles();
+        let walls = circle.wall_tiles();
+
+        for tile in &floor {
+            for neighbour in NEIGHBOURHOOD {
+                let next = *tile + neighbour;
+                assert!(floor.contains(&next) || walls.contains(&next));
+            }
+        }
+        for orientation in [UP, DOWN, LEFT, RIGHT] {
+            assert!(!circle.wall_openings(orientation).is_empty());
+        }
+    }
 }
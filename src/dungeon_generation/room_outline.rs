@@ -0,0 +1,168 @@
+use crate::dungeon_generation::cave::{grid, largest_area, smooth};
+use crate::dungeon_generation::dungeon_state::DungeonRng;
+use crate::dungeon_generation::room::Rectangle;
+use bevy::math::IVec2;
+use rand::Rng;
+
+/// Which tiles of a room's bounding `Rectangle` are floor. Walls are never part of the outline:
+/// they are drawn on every tile around the floor, so the outer ring of the rectangle is always
+/// left for them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RoomOutline {
+    #[default]
+    Rectangle,
+    /// The largest ellipse that fits the rectangle.
+    Circle,
+    /// A plus sign with arms a third of the rectangle wide.
+    Cross,
+    /// The rectangle with its top right quarter cut away.
+    L,
+    /// Any floor plan, one entry per tile of the rectangle, row by row from the bottom.
+    Mask(Vec<bool>),
+}
+
+/// Caves covering less than this share of the circle they are carved from fall back to it, so
+/// a cave never floats small in a large bounding box.
+const MIN_CAVE_SHARE: f32 = 0.5;
+
+impl RoomOutline {
+    /// Whether the tile at `offset` from the bottom left corner of `shape` is floor.
+    pub fn is_floor(&self, shape: Rectangle, offset: IVec2) -> bool {
+        let (width, height) = (shape.width as i32, shape.height as i32);
+
+        if offset.x < 1 || offset.y < 1 || offset.x >= width - 1 || offset.y >= height - 1 {
+            return false;
+        }
+
+        match self {
+            RoomOutline::Rectangle => true,
+            RoomOutline::Circle => {
+                let radius_x = (width - 2) as f32 / 2.0;
+                let radius_y = (height - 2) as f32 / 2.0;
+                let dx = (offset.x as f32 - 0.5) - radius_x;
+                let dy = (offset.y as f32 - 0.5) - radius_y;
+
+                (dx / radius_x).powi(2) + (dy / radius_y).powi(2) <= 1.0
+            }
+            RoomOutline::Cross => {
+                let (inner_x, inner_y) = (offset.x - 1, offset.y - 1);
+                let (floor_width, floor_height) = (width - 2, height - 2);
+                let arm_x = floor_width / 3;
+                let arm_y = floor_height / 3;
+
+                (inner_x >= arm_x && inner_x < floor_width - arm_x)
+                    || (inner_y >= arm_y && inner_y < floor_height - arm_y)
+            }
+            RoomOutline::L => offset.x < width / 2 || offset.y < height / 2,
+            RoomOutline::Mask(mask) => mask
+                .get((offset.y * width + offset.x) as usize)
+                .copied()
+                .unwrap_or(false),
+        }
+    }
+
+    /// A random outline for a new room, rectangles being the most common.
+    pub fn random(shape: Rectangle, rng: &mut DungeonRng) -> RoomOutline {
+        match rng.gen_range(0..10) {
+            0..=4 => RoomOutline::Rectangle,
+            5 | 6 => RoomOutline::Circle,
+            7 => RoomOutline::Cross,
+            8 => RoomOutline::L,
+            _ => RoomOutline::cave(shape, rng),
+        }
+    }
+
+    /// An irregular blob: random floor inside the ellipse of `shape`, smoothed with a few rounds
+    /// of cellular automaton and trimmed to its largest connected area.
+    pub fn cave(shape: Rectangle, rng: &mut DungeonRng) -> RoomOutline {
+        let (width, height) = (shape.width as i32, shape.height as i32);
+        let in_circle = |offset: IVec2| RoomOutline::Circle.is_floor(shape, offset);
+
+        let mut mask: Vec<bool> = grid(width, height)
+            .map(|offset| in_circle(offset) && rng.gen_bool(0.6))
+            .collect();
+
+        for _ in 0..3 {
+            mask = smooth(&mask, width, height, in_circle);
+        }
+
+        let circle_floor = grid(width, height)
+            .filter(|offset| in_circle(*offset))
+            .count();
+
+        let largest = largest_area(&mask, width, height);
+        if (largest.len() as f32) < circle_floor as f32 * MIN_CAVE_SHARE {
+            return RoomOutline::Circle;
+        }
+
+        let mut trimmed = vec![false; mask.len()];
+        for cell in largest {
+            trimmed[cell] = true;
+        }
+
+        RoomOutline::Mask(trimmed)
+    }
+}
+
+#[cfg(test)]
+mod room_outline_tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    const SHAPE: Rectangle = Rectangle {
+        width: 11,
+        height: 9,
+    };
+
+    fn floor(outline: &RoomOutline) -> Vec<IVec2> {
+        (0..SHAPE.height as i32)
+            .flat_map(|y| (0..SHAPE.width as i32).map(move |x| IVec2::new(x, y)))
+            .filter(|offset| outline.is_floor(SHAPE, *offset))
+            .collect()
+    }
+
+    #[test]
+    fn border_is_never_floor() {
+        for outline in [
+            RoomOutline::Rectangle,
+            RoomOutline::Circle,
+            RoomOutline::Cross,
+            RoomOutline::L,
+        ] {
+            assert!(floor(&outline).iter().all(|tile| tile.x > 0
+                && tile.y > 0
+                && tile.x < SHAPE.width as i32 - 1
+                && tile.y < SHAPE.height as i32 - 1));
+        }
+    }
+
+    #[test]
+    fn shapes_cut_corners_off_the_rectangle() {
+        let corner = IVec2::new(1, 1);
+
+        assert!(RoomOutline::Rectangle.is_floor(SHAPE, corner));
+        assert!(!RoomOutline::Circle.is_floor(SHAPE, corner));
+        assert!(!RoomOutline::Cross.is_floor(SHAPE, corner));
+        assert!(RoomOutline::L.is_floor(SHAPE, corner));
+        assert!(!RoomOutline::L.is_floor(SHAPE, IVec2::new(9, 7)));
+        assert!(floor(&RoomOutline::Circle).len() < floor(&RoomOutline::Rectangle).len());
+    }
+
+    #[test]
+    fn cave_floor_is_one_connected_area() {
+        for seed in 0..20 {
+            let cave = RoomOutline::cave(SHAPE, &mut DungeonRng::seed_from_u64(seed));
+            let mask: Vec<bool> = grid(SHAPE.width as i32, SHAPE.height as i32)
+                .map(|offset| cave.is_floor(SHAPE, offset))
+                .collect();
+
+            let floor_count = mask.iter().filter(|floor| **floor).count();
+            assert!(floor_count > 0, "seed {seed}");
+            assert_eq!(
+                largest_area(&mask, SHAPE.width as i32, SHAPE.height as i32).len(),
+                floor_count,
+                "seed {seed}"
+            );
+        }
+    }
+}
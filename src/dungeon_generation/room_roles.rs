@@ -0,0 +1,221 @@
+use crate::dungeon_generation::dungeon_state::{DungeonLayout, DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::spatial_index::LayoutPart;
+use crate::dungeon_generation::spawn_generation::SpawnType;
+use bevy::math::IVec2;
+use std::cmp::Reverse;
+
+/// What a room is for, given by `assign_room_roles`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomRole {
+    /// The room the player starts in.
+    Start,
+    /// A dead end off the way to the exit, worth a detour.
+    Treasure,
+    /// The largest room in the far half of the floor.
+    Boss,
+    /// The dead end farthest from the start.
+    Exit,
+}
+
+impl DungeonLayout {
+    /// The room whose floor holds `tile`.
+    pub fn room_at(&self, tile: IVec2) -> Option<usize> {
+        self.parts_at(tile).into_iter().find_map(|part| match part {
+            LayoutPart::Room(room) => Some(room),
+            LayoutPart::Corridor(_) => None,
+        })
+    }
+
+    /// The rooms given `role`, in ascending order.
+    pub fn rooms_with_role(&self, role: RoomRole) -> Vec<usize> {
+        (0..self.rooms.len())
+            .filter(|&room| self.rooms[room].role == Some(role))
+            .collect()
+    }
+}
+
+/// Generator step that gives rooms their roles by how many rooms lie between them and the
+/// player: the player's room is the start, the farthest dead end the exit, the largest room at
+/// least half way to the farthest room the boss room, and the remaining dead ends hold treasure.
+/// Roles from an earlier run of the step are cleared first, so run it again after adding rooms.
+pub fn assign_room_roles(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let player = state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .ok_or(StepError::NoRoom)?;
+
+    let start = state
+        .layout
+        .room_at(player.position)
+        .ok_or(StepError::NoRoom)?;
+
+    let graph = state.layout.graph();
+    let distances = graph.distances_from(start);
+    let distance = |room: usize| distances[room].unwrap_or(0);
+
+    let reachable: Vec<usize> = (0..graph.room_count())
+        .filter(|&room| room != start && distances[room].is_some())
+        .collect();
+
+    let dead_ends: Vec<usize> = graph
+        .leaf_rooms()
+        .into_iter()
+        .filter(|room| reachable.contains(room))
+        .collect();
+
+    // Ties go to the lowest index.
+    let farthest = |rooms: &[usize]| {
+        rooms
+            .iter()
+            .copied()
+            .max_by_key(|&room| (distance(room), Reverse(room)))
+    };
+    let exit = farthest(&dead_ends).or_else(|| farthest(&reachable));
+
+    let max_distance = distances.iter().flatten().copied().max().unwrap_or(0);
+    let boss = reachable
+        .iter()
+        .copied()
+        .filter(|&room| Some(room) != exit && distance(room) * 2 >= max_distance)
+        .max_by_key(|&room| (state.layout.rooms[room].floor_tiles().len(), Reverse(room)));
+
+    let mut layout = state.layout.clone();
+    for (index, room) in layout.rooms.iter_mut().enumerate() {
+        room.role = if index == start {
+            Some(RoomRole::Start)
+        } else if Some(index) == exit {
+            Some(RoomRole::Exit)
+        } else if Some(index) == boss {
+            Some(RoomRole::Boss)
+        } else if dead_ends.contains(&index) {
+            Some(RoomRole::Treasure)
+        } else {
+            None
+        };
+    }
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .layout(layout)
+        .build())
+}
+
+#[cfg(test)]
+mod room_roles_tests {
+    use super::*;
+    use crate::dungeon_generation::key::add_key;
+    use crate::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+    use crate::dungeon_generation::room::Orientation::{RIGHT, UP};
+    use crate::dungeon_generation::room::{Corridor, IShape, Rectangle, Room};
+    use crate::dungeon_generation::room_outline::RoomOutline;
+    use crate::dungeon_generation::spawn_generation::Spawn;
+    use im::vector;
+
+    fn room(x: i32, y: i32, size: u32) -> Room {
+        Room {
+            shape: Rectangle {
+                width: size,
+                height: size,
+            },
+            position: IVec2::new(x, y),
+            outline: RoomOutline::Rectangle,
+            role: None,
+        }
+    }
+
+    /// A row of four rooms with a big room and a small room off the second, and the player in the
+    /// first:
+    ///
+    /// ```text
+    ///      4
+    ///      |
+    /// 0 -- 1 -- 2 -- 3
+    ///      |
+    ///      5
+    /// ```
+    fn branching_floor() -> DungeonState {
+        let rooms = vector![
+            room(0, 0, 5),
+            room(10, 0, 5),
+            room(20, 0, 5),
+            room(30, 0, 5),
+            room(8, 10, 9),
+            room(10, -10, 5),
+        ];
+
+        let corridor = |x, y, orientation, length| Corridor {
+            shape: IShape {
+                orientation,
+                length,
+            },
+            position: IVec2::new(x, y),
+        };
+
+        let corridors = vector![
+            corridor(4, 2, RIGHT, 7),
+            corridor(14, 2, RIGHT, 7),
+            corridor(24, 2, RIGHT, 7),
+            corridor(12, 4, UP, 7),
+            corridor(12, -6, UP, 7),
+        ];
+
+        DungeonStateBuilder::default()
+            .layout(DungeonLayout::new(rooms, corridors))
+            .spawns(vec![Spawn {
+                position: IVec2::new(2, 2),
+                spawn_type: SpawnType::Player,
+            }])
+            .build()
+    }
+
+    #[test]
+    fn roles_follow_distance_from_the_player() {
+        let layout = assign_room_roles(&branching_floor()).unwrap().layout;
+
+        assert_eq!(layout.rooms_with_role(RoomRole::Start), vec![0]);
+        assert_eq!(layout.rooms_with_role(RoomRole::Exit), vec![3]);
+        assert_eq!(layout.rooms_with_role(RoomRole::Boss), vec![4]);
+        assert_eq!(layout.rooms_with_role(RoomRole::Treasure), vec![5]);
+        assert_eq!(layout.rooms[1].role, None);
+    }
+
+    #[test]
+    fn needs_a_player() {
+        let mut state = branching_floor();
+        state.spawns.clear();
+
+        assert_eq!(assign_room_roles(&state).unwrap_err(), StepError::NoRoom);
+    }
+
+    #[test]
+    fn keys_go_in_treasure_rooms() {
+        let state = assign_room_roles(&branching_floor()).unwrap();
+        let treasure = &state.layout.rooms[5];
+
+        for _ in 0..10 {
+            let with_key = add_key(&state).unwrap();
+            assert!(treasure.contains(with_key.spawns.last().unwrap().position));
+        }
+    }
+
+    #[test]
+    fn default_recipe_has_a_start_and_an_exit() {
+        for seed in 0..10 {
+            let dungeon = GenerationRecipe::default()
+                .to_generator(&StepRegistry::default())
+                .unwrap()
+                .seed(seed)
+                .generate()
+                .unwrap();
+
+            let layout = &dungeon.layout;
+            assert_eq!(layout.rooms_with_role(RoomRole::Start).len(), 1);
+            assert_eq!(
+                layout.rooms_with_role(RoomRole::Exit).len(),
+                1,
+                "seed {seed}"
+            );
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::dungeon_generation::constraints::{player_of, stairs_down_of};
+use crate::dungeon_generation::dungeon_state::DungeonState;
+use crate::dungeon_generation::solvability::walking_distances;
+use crate::dungeon_generation::tile_map::{get_tile_map, TileType};
+use serde::Deserialize;
+
+/// Which of the functions below a recipe rates its floors by when it keeps the best of several.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum Score {
+    #[default]
+    Compactness,
+    Loops,
+    PathLength,
+}
+
+impl Score {
+    pub fn rate(&self, state: &DungeonState) -> f64 {
+        match self {
+            Score::Compactness => compactness(state),
+            Score::Loops => loop_count(state),
+            Score::PathLength => path_length(state),
+        }
+    }
+}
+
+/// The share of the floor's bounding box taken up by floor tiles, from 0 for an empty floor to 1
+/// for a single rectangle of floor. Prefers tightly packed floors over sprawling ones.
+pub fn compactness(state: &DungeonState) -> f64 {
+    let size = state.layout.size();
+    let area = size.x as f64 * size.y as f64;
+    if area <= 0.0 {
+        return 0.0;
+    }
+
+    let floor = get_tile_map(&state.layout)
+        .tiles()
+        .filter(|(_, tile_type)| *tile_type == TileType::Floor)
+        .count();
+
+    floor as f64 / area
+}
+
+/// How many separate loops the corridors make, so the player has more than one way around.
+pub fn loop_count(state: &DungeonState) -> f64 {
+    state.layout.graph().loop_count() as f64
+}
+
+/// How many steps the player has to walk from their spawn to the stairs down, through doors as
+/// if they were open, or 0 if either is missing or the stairs cannot be reached.
+pub fn path_length(state: &DungeonState) -> f64 {
+    let (player, stairs) = match (player_of(state), stairs_down_of(state)) {
+        (Some(player), Some(stairs)) => (player, stairs),
+        _ => return 0.0,
+    };
+
+    walking_distances(&get_tile_map(&state.layout), player)
+        .get(&stairs)
+        .map_or(0.0, |&distance| distance as f64)
+}
+
+#[cfg(test)]
+mod scoring_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::fallback_dungeon;
+    use crate::dungeon_generation::dungeon_state::DungeonStateBuilder;
+    use crate::dungeon_generation::fixtures::ring_of_rooms;
+    use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+    use crate::dungeon_generation::stairs::Stairs;
+    use bevy::math::IVec2;
+
+    /// Four rooms in a square joined into a ring, with the player in the bottom left room and
+    /// the stairs down in the bottom right one.
+    fn ring() -> DungeonState {
+        DungeonStateBuilder::default()
+            .layout(ring_of_rooms())
+            .spawns(vec![
+                Spawn {
+                    position: IVec2::new(2, 2),
+                    spawn_type: SpawnType::Player,
+                },
+                Spawn {
+                    position: IVec2::new(12, 2),
+                    spawn_type: SpawnType::Stairs(Stairs::Down),
+                },
+            ])
+            .build()
+    }
+
+    #[test]
+    fn a_single_room_is_as_compact_as_its_walls_allow() {
+        assert_eq!(compactness(&fallback_dungeon(0)), 100.0 / 144.0);
+        assert!(compactness(&ring()) < compactness(&fallback_dungeon(0)));
+        assert_eq!(compactness(&DungeonStateBuilder::default().build()), 0.0);
+    }
+
+    #[test]
+    fn counts_loops() {
+        assert_eq!(loop_count(&ring()), 1.0);
+        assert_eq!(loop_count(&fallback_dungeon(0)), 0.0);
+    }
+
+    #[test]
+    fn measures_the_walk_to_the_stairs() {
+        assert_eq!(path_length(&ring()), 10.0);
+        assert_eq!(path_length(&fallback_dungeon(0)), 3.0);
+    }
+}
@@ -0,0 +1,344 @@
+use crate::dungeon_generation::dungeon_state::{DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::key::KeyColour;
+use crate::dungeon_generation::spawn_generation::SpawnType;
+use crate::dungeon_generation::tile_map::{get_tile_map, TileMap, TileType};
+use bevy::math::IVec2;
+use bevy::utils::{HashMap, HashSet};
+use rand::Rng;
+use std::collections::VecDeque;
+
+const NEIGHBOURS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// Why the player cannot finish a floor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Unsolvable {
+    NoPlayerSpawn,
+    /// Keys the player can never pick up.
+    UnreachableKeys(Vec<IVec2>),
+    /// Doors the player can never open, because they are unreachable or there are not enough
+    /// reachable keys of their colour.
+    LockedDoors(Vec<IVec2>),
+}
+
+/// Plays the floor out from the player spawn: flood fill over floor tiles, pick up every key in
+/// reach, spend a key on a door of the same colour next to the filled area and repeat until
+/// stuck. The floor is solvable when every key gets picked up and every door opened.
+pub fn check_solvability(state: &DungeonState) -> Result<(), Unsolvable> {
+    let player = state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .ok_or(Unsolvable::NoPlayerSpawn)?;
+
+    let tile_map = get_tile_map(&state.layout);
+    let mut keys = keys_of(state);
+    let mut locked_doors: HashMap<IVec2, KeyColour> = doors_of(state).into_iter().collect();
+    let mut keys_held: HashMap<KeyColour, usize> = HashMap::default();
+
+    loop {
+        let blocked: HashSet<IVec2> = locked_doors.keys().copied().collect();
+        let reachable = flood_fill(&tile_map, player.position, &blocked);
+
+        keys.retain(|(key, colour)| {
+            if reachable.contains(key) {
+                *keys_held.entry(*colour).or_default() += 1;
+                false
+            } else {
+                true
+            }
+        });
+
+        let mut openable: Vec<IVec2> = locked_doors
+            .iter()
+            .filter(|(_, colour)| keys_held.get(colour).copied().unwrap_or(0) > 0)
+            .map(|(door, _)| *door)
+            .filter(|door| NEIGHBOURS.iter().any(|n| reachable.contains(&(*door + *n))))
+            .collect();
+        sort_tiles(&mut openable);
+
+        match openable.first() {
+            Some(door) => {
+                if let Some(colour) = locked_doors.remove(door) {
+                    *keys_held.entry(colour).or_default() -= 1;
+                }
+            }
+            None => break,
+        }
+    }
+
+    if !keys.is_empty() {
+        let mut keys: Vec<IVec2> = keys.into_iter().map(|(key, _)| key).collect();
+        sort_tiles(&mut keys);
+        return Err(Unsolvable::UnreachableKeys(keys));
+    }
+
+    if !locked_doors.is_empty() {
+        let mut doors: Vec<IVec2> = locked_doors.into_keys().collect();
+        sort_tiles(&mut doors);
+        return Err(Unsolvable::LockedDoors(doors));
+    }
+
+    Ok(())
+}
+
+pub fn is_solvable(state: &DungeonState) -> bool {
+    check_solvability(state).is_ok()
+}
+
+/// Generator step that leaves solvable floors alone and moves keys locked behind their own door
+/// onto floor the player can reach without opening any door.
+pub fn ensure_solvable(state: &DungeonState) -> Result<DungeonState, StepError> {
+    match check_solvability(state) {
+        Ok(()) => return Ok(DungeonStateBuilder::from_state(state).build()),
+        Err(Unsolvable::NoPlayerSpawn) => return Err(StepError::Unsolvable),
+        Err(_) => {}
+    }
+
+    let player = state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .map(|spawn| spawn.position)
+        .ok_or(StepError::Unsolvable)?;
+
+    let tile_map = get_tile_map(&state.layout);
+    let doors: HashSet<IVec2> = doors_of(state).into_iter().map(|(door, _)| door).collect();
+    let occupied: HashSet<IVec2> = state.spawns.iter().map(|spawn| spawn.position).collect();
+
+    let mut free_tiles: Vec<IVec2> = flood_fill(&tile_map, player, &doors)
+        .into_iter()
+        .filter(|tile| !occupied.contains(tile))
+        .collect();
+    sort_tiles(&mut free_tiles);
+
+    let mut spawns = state.spawns.clone();
+    let mut rng = state.rng.lock().unwrap();
+
+    for spawn in spawns
+        .iter_mut()
+        .filter(|spawn| matches!(spawn.spawn_type, SpawnType::Key(_)))
+    {
+        if free_tiles.is_empty() {
+            return Err(StepError::Unsolvable);
+        }
+
+        spawn.position = free_tiles.swap_remove(rng.gen_range(0..free_tiles.len()));
+    }
+    drop(rng);
+
+    let repaired = DungeonStateBuilder::from_state(state)
+        .spawns(spawns)
+        .build();
+
+    if is_solvable(&repaired) {
+        Ok(repaired)
+    } else {
+        Err(StepError::Unsolvable)
+    }
+}
+
+/// Every floor tile reachable from `start` by walking up, down, left and right without stepping
+/// on a `blocked` tile.
+pub fn flood_fill(tile_map: &TileMap, start: IVec2, blocked: &HashSet<IVec2>) -> HashSet<IVec2> {
+    let mut reached = HashSet::default();
+
+    if tile_map.get(start) != TileType::Floor || blocked.contains(&start) {
+        return reached;
+    }
+
+    let mut queue = VecDeque::from([start]);
+    reached.insert(start);
+
+    while let Some(tile) = queue.pop_front() {
+        for offset in NEIGHBOURS {
+            let next = tile + offset;
+
+            if tile_map.get(next) == TileType::Floor
+                && !blocked.contains(&next)
+                && reached.insert(next)
+            {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    reached
+}
+
+/// How many steps it takes to walk from `start` to every floor tile reachable from it, going
+/// through doors as if they were open.
+pub fn walking_distances(tile_map: &TileMap, start: IVec2) -> HashMap<IVec2, usize> {
+    let mut distances = HashMap::default();
+
+    if tile_map.get(start) != TileType::Floor {
+        return distances;
+    }
+
+    let mut queue = VecDeque::from([start]);
+    distances.insert(start, 0);
+
+    while let Some(tile) = queue.pop_front() {
+        let distance = distances[&tile];
+
+        for offset in NEIGHBOURS {
+            let next = tile + offset;
+
+            if tile_map.get(next) == TileType::Floor && !distances.contains_key(&next) {
+                distances.insert(next, distance + 1);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    distances
+}
+
+pub(crate) fn keys_of(state: &DungeonState) -> Vec<(IVec2, KeyColour)> {
+    state
+        .spawns
+        .iter()
+        .filter_map(|spawn| match spawn.spawn_type {
+            SpawnType::Key(colour) => Some((spawn.position, colour)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Door tiles and colours, in the order the generator placed them.
+pub(crate) fn doors_of(state: &DungeonState) -> Vec<(IVec2, KeyColour)> {
+    state
+        .spawns
+        .iter()
+        .filter_map(|spawn| match spawn.spawn_type {
+            SpawnType::Door(colour) => Some((spawn.position, colour)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Tile sets are hashed with a per-process seed, so sort before letting the RNG pick one.
+pub(crate) fn sort_tiles(tiles: &mut [IVec2]) {
+    tiles.sort_unstable_by_key(|tile| (tile.x, tile.y));
+}
+
+#[cfg(test)]
+mod solvability_tests {
+    use super::*;
+    use crate::dungeon_generation::fixtures::row_of_rooms;
+    use crate::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+    use crate::dungeon_generation::spawn_generation::Spawn;
+
+    fn spawn(x: i32, y: i32, spawn_type: SpawnType) -> Spawn {
+        Spawn {
+            position: IVec2::new(x, y),
+            spawn_type,
+        }
+    }
+
+    /// Three rooms in a row with the player in the first and a door in the second corridor.
+    fn dungeon_with_key_at(key_x: i32) -> DungeonState {
+        DungeonStateBuilder::default()
+            .layout(row_of_rooms(3))
+            .spawns(vec![
+                spawn(2, 2, SpawnType::Player),
+                spawn(key_x, 2, SpawnType::Key(KeyColour::Gold)),
+                spawn(17, 2, SpawnType::Door(KeyColour::Gold)),
+            ])
+            .build()
+    }
+
+    #[test]
+    fn key_before_door_is_solvable() {
+        assert_eq!(check_solvability(&dungeon_with_key_at(12)), Ok(()));
+    }
+
+    #[test]
+    fn key_behind_its_door_is_not_solvable() {
+        assert_eq!(
+            check_solvability(&dungeon_with_key_at(22)),
+            Err(Unsolvable::UnreachableKeys(vec![IVec2::new(22, 2)]))
+        );
+    }
+
+    #[test]
+    fn door_without_key_is_locked() {
+        let mut dungeon = dungeon_with_key_at(12);
+        dungeon
+            .spawns
+            .retain(|spawn| !matches!(spawn.spawn_type, SpawnType::Key(_)));
+
+        assert_eq!(
+            check_solvability(&dungeon),
+            Err(Unsolvable::LockedDoors(vec![IVec2::new(17, 2)]))
+        );
+    }
+
+    #[test]
+    fn key_of_another_colour_does_not_open_door() {
+        let mut dungeon = dungeon_with_key_at(12);
+        dungeon.spawns[1].spawn_type = SpawnType::Key(KeyColour::Red);
+
+        assert_eq!(
+            check_solvability(&dungeon),
+            Err(Unsolvable::LockedDoors(vec![IVec2::new(17, 2)]))
+        );
+    }
+
+    #[test]
+    fn missing_player_is_not_solvable() {
+        let mut dungeon = dungeon_with_key_at(12);
+        dungeon.spawns.remove(0);
+
+        assert_eq!(check_solvability(&dungeon), Err(Unsolvable::NoPlayerSpawn));
+    }
+
+    #[test]
+    fn ensure_solvable_moves_key_in_front_of_door() {
+        let repaired = ensure_solvable(&dungeon_with_key_at(22)).unwrap();
+
+        let key = repaired
+            .spawns
+            .iter()
+            .find(|spawn| matches!(spawn.spawn_type, SpawnType::Key(_)))
+            .unwrap();
+
+        assert!(key.position.x < 17);
+        assert!(is_solvable(&repaired));
+    }
+
+    #[test]
+    fn ensure_solvable_keeps_solvable_floor() {
+        let dungeon = dungeon_with_key_at(12);
+
+        assert_eq!(ensure_solvable(&dungeon).unwrap().spawns, dungeon.spawns);
+    }
+
+    #[test]
+    fn flood_fill_stops_at_blocked_tiles() {
+        let dungeon = dungeon_with_key_at(12);
+        let tile_map = get_tile_map(&dungeon.layout);
+        let blocked = HashSet::from_iter([IVec2::new(17, 2)]);
+
+        let reached = flood_fill(&tile_map, IVec2::new(2, 2), &blocked);
+
+        assert!(reached.contains(&IVec2::new(16, 2)));
+        assert!(!reached.contains(&IVec2::new(17, 2)));
+        assert!(!reached.contains(&IVec2::new(22, 2)));
+    }
+
+    #[test]
+    fn default_recipe_produces_solvable_floors() {
+        for seed in 0..30 {
+            let generator = GenerationRecipe::default()
+                .to_generator(&StepRegistry::default())
+                .unwrap()
+                .seed(seed);
+
+            let dungeon = generator
+                .generate()
+                .unwrap_or_else(|error| panic!("seed {seed}: {error}"));
+            assert_eq!(check_solvability(&dungeon), Ok(()), "seed {seed}");
+        }
+    }
+}
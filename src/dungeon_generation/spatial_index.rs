@@ -0,0 +1,267 @@
+use crate::dungeon_generation::dungeon_state::DungeonLayout;
+use crate::dungeon_generation::room::{Collision, CollisionBox, Corridor, Room};
+use bevy::math::IVec2;
+use im::{HashMap, Vector};
+
+/// Width and height of a grid cell, in tiles. About the size of a room, so most rooms are listed
+/// in a handful of cells.
+const CELL_SIZE: i32 = 16;
+
+/// A room or corridor of a layout, by its index in `rooms` or `corridors`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LayoutPart {
+    Room(usize),
+    Corridor(usize),
+}
+
+/// Which rooms and corridors lie where, so collision checks and tile lookups only look at the
+/// parts of a layout close by instead of every room and corridor on the floor.
+///
+/// The floor is cut into a grid of square cells and every part is listed in each cell it reaches
+/// into. Lookups return every part listed near what was asked about, which may include parts
+/// that turn out not to touch it, so callers still check each one. The grid is a persistent map
+/// and keeps the rooms and corridors it indexes, so copying it is as cheap as copying the layout.
+#[derive(Clone, Debug, Default)]
+pub struct SpatialIndex {
+    cells: HashMap<IVec2, Vector<LayoutPart>>,
+    rooms: Vector<Room>,
+    corridors: Vector<Corridor>,
+}
+
+impl SpatialIndex {
+    pub fn new(rooms: &Vector<Room>, corridors: &Vector<Corridor>) -> SpatialIndex {
+        let mut index = SpatialIndex::default();
+
+        for (i, room) in rooms.iter().enumerate() {
+            index.insert(LayoutPart::Room(i), room_bounds(room));
+        }
+        for (i, corridor) in corridors.iter().enumerate() {
+            index.insert(LayoutPart::Corridor(i), corridor_bounds(corridor));
+        }
+
+        index.rooms = rooms.clone();
+        index.corridors = corridors.clone();
+        index
+    }
+
+    /// The rooms indexed.
+    pub fn rooms(&self) -> &Vector<Room> {
+        &self.rooms
+    }
+
+    /// The corridors indexed.
+    pub fn corridors(&self) -> &Vector<Corridor> {
+        &self.corridors
+    }
+
+    /// Whether the index was built from these vectors. Takes constant time: a vector shared with
+    /// the index is copied where it is changed, so one still sharing its contents is unchanged.
+    /// Vectors of only a few items keep them inline, sharing nothing, and are compared instead.
+    pub fn indexes(&self, rooms: &Vector<Room>, corridors: &Vector<Corridor>) -> bool {
+        same(&self.rooms, rooms) && same(&self.corridors, corridors)
+    }
+
+    pub fn push_room(&mut self, room: Room) {
+        self.insert(LayoutPart::Room(self.rooms.len()), room_bounds(&room));
+        self.rooms.push_back(room);
+    }
+
+    pub fn push_corridor(&mut self, corridor: Corridor) {
+        self.insert(
+            LayoutPart::Corridor(self.corridors.len()),
+            corridor_bounds(&corridor),
+        );
+        self.corridors.push_back(corridor);
+    }
+
+    /// Puts `corridor` in place of the corridor at `index`.
+    pub fn set_corridor(&mut self, index: usize, corridor: Corridor) {
+        let part = LayoutPart::Corridor(index);
+
+        for cell in cells(corridor_bounds(&self.corridors[index])) {
+            if let Some(parts) = self.cells.get_mut(&cell) {
+                parts.retain(|other| *other != part);
+            }
+        }
+
+        self.insert(part, corridor_bounds(&corridor));
+        self.corridors.set(index, corridor);
+    }
+
+    /// The parts that might collide with `shape`, in order, rooms first.
+    pub fn near(&self, shape: &impl Collision) -> Vec<LayoutPart> {
+        // A corridor too short to have a collision box still collides with what is right next to
+        // it, so look a tile further on every side.
+        let (min, max) = box_bounds(&shape.to_collision_box());
+        self.parts_in((min - IVec2::ONE, max + IVec2::ONE))
+    }
+
+    /// The parts that might hold `tile`, in order, rooms first.
+    pub fn at(&self, tile: IVec2) -> Vec<LayoutPart> {
+        self.parts_in((tile, tile))
+    }
+
+    fn insert(&mut self, part: LayoutPart, bounds: (IVec2, IVec2)) {
+        for cell in cells(bounds) {
+            self.cells.entry(cell).or_default().push_back(part);
+        }
+    }
+
+    fn parts_in(&self, bounds: (IVec2, IVec2)) -> Vec<LayoutPart> {
+        let mut parts: Vec<LayoutPart> = cells(bounds)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flat_map(|parts| parts.iter().copied())
+            .collect();
+
+        parts.sort_unstable();
+        parts.dedup();
+        parts
+    }
+}
+
+impl DungeonLayout {
+    /// The rooms and corridors `shape` collides with, in order, rooms first.
+    pub fn colliding(&self, shape: &impl Collision) -> Vec<LayoutPart> {
+        self.spatial_index()
+            .near(shape)
+            .into_iter()
+            .filter(|part| match *part {
+                LayoutPart::Room(i) => self.rooms[i].collides_with(shape),
+                LayoutPart::Corridor(i) => self.corridors[i].collides_with(shape),
+            })
+            .collect()
+    }
+
+    /// The rooms, walls included, and corridors that `tile` is part of, in order, rooms first.
+    /// More than one where rooms share a wall or corridors meet.
+    pub fn parts_at(&self, tile: IVec2) -> Vec<LayoutPart> {
+        self.spatial_index()
+            .at(tile)
+            .into_iter()
+            .filter(|part| match *part {
+                LayoutPart::Room(i) => self.rooms[i].contains(tile),
+                LayoutPart::Corridor(i) => self.corridors[i].contains(tile),
+            })
+            .collect()
+    }
+}
+
+fn same<A: Clone + PartialEq>(indexed: &Vector<A>, other: &Vector<A>) -> bool {
+    indexed.ptr_eq(other) || (indexed.is_inline() && other.is_inline() && indexed == other)
+}
+
+/// The lowest and highest tile of a collision box. A box with no size is taken to be the tile at
+/// its position.
+fn box_bounds(collision_box: &CollisionBox) -> (IVec2, IVec2) {
+    let size = IVec2::new(
+        collision_box.shape.width as i32,
+        collision_box.shape.height as i32,
+    );
+
+    (
+        collision_box.position,
+        collision_box.position + (size - IVec2::ONE).max(IVec2::ZERO),
+    )
+}
+
+fn room_bounds(room: &Room) -> (IVec2, IVec2) {
+    box_bounds(&room.to_collision_box())
+}
+
+/// A corridor's collision box leaves out its two end tiles, which a tile lookup still has to find.
+fn corridor_bounds(corridor: &Corridor) -> (IVec2, IVec2) {
+    let (min, max) = box_bounds(&corridor.to_collision_box());
+    let (start, end) = (corridor.position, corridor.end());
+
+    (min.min(start).min(end), max.max(start).max(end))
+}
+
+fn cells((min, max): (IVec2, IVec2)) -> impl Iterator<Item = IVec2> {
+    let (min, max) = (cell_of(min), cell_of(max));
+    (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+}
+
+fn cell_of(tile: IVec2) -> IVec2 {
+    IVec2::new(tile.x.div_euclid(CELL_SIZE), tile.y.div_euclid(CELL_SIZE))
+}
+
+#[cfg(test)]
+mod spatial_index_tests {
+    use super::*;
+    use crate::dungeon_generation::corridor_shapes::segment;
+    use crate::dungeon_generation::fixtures::{room, row_of_rooms};
+    use crate::dungeon_generation::room::Orientation::UP;
+
+    #[test]
+    fn only_looks_near_the_shape() {
+        let layout = row_of_rooms(20);
+        let index = layout.spatial_index();
+
+        let near = index.near(&room(100, 0));
+        assert!(near.contains(&LayoutPart::Room(10)));
+        assert!(near.len() < 5, "{near:?}");
+        assert!(index.near(&room(500, 500)).is_empty());
+    }
+
+    #[test]
+    fn finds_what_collides() {
+        let layout = row_of_rooms(3);
+
+        assert_eq!(
+            layout.colliding(&room(12, 3)),
+            vec![LayoutPart::Room(1), LayoutPart::Corridor(1)]
+        );
+        assert_eq!(
+            layout.colliding(&segment(IVec2::new(7, -3), UP, 7)),
+            vec![LayoutPart::Corridor(0)]
+        );
+        assert!(layout.colliding(&room(5, 10)).is_empty());
+    }
+
+    #[test]
+    fn finds_the_parts_at_a_tile() {
+        let layout = row_of_rooms(2);
+
+        assert_eq!(layout.parts_at(IVec2::new(2, 2)), vec![LayoutPart::Room(0)]);
+        assert_eq!(
+            layout.parts_at(IVec2::new(4, 2)),
+            vec![LayoutPart::Room(0), LayoutPart::Corridor(0)]
+        );
+        assert_eq!(
+            layout.parts_at(IVec2::new(7, 2)),
+            vec![LayoutPart::Corridor(0)]
+        );
+        assert!(layout.parts_at(IVec2::new(7, 3)).is_empty());
+        assert_eq!(layout.room_at(IVec2::new(12, 1)), Some(1));
+    }
+
+    #[test]
+    fn follows_parts_pushed_and_replaced() {
+        let mut layout = row_of_rooms(2);
+        layout.push_room(room(0, 40));
+        layout.set_corridor(0, segment(IVec2::new(2, 4), UP, 37));
+
+        assert!(layout
+            .spatial_index()
+            .indexes(&layout.rooms, &layout.corridors));
+        assert_eq!(layout.room_at(IVec2::new(1, 41)), Some(2));
+        assert_eq!(
+            layout.parts_at(IVec2::new(2, 20)),
+            vec![LayoutPart::Corridor(0)]
+        );
+        assert!(layout.parts_at(IVec2::new(7, 2)).is_empty());
+    }
+
+    #[test]
+    fn rooms_and_corridors_changed_directly_are_still_found() {
+        let mut layout = row_of_rooms(2);
+        let index = layout.spatial_index().into_owned();
+
+        layout.rooms.push_back(room(0, 40));
+        layout.corridors = Vector::new();
+
+        assert!(!index.indexes(&layout.rooms, &layout.corridors));
+        assert_eq!(layout.room_at(IVec2::new(1, 41)), Some(2));
+        assert!(layout.parts_at(IVec2::new(7, 2)).is_empty());
+    }
+}
@@ -1,8 +1,12 @@
 use crate::dungeon_generation::dungeon_state::{DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::key::KeyColour;
+use crate::dungeon_generation::stairs::Stairs;
 use bevy::math::IVec2;
-use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::HashSet;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Spawn {
     pub position: IVec2,
     pub spawn_type: SpawnType,
@@ -11,37 +15,72 @@ pub struct Spawn {
 #[derive(Clone, Debug, PartialEq)]
 pub enum SpawnType {
     Player,
-    Key,
-    Door,
+    Key(KeyColour),
+    Door(KeyColour),
+    Stairs(Stairs),
 }
 
-pub fn place_player_spawn(state: &DungeonState) -> Result<DungeonState, String> {
-    let first_room = state.layout.rooms.get(0);
+/// Generator step that puts the player at one end of the floor, so the rest of it lies ahead of
+/// them. Run it once the rooms are laid out. A player already on the floor, such as one placed
+/// by a prefab, is kept.
+pub fn place_player_spawn(state: &DungeonState) -> Result<DungeonState, StepError> {
+    if state
+        .spawns
+        .iter()
+        .any(|spawn| spawn.spawn_type == SpawnType::Player)
+    {
+        return Ok(DungeonStateBuilder::from_state(state).build());
+    }
 
-    if let Some(room) = first_room {
-        let mut rng = state.rng.borrow_mut();
+    if let Some(room) = start_room(state).map(|start| &state.layout.rooms[start]) {
+        let mut rng = state.rng.lock().unwrap();
 
-        let x = rng.gen_range(1..(room.shape.width - 1)) as i32;
-        let y = rng.gen_range(1..(room.shape.height - 1)) as i32;
-
-        let mut spawns = state.spawns.clone();
-        spawns.push(Spawn {
-            position: IVec2::new(x, y) + room.position,
-            spawn_type: SpawnType::Player,
-        });
+        let position = room
+            .random_floor_tile(&mut rng)
+            .ok_or(StepError::OutOfBounds)?;
 
         return Ok(DungeonStateBuilder::from_state(state)
-            .spawns(spawns)
+            .push_spawn(Spawn {
+                position,
+                spawn_type: SpawnType::Player,
+            })
             .build());
     }
 
-    return Err("Could not place player spawn".to_string());
+    Err(StepError::NoRoom)
+}
+
+/// The dead end farthest from the first room, which is an end of the longest way across the
+/// floor, or the farthest room when every room is on a loop. Rooms that already hold spawns,
+/// like a prefab vault, are passed over. Ties go to the lowest index.
+fn start_room(state: &DungeonState) -> Option<usize> {
+    let layout = &state.layout;
+    if layout.rooms.is_empty() {
+        return None;
+    }
+
+    let furnished: HashSet<usize> = state
+        .spawns
+        .iter()
+        .filter_map(|spawn| layout.room_at(spawn.position))
+        .collect();
+    let graph = layout.graph();
+    let distances = graph.distances_from(0);
+    let farthest = |rooms: Vec<usize>| {
+        rooms
+            .into_iter()
+            .filter(|&room| distances[room].is_some() && !furnished.contains(&room))
+            .max_by_key(|&room| (distances[room], Reverse(room)))
+    };
+
+    farthest(graph.leaf_rooms()).or_else(|| farthest((0..graph.room_count()).collect()))
 }
 
 #[cfg(test)]
 mod player_spawn_generation_tests {
     use super::*;
     use crate::dungeon_generation::dungeon_generator::{add_room, DungeonGenerator};
+    use crate::dungeon_generation::fixtures::row_of_rooms;
 
     #[test]
     fn fails_if_no_room() {
@@ -59,4 +98,42 @@ mod player_spawn_generation_tests {
 
         assert_eq!(dungeon.is_ok(), true);
     }
+
+    /// Three rooms in a row, joined left to right.
+    fn row() -> DungeonState {
+        DungeonStateBuilder::default()
+            .layout(row_of_rooms(3))
+            .build()
+    }
+
+    #[test]
+    fn starts_at_the_far_end_of_the_floor() {
+        let state = place_player_spawn(&row()).unwrap();
+
+        assert_eq!(state.layout.room_at(state.spawns[0].position), Some(2));
+    }
+
+    #[test]
+    fn does_not_start_in_a_furnished_room() {
+        let mut state = row();
+        state.spawns.push_back(Spawn {
+            position: IVec2::new(22, 2),
+            spawn_type: SpawnType::Key(KeyColour::Gold),
+        });
+
+        let state = place_player_spawn(&state).unwrap();
+
+        assert_eq!(state.layout.room_at(state.spawns[1].position), Some(0));
+    }
+
+    #[test]
+    fn keeps_a_player_already_placed() {
+        let mut state = row();
+        state.spawns.push_back(Spawn {
+            position: IVec2::new(2, 2),
+            spawn_type: SpawnType::Player,
+        });
+
+        assert_eq!(place_player_spawn(&state).unwrap().spawns, state.spawns);
+    }
 }
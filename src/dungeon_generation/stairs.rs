@@ -0,0 +1,145 @@
+use crate::dungeon_generation::dungeon_state::{DungeonState, DungeonStateBuilder};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::room_roles::RoomRole;
+use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+use bevy::math::IVec2;
+use bevy::prelude::{Color, Component};
+use rand::Rng;
+
+/// Which way a staircase leads. Floors are numbered from 0 at the top, so `Down` goes one deeper.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stairs {
+    Down,
+    Up,
+}
+
+impl Stairs {
+    pub fn sprite_colour(&self) -> Color {
+        match self {
+            Stairs::Down => Color::rgb(0.15, 0.15, 0.15),
+            Stairs::Up => Color::rgb(0.6, 0.6, 0.6),
+        }
+    }
+}
+
+/// Generator step that puts the stairs down to the next floor in the exit room, or in the room
+/// farthest from the player when roles have not been assigned. The stairs up are added by the
+/// game when it enters a floor from above, since a recipe does not know how deep its floor is.
+pub fn place_stairs(state: &DungeonState) -> Result<DungeonState, StepError> {
+    let room = match state.layout.rooms_with_role(RoomRole::Exit).first() {
+        Some(&exit) => exit,
+        None => farthest_room(state).ok_or(StepError::NoRoom)?,
+    };
+
+    let occupied: Vec<IVec2> = state.spawns.iter().map(|spawn| spawn.position).collect();
+    let free_tiles: Vec<IVec2> = state.layout.rooms[room]
+        .floor_tiles()
+        .into_iter()
+        .filter(|tile| !occupied.contains(tile))
+        .collect();
+
+    if free_tiles.is_empty() {
+        return Err(StepError::OutOfBounds);
+    }
+
+    let mut rng = state.rng.lock().unwrap();
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .push_spawn(Spawn {
+            position: free_tiles[rng.gen_range(0..free_tiles.len())],
+            spawn_type: SpawnType::Stairs(Stairs::Down),
+        })
+        .build())
+}
+
+/// The room the most rooms away from the player, or the last room when there is no player.
+fn farthest_room(state: &DungeonState) -> Option<usize> {
+    let last = state.layout.rooms.len().checked_sub(1)?;
+
+    let start = state
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .and_then(|player| state.layout.room_at(player.position));
+
+    let start = match start {
+        Some(start) => start,
+        None => return Some(last),
+    };
+
+    let distances = state.layout.graph().distances_from(start);
+    (0..distances.len()).max_by_key(|&room| (distances[room], std::cmp::Reverse(room)))
+}
+
+#[cfg(test)]
+mod stairs_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::fallback_dungeon;
+    use crate::dungeon_generation::fixtures::row_of_rooms;
+    use crate::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+
+    fn stairs_of(state: &DungeonState) -> Vec<IVec2> {
+        state
+            .spawns
+            .iter()
+            .filter(|spawn| spawn.spawn_type == SpawnType::Stairs(Stairs::Down))
+            .map(|spawn| spawn.position)
+            .collect()
+    }
+
+    #[test]
+    fn fails_without_a_room() {
+        let state = DungeonStateBuilder::default().build();
+
+        assert_eq!(place_stairs(&state).unwrap_err(), StepError::NoRoom);
+    }
+
+    #[test]
+    fn goes_in_the_room_farthest_from_the_player() {
+        let state = DungeonStateBuilder::default()
+            .layout(row_of_rooms(3))
+            .spawns(vec![Spawn {
+                position: IVec2::new(22, 2),
+                spawn_type: SpawnType::Player,
+            }])
+            .build();
+
+        let stairs = stairs_of(&place_stairs(&state).unwrap());
+
+        assert_eq!(stairs.len(), 1);
+        assert!(state.layout.rooms[0].contains(stairs[0]));
+    }
+
+    #[test]
+    fn not_on_the_player() {
+        let mut dungeon = fallback_dungeon(3);
+        dungeon
+            .spawns
+            .retain(|spawn| spawn.spawn_type == SpawnType::Player);
+
+        for _ in 0..20 {
+            let stairs = stairs_of(&place_stairs(&dungeon).unwrap());
+            assert_ne!(stairs[0], dungeon.spawns[0].position);
+        }
+    }
+
+    #[test]
+    fn default_recipe_has_stairs_in_the_exit() {
+        for seed in 0..10 {
+            let dungeon = GenerationRecipe::default()
+                .to_generator(&StepRegistry::default())
+                .unwrap()
+                .seed(seed)
+                .generate()
+                .unwrap();
+
+            let stairs = stairs_of(&dungeon);
+            let exit = dungeon.layout.rooms_with_role(RoomRole::Exit)[0];
+            assert_eq!(stairs.len(), 1, "seed {seed}");
+            assert!(
+                dungeon.layout.rooms[exit].contains(stairs[0]),
+                "seed {seed}"
+            );
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::dungeon_generation::dungeon_generator::DungeonGenerator;
+use std::fmt;
+use std::ops::Range;
+use std::time::Duration;
+
+/// What happened over many generations with the same generator, one per seed, for finding out
+/// which steps are slow, which retry the most and how close they come to giving up.
+#[derive(Debug, Default)]
+pub struct GenerationStats {
+    pub runs: u32,
+    pub successes: u32,
+    /// Generations that found no floor meeting the generator's constraints.
+    pub unacceptable: u32,
+    /// One entry per step of the generator, in order.
+    pub steps: Vec<StepStats>,
+    /// Of the floors that generated.
+    pub room_counts: Distribution,
+    pub corridor_counts: Distribution,
+    pub widths: Distribution,
+    pub heights: Distribution,
+}
+
+#[derive(Debug, Default)]
+pub struct StepStats {
+    pub name: String,
+    pub max_attempts: u32,
+    /// How many times the step ran, counting the times it was replayed after backtracking.
+    pub runs: u32,
+    /// Attempts taken each time the step ran.
+    pub attempts: Distribution,
+    /// Optional steps that gave up.
+    pub skipped: u32,
+    /// Required steps that gave up and went back to replay earlier steps.
+    pub backtracks: u32,
+    /// Required steps that gave up, failing generation.
+    pub failed: u32,
+    pub time: Duration,
+}
+
+/// A set of counts, for their spread.
+#[derive(Debug, Default)]
+pub struct Distribution {
+    values: Vec<u32>,
+}
+
+impl Distribution {
+    pub fn push(&mut self, value: u32) {
+        self.values.push(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn min(&self) -> u32 {
+        self.values.iter().copied().min().unwrap_or(0)
+    }
+
+    pub fn max(&self) -> u32 {
+        self.values.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+
+        self.values.iter().map(|&value| value as f64).sum::<f64>() / self.values.len() as f64
+    }
+
+    /// The value `percent` of the way through the sorted values, so 50 is the median.
+    pub fn percentile(&self, percent: u32) -> u32 {
+        if self.values.is_empty() {
+            return 0;
+        }
+
+        let mut sorted = self.values.clone();
+        sorted.sort_unstable();
+
+        let index = (sorted.len() - 1) * percent.min(100) as usize / 100;
+        sorted[index]
+    }
+}
+
+impl GenerationStats {
+    /// Generates a floor from every seed in `seeds`, recording every step as it runs.
+    pub fn measure(generator: &DungeonGenerator, seeds: Range<u64>) -> GenerationStats {
+        let mut stats = GenerationStats::default();
+
+        for seed in seeds {
+            stats.runs += 1;
+
+            let result = generator.generate_with_reports(seed, |index, report| {
+                if stats.steps.len() <= index {
+                    stats.steps.push(StepStats {
+                        name: report.name.clone(),
+                        max_attempts: report.max_attempts,
+                        ..Default::default()
+                    });
+                }
+
+                let step = &mut stats.steps[index];
+                step.runs += 1;
+                step.attempts.push(report.attempts);
+                step.time += report.time;
+                if report.skipped {
+                    step.skipped += 1;
+                }
+                if report.backtracked {
+                    step.backtracks += 1;
+                }
+            });
+
+            match result {
+                Ok(state) => {
+                    stats.successes += 1;
+                    stats.room_counts.push(state.layout.rooms.len() as u32);
+                    stats
+                        .corridor_counts
+                        .push(state.layout.corridors.len() as u32);
+
+                    let size = state.layout.size();
+                    stats.widths.push(size.x as u32);
+                    stats.heights.push(size.y as u32);
+                }
+                Err(error) => match stats.steps.get_mut(error.step_index) {
+                    Some(step) => step.failed += 1,
+                    None => stats.unacceptable += 1,
+                },
+            }
+        }
+
+        stats
+    }
+
+    pub fn success_rate(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+
+        self.successes as f64 / self.runs as f64
+    }
+}
+
+impl StepStats {
+    pub fn mean_time(&self) -> Duration {
+        if self.runs == 0 {
+            return Duration::ZERO;
+        }
+
+        self.time / self.runs
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {min}, median {median}, p95 {p95}, max {max}, mean {mean:.1}",
+            min = self.min(),
+            median = self.percentile(50),
+            p95 = self.percentile(95),
+            max = self.max(),
+            mean = self.mean(),
+        )
+    }
+}
+
+impl fmt::Display for GenerationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{successes}/{runs} generated ({rate:.1}%)",
+            successes = self.successes,
+            runs = self.runs,
+            rate = self.success_rate() * 100.0,
+        )?;
+        if self.unacceptable > 0 {
+            writeln!(
+                f,
+                "{} found no floor meeting the constraints",
+                self.unacceptable
+            )?;
+        }
+        writeln!(f, "rooms:     {}", self.room_counts)?;
+        writeln!(f, "corridors: {}", self.corridor_counts)?;
+        writeln!(f, "width:     {}", self.widths)?;
+        writeln!(f, "height:    {}", self.heights)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>3} {:<32} {:>10} {:>8} {:>8} {:>8} {:>7} {:>10} {:>7}",
+            "#", "step", "mean time", "attempts", "p95", "worst", "skipped", "backtracks", "failed"
+        )?;
+
+        for (index, step) in self.steps.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>3} {:<32} {:>10} {:>8.1} {:>8} {:>8} {:>7} {:>10} {:>7}",
+                index + 1,
+                step.name,
+                format!("{:.1?}", step.mean_time()),
+                step.attempts.mean(),
+                step.attempts.percentile(95),
+                format!("{}/{}", step.attempts.max(), step.max_attempts),
+                step.skipped,
+                step.backtracks,
+                step.failed,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod generation_stats_tests {
+    use super::*;
+    use crate::dungeon_generation::constraints::Constraint;
+    use crate::dungeon_generation::dungeon_generator::{add_corridor_then_room, add_room};
+    use crate::dungeon_generation::dungeon_state::{DungeonLayout, DungeonState};
+    use crate::dungeon_generation::generation_error::StepError;
+    use crate::dungeon_generation::retry_policy::RetryPolicy;
+    use bevy::math::IVec2;
+
+    #[test]
+    fn distribution_summaries() {
+        let mut distribution = Distribution::default();
+        assert_eq!(distribution.percentile(50), 0);
+
+        for value in [5, 1, 4, 2, 3] {
+            distribution.push(value);
+        }
+
+        assert_eq!(distribution.min(), 1);
+        assert_eq!(distribution.max(), 5);
+        assert_eq!(distribution.percentile(50), 3);
+        assert_eq!(distribution.percentile(100), 5);
+        assert_eq!(distribution.mean(), 3.0);
+    }
+
+    #[test]
+    fn counts_every_step_of_every_run() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .add_retryable_step(add_corridor_then_room);
+
+        let stats = GenerationStats::measure(&generator, 0..20);
+
+        assert_eq!(stats.runs, 20);
+        assert_eq!(stats.successes, 20);
+        assert_eq!(stats.steps.len(), 2);
+        assert_eq!(stats.steps[0].attempts.max(), 1);
+        assert_eq!(stats.steps[1].runs, 20);
+        assert_eq!(stats.steps[1].max_attempts, 1000);
+        assert_eq!(stats.room_counts.min(), 2);
+        assert_eq!(stats.corridor_counts.max(), 1);
+    }
+
+    #[test]
+    fn failed_runs_stop_at_the_failing_step() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .add_step(add_room)
+            .add_step(add_room);
+
+        let stats = GenerationStats::measure(&generator, 0..5);
+
+        assert_eq!(stats.successes, 0);
+        assert_eq!(stats.steps[1].failed, 5);
+        assert_eq!(stats.steps.len(), 2);
+        assert!(stats.room_counts.is_empty());
+    }
+
+    #[test]
+    fn counts_backtracks_and_replayed_steps() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .add_step_with_policy(
+                "never",
+                |_: &DungeonState| Err(StepError::OutOfBounds),
+                RetryPolicy::attempts(1).backtrack(1, 2),
+            );
+
+        let stats = GenerationStats::measure(&generator, 0..1);
+
+        assert_eq!(stats.steps[0].runs, 3);
+        assert_eq!(stats.steps[1].backtracks, 2);
+        assert_eq!(stats.steps[1].failed, 1);
+    }
+
+    #[test]
+    fn counts_generations_the_constraints_rejected() {
+        let generator = DungeonGenerator::new()
+            .add_step(add_room)
+            .constraint(Constraint::MinRooms(2))
+            .max_candidates(3);
+
+        let stats = GenerationStats::measure(&generator, 0..4);
+
+        assert_eq!(stats.successes, 0);
+        assert_eq!(stats.unacceptable, 4);
+        assert_eq!(stats.steps[0].runs, 12);
+        assert_eq!(stats.steps[0].failed, 0);
+    }
+
+    #[test]
+    fn bounds_cover_rooms_and_corridors() {
+        let generator = DungeonGenerator::new().add_step(add_room);
+        let state = generator.generate().unwrap();
+        let room = &state.layout.rooms[0];
+
+        assert_eq!(
+            state.layout.size(),
+            IVec2::new(room.shape.width as i32, room.shape.height as i32)
+        );
+        assert_eq!(DungeonLayout::default().size(), IVec2::ZERO);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::dungeon_generation::dungeon_state::DungeonLayout;
+use bevy::prelude::{Component, IVec2, Resource};
+use bevy::utils::HashMap;
+
+#[derive(Component, Clone, PartialEq, Copy, Debug)]
+pub enum TileType {
+    Void,
+    Floor,
+    Wall,
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct TileMap {
+    tile_map: HashMap<IVec2, TileType>,
+}
+
+impl TileMap {
+    pub fn new(map: HashMap<IVec2, TileType>) -> TileMap {
+        TileMap { tile_map: map }
+    }
+
+    pub fn get(&self, pos: IVec2) -> TileType {
+        let tile_option = self.tile_map.get(&pos);
+        if let Some(tile) = tile_option {
+            return *tile;
+        }
+
+        return TileType::Void;
+    }
+
+    pub fn set(&mut self, pos: IVec2, tile_type: TileType) {
+        self.tile_map.insert(pos, tile_type);
+    }
+
+    /// Every tile that is not void, in no particular order.
+    pub fn tiles(&self) -> impl Iterator<Item = (IVec2, TileType)> + '_ {
+        self.tile_map
+            .iter()
+            .map(|(pos, tile_type)| (*pos, *tile_type))
+            .filter(|(_, tile_type)| *tile_type != TileType::Void)
+    }
+}
+
+/// Floors are laid first so that corridors can open through room walls and meet each other at
+/// bends and junctions; walls then follow each room's outline and fill any empty tile around a
+/// corridor.
+pub fn get_tile_map(layout: &DungeonLayout) -> TileMap {
+    let grid: HashMap<IVec2, TileType> = HashMap::new();
+    let mut tile_map = TileMap::new(grid);
+
+    for room in &layout.rooms {
+        for tile in room.floor_tiles() {
+            tile_map.set(tile, TileType::Floor);
+        }
+    }
+
+    for corridor in &layout.corridors {
+        let dir: IVec2 = corridor.shape.orientation.into();
+
+        for i in 0..corridor.shape.length as i32 {
+            tile_map.set(corridor.position + dir * i, TileType::Floor);
+        }
+    }
+
+    for room in &layout.rooms {
+        for tile in room.wall_tiles() {
+            if tile_map.get(tile) != TileType::Floor {
+                tile_map.set(tile, TileType::Wall);
+            }
+        }
+    }
+
+    for corridor in &layout.corridors {
+        let dir: IVec2 = corridor.shape.orientation.into();
+
+        for i in 0..corridor.shape.length as i32 {
+            let floor_pos = corridor.position + dir * i;
+
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    let pos = floor_pos + IVec2::new(x, y);
+                    if tile_map.get(pos) == TileType::Void {
+                        tile_map.set(pos, TileType::Wall);
+                    }
+                }
+            }
+        }
+    }
+
+    return tile_map;
+}
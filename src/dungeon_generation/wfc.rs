@@ -0,0 +1,596 @@
+use crate::dungeon_generation::cave::{grid, largest_area};
+use crate::dungeon_generation::dungeon_state::{
+    DungeonLayout, DungeonRng, DungeonState, DungeonStateBuilder,
+};
+use crate::dungeon_generation::generation_error::StepError;
+use crate::dungeon_generation::room::{Rectangle, Room};
+use crate::dungeon_generation::room_outline::RoomOutline;
+use crate::dungeon_generation::tile_map::{TileMap, TileType};
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+use im::{vector, Vector};
+use rand::Rng;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// The area a WFC floor is generated in, with its bottom left corner at the origin.
+pub const WFC_AREA: Rectangle = Rectangle {
+    width: 48,
+    height: 32,
+};
+
+/// Floors whose largest connected area covers less than this share of `WFC_AREA` are rejected.
+const MIN_WFC_SHARE: f32 = 0.2;
+
+/// The four directions cells are compared in, as offsets from a cell to its neighbour.
+const DIRECTIONS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// A `.wfc.ron` rule file. Tiles are written as characters: `.` for floor, `#` for wall and
+/// anything else for empty space.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub enum WfcRuleFile {
+    /// Tiles with their weights, and which tiles may sit next to which. Horizontal pairs are
+    /// `(left, right)` and vertical pairs `(below, above)`.
+    Adjacency {
+        tiles: Vec<(char, u32)>,
+        horizontal: Vec<(char, char)>,
+        vertical: Vec<(char, char)>,
+    },
+    /// A small example map, top row first, to learn every `pattern_size` square of tiles from.
+    /// Generated maps only contain squares seen in the example, so they keep its style.
+    Example {
+        pattern_size: usize,
+        map: Vec<String>,
+    },
+}
+
+/// Square patterns of tiles, how often each should appear, and which patterns may neighbour
+/// each other. Adjacency rules are patterns of a single tile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WfcRules {
+    size: usize,
+    /// Tiles of each pattern, row by row from the bottom.
+    patterns: Vec<Vec<char>>,
+    weights: Vec<u32>,
+    /// For each entry of `DIRECTIONS` and each pattern, the patterns allowed in that direction.
+    compatible: [Vec<PatternSet>; 4],
+}
+
+/// A set of pattern indices.
+#[derive(Clone, Debug, PartialEq)]
+struct PatternSet(Vec<u64>);
+
+impl WfcRules {
+    /// Reads the rule file at `path`. Learning an example map's patterns takes a while, so
+    /// steps load their rules once and keep them.
+    pub fn load(path: &Path) -> Result<WfcRules, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("could not read {}: {err}", path.display()))?;
+
+        WfcRules::parse(&source).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    pub fn parse(source: &str) -> Result<WfcRules, String> {
+        let file: WfcRuleFile =
+            ron::from_str(source).map_err(|err| format!("Invalid WFC rules: {err}"))?;
+
+        match file {
+            WfcRuleFile::Adjacency {
+                tiles,
+                horizontal,
+                vertical,
+            } => WfcRules::from_adjacency(&tiles, &horizontal, &vertical),
+            WfcRuleFile::Example { pattern_size, map } => WfcRules::learn(&map, pattern_size),
+        }
+    }
+
+    pub fn from_adjacency(
+        tiles: &[(char, u32)],
+        horizontal: &[(char, char)],
+        vertical: &[(char, char)],
+    ) -> Result<WfcRules, String> {
+        if tiles.is_empty() {
+            return Err("WFC rules need at least one tile".to_string());
+        }
+
+        let index: HashMap<char, usize> = tiles
+            .iter()
+            .enumerate()
+            .map(|(index, (tile, _))| (*tile, index))
+            .collect();
+        let lookup = |tile: char| {
+            index
+                .get(&tile)
+                .copied()
+                .ok_or_else(|| format!("Unknown WFC tile '{tile}'"))
+        };
+
+        let mut compatible: [Vec<PatternSet>; 4] =
+            DIRECTIONS.map(|_| vec![PatternSet::empty(tiles.len()); tiles.len()]);
+
+        for (pairs, forward) in [(horizontal, 0), (vertical, 2)] {
+            for (first, second) in pairs {
+                let (first, second) = (lookup(*first)?, lookup(*second)?);
+                compatible[forward][first].insert(second);
+                compatible[forward + 1][second].insert(first);
+            }
+        }
+
+        Ok(WfcRules {
+            size: 1,
+            patterns: tiles.iter().map(|(tile, _)| vec![*tile]).collect(),
+            weights: tiles.iter().map(|(_, weight)| *weight).collect(),
+            compatible,
+        })
+    }
+
+    /// Learns every `size` by `size` pattern of `map`, given top row first, weighted by how often
+    /// it appears.
+    pub fn learn(map: &[String], size: usize) -> Result<WfcRules, String> {
+        let rows: Vec<Vec<char>> = map.iter().rev().map(|row| row.chars().collect()).collect();
+        let height = rows.len();
+        let width = rows.iter().map(Vec::len).min().unwrap_or(0);
+
+        if size == 0 || width < size || height < size {
+            return Err(format!(
+                "WFC example must be at least {size} tiles wide and high"
+            ));
+        }
+
+        let mut patterns: Vec<Vec<char>> = Vec::new();
+        let mut weights: Vec<u32> = Vec::new();
+
+        for y in 0..=height - size {
+            for x in 0..=width - size {
+                let pattern: Vec<char> = (0..size)
+                    .flat_map(|dy| (0..size).map(move |dx| (dx, dy)))
+                    .map(|(dx, dy)| rows[y + dy][x + dx])
+                    .collect();
+
+                match patterns.iter().position(|seen| *seen == pattern) {
+                    Some(index) => weights[index] += 1,
+                    None => {
+                        patterns.push(pattern);
+                        weights.push(1);
+                    }
+                }
+            }
+        }
+
+        let compatible = DIRECTIONS.map(|direction| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    let mut set = PatternSet::empty(patterns.len());
+                    for (index, other) in patterns.iter().enumerate() {
+                        if overlaps(pattern, other, direction, size) {
+                            set.insert(index);
+                        }
+                    }
+                    set
+                })
+                .collect()
+        });
+
+        Ok(WfcRules {
+            size,
+            patterns,
+            weights,
+            compatible,
+        })
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Fills `shape`, with its bottom left corner at `position`, using the rules. A cell that runs
+    /// out of allowed patterns fails the attempt with `StepError::Contradiction`; run it again with
+    /// the same RNG to restart from a fresh grid.
+    pub fn fill(
+        &self,
+        shape: Rectangle,
+        position: IVec2,
+        rng: &mut DungeonRng,
+    ) -> Result<TileMap, StepError> {
+        let size = self.size as i32;
+        let (width, height) = (shape.width as i32, shape.height as i32);
+
+        if width < size || height < size {
+            return Err(StepError::OutOfBounds);
+        }
+
+        let chosen = Wave::new(self, width - size + 1, height - size + 1).collapse(rng)?;
+
+        let mut tile_map = TileMap::default();
+        for tile in grid(width, height) {
+            let cell = IVec2::new(tile.x.min(width - size), tile.y.min(height - size));
+            let offset = tile - cell;
+            let pattern = &self.patterns[chosen[(cell.y * (width - size + 1) + cell.x) as usize]];
+
+            tile_map.set(
+                position + tile,
+                tile_type(pattern[(offset.y * size + offset.x) as usize]),
+            );
+        }
+
+        Ok(tile_map)
+    }
+}
+
+/// The patterns each cell of the output grid may still become.
+struct Wave<'a> {
+    rules: &'a WfcRules,
+    width: i32,
+    height: i32,
+    cells: Vec<PatternSet>,
+}
+
+impl<'a> Wave<'a> {
+    fn new(rules: &'a WfcRules, width: i32, height: i32) -> Wave<'a> {
+        let all = PatternSet::full(rules.patterns.len());
+
+        Wave {
+            rules,
+            width,
+            height,
+            cells: vec![all; (width * height) as usize],
+        }
+    }
+
+    /// Repeatedly settles the most constrained cell on a random pattern and propagates what that
+    /// rules out, until every cell has a single pattern.
+    fn collapse(mut self, rng: &mut DungeonRng) -> Result<Vec<usize>, StepError> {
+        while let Some(cell) = self.most_constrained(rng) {
+            let options = self.cells[cell].indices();
+            let total: u32 = options.iter().map(|index| self.rules.weights[*index]).sum();
+
+            let mut roll = rng.gen_range(0..total);
+            let pattern = options
+                .into_iter()
+                .find(|index| {
+                    let weight = self.rules.weights[*index];
+                    if roll < weight {
+                        return true;
+                    }
+                    roll -= weight;
+                    false
+                })
+                .expect("roll is below the total weight");
+
+            self.cells[cell] = PatternSet::single(self.rules.patterns.len(), pattern);
+            self.propagate(cell)?;
+        }
+
+        Ok(self.cells.iter().map(|cell| cell.indices()[0]).collect())
+    }
+
+    /// A random one of the undecided cells with the fewest patterns left, or `None` when every
+    /// cell is decided.
+    fn most_constrained(&self, rng: &mut DungeonRng) -> Option<usize> {
+        let counts: Vec<usize> = self.cells.iter().map(PatternSet::len).collect();
+        let fewest = counts.iter().copied().filter(|count| *count > 1).min()?;
+
+        let candidates: Vec<usize> = (0..counts.len())
+            .filter(|cell| counts[*cell] == fewest)
+            .collect();
+
+        Some(candidates[rng.gen_range(0..candidates.len())])
+    }
+
+    fn propagate(&mut self, start: usize) -> Result<(), StepError> {
+        let mut stack = vec![start];
+
+        while let Some(cell) = stack.pop() {
+            let tile = IVec2::new(cell as i32 % self.width, cell as i32 / self.width);
+
+            for (direction, offset) in DIRECTIONS.iter().enumerate() {
+                let next = tile + *offset;
+                if next.x < 0 || next.y < 0 || next.x >= self.width || next.y >= self.height {
+                    continue;
+                }
+
+                let mut allowed = PatternSet::empty(self.rules.patterns.len());
+                for pattern in self.cells[cell].indices() {
+                    allowed.union_with(&self.rules.compatible[direction][pattern]);
+                }
+
+                let next_cell = (next.y * self.width + next.x) as usize;
+                if self.cells[next_cell].intersect_with(&allowed) {
+                    if self.cells[next_cell].len() == 0 {
+                        return Err(StepError::Contradiction);
+                    }
+                    stack.push(next_cell);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PatternSet {
+    fn empty(len: usize) -> PatternSet {
+        PatternSet(vec![0; len.div_ceil(64)])
+    }
+
+    fn full(len: usize) -> PatternSet {
+        let mut set = PatternSet::empty(len);
+        for index in 0..len {
+            set.insert(index);
+        }
+        set
+    }
+
+    fn single(len: usize, index: usize) -> PatternSet {
+        let mut set = PatternSet::empty(len);
+        set.insert(index);
+        set
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn indices(&self) -> Vec<usize> {
+        let mut indices = Vec::new();
+
+        for (word_index, word) in self.0.iter().enumerate() {
+            let mut bits = *word;
+            while bits != 0 {
+                indices.push(word_index * 64 + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+        }
+
+        indices
+    }
+
+    fn union_with(&mut self, other: &PatternSet) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    /// Keeps only the patterns also in `other`, returning whether any were removed.
+    fn intersect_with(&mut self, other: &PatternSet) -> bool {
+        let mut changed = false;
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            let kept = *word & other;
+            changed |= kept != *word;
+            *word = kept;
+        }
+        changed
+    }
+}
+
+/// Generator step that lays out a whole floor with wave function collapse from `rules`,
+/// registered as `add_wfc_layout` reading `assets/wfc/default.wfc.ron` or the rule file a recipe
+/// names. Only the largest connected area of floor is kept, stored as one room with a mask
+/// outline, so the rest of the pipeline can spawn on it and check it like any other room. It
+/// must run on an empty layout.
+pub fn add_wfc_layout_with(
+    state: &DungeonState,
+    rules: &WfcRules,
+) -> Result<DungeonState, StepError> {
+    if !state.layout.rooms.is_empty() || !state.layout.corridors.is_empty() {
+        return Err(StepError::Collision);
+    }
+
+    let tile_map = rules.fill(WFC_AREA, IVec2::ZERO, &mut state.rng.lock().unwrap())?;
+
+    let (width, height) = (WFC_AREA.width as i32, WFC_AREA.height as i32);
+    // The outer ring is left for walls, as in every room outline.
+    let floor: Vec<bool> = grid(width, height)
+        .map(|tile| {
+            tile.x > 0
+                && tile.y > 0
+                && tile.x < width - 1
+                && tile.y < height - 1
+                && tile_map.get(tile) == TileType::Floor
+        })
+        .collect();
+
+    let largest = largest_area(&floor, width, height);
+    if (largest.len() as f32) < (width * height) as f32 * MIN_WFC_SHARE {
+        return Err(StepError::OutOfBounds);
+    }
+
+    let mut mask = vec![false; floor.len()];
+    for cell in largest {
+        mask[cell] = true;
+    }
+
+    Ok(DungeonStateBuilder::from_state(state)
+        .layout(DungeonLayout::new(
+            vector![Room {
+                shape: WFC_AREA,
+                position: IVec2::ZERO,
+                outline: RoomOutline::Mask(mask),
+                role: None,
+            }],
+            Vector::new(),
+        ))
+        .build())
+}
+
+/// Whether `second`, placed one step in `direction` from `first`, agrees with it on every tile
+/// where the two overlap.
+fn overlaps(first: &[char], second: &[char], direction: IVec2, size: usize) -> bool {
+    let size = size as i32;
+
+    grid(size, size).all(|tile| {
+        let other = tile - direction;
+        other.x < 0
+            || other.y < 0
+            || other.x >= size
+            || other.y >= size
+            || first[(tile.y * size + tile.x) as usize]
+                == second[(other.y * size + other.x) as usize]
+    })
+}
+
+fn tile_type(tile: char) -> TileType {
+    match tile {
+        '.' => TileType::Floor,
+        '#' => TileType::Wall,
+        _ => TileType::Void,
+    }
+}
+
+#[cfg(test)]
+mod wfc_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::DungeonGenerator;
+    use crate::dungeon_generation::recipe::{asset_path, GenerationRecipe, StepRegistry};
+    use rand::SeedableRng;
+
+    const DEFAULT_WFC_RULES: &str = include_str!("../../assets/wfc/default.wfc.ron");
+
+    const SMALL: Rectangle = Rectangle {
+        width: 16,
+        height: 12,
+    };
+
+    fn stripes() -> WfcRules {
+        WfcRules::parse(
+            r#"Adjacency(
+                tiles: [('.', 1), ('#', 1)],
+                horizontal: [('.', '.'), ('#', '#')],
+                vertical: [('.', '#'), ('#', '.')],
+            )"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn adjacency_rules_are_followed() {
+        let tile_map = stripes()
+            .fill(SMALL, IVec2::ZERO, &mut DungeonRng::seed_from_u64(0))
+            .unwrap();
+
+        for tile in grid(SMALL.width as i32, SMALL.height as i32) {
+            let here = tile_map.get(tile);
+            if tile.x > 0 {
+                assert_eq!(tile_map.get(tile - IVec2::X), here);
+            }
+            if tile.y > 0 {
+                assert_ne!(tile_map.get(tile - IVec2::Y), here);
+            }
+        }
+    }
+
+    #[test]
+    fn learns_each_pattern_once() {
+        let map = ["#..#", "#..#", "#..#"].map(String::from);
+
+        let rules = WfcRules::learn(&map, 2).unwrap();
+
+        assert_eq!(rules.pattern_count(), 3);
+        assert_eq!(rules.weights, vec![2, 2, 2]);
+    }
+
+    #[test]
+    fn learned_output_only_contains_example_patterns() {
+        let rules = WfcRules::parse(DEFAULT_WFC_RULES).unwrap();
+        let size = rules.size as i32;
+
+        let tile_map = (0..20)
+            .find_map(|seed| {
+                rules
+                    .fill(SMALL, IVec2::ZERO, &mut DungeonRng::seed_from_u64(seed))
+                    .ok()
+            })
+            .unwrap();
+
+        for corner in grid(
+            SMALL.width as i32 - size + 1,
+            SMALL.height as i32 - size + 1,
+        ) {
+            let window: Vec<TileType> = grid(size, size)
+                .map(|offset| tile_map.get(corner + offset))
+                .collect();
+
+            assert!(rules.patterns.iter().any(|pattern| pattern
+                .iter()
+                .map(|tile| tile_type(*tile))
+                .eq(window.iter().copied())));
+        }
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let rules = WfcRules::parse(DEFAULT_WFC_RULES).unwrap();
+        let fill = |seed| {
+            let tile_map = rules.fill(SMALL, IVec2::ZERO, &mut DungeonRng::seed_from_u64(seed));
+            tile_map.map(|tile_map| {
+                grid(SMALL.width as i32, SMALL.height as i32)
+                    .map(|tile| tile_map.get(tile))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(fill(3), fill(3));
+    }
+
+    #[test]
+    fn contradiction_fails_the_attempt() {
+        let rules = WfcRules::from_adjacency(&[('.', 1), ('#', 1)], &[('.', '#')], &[]).unwrap();
+
+        assert_eq!(
+            rules
+                .fill(SMALL, IVec2::ZERO, &mut DungeonRng::seed_from_u64(0))
+                .err(),
+            Some(StepError::Contradiction)
+        );
+    }
+
+    #[test]
+    fn unknown_tile_is_an_error() {
+        assert!(WfcRules::from_adjacency(&[('.', 1)], &[('.', '#')], &[]).is_err());
+    }
+
+    #[test]
+    fn wfc_recipe_generates() {
+        let recipe =
+            GenerationRecipe::parse(include_str!("../../assets/recipes/wfc.recipe.ron")).unwrap();
+
+        let dungeon = recipe
+            .to_generator(&StepRegistry::default())
+            .unwrap()
+            .seed(0)
+            .generate()
+            .unwrap();
+
+        assert_eq!(dungeon.layout.rooms.len(), 1);
+        assert!(!dungeon.spawns.is_empty());
+    }
+
+    #[test]
+    fn loads_the_rule_file() {
+        assert_eq!(
+            WfcRules::load(&asset_path("wfc/default.wfc.ron")),
+            WfcRules::parse(DEFAULT_WFC_RULES)
+        );
+        assert!(WfcRules::load(&asset_path("wfc/missing.wfc.ron")).is_err());
+    }
+
+    #[test]
+    fn needs_an_empty_layout() {
+        let rules = WfcRules::parse(DEFAULT_WFC_RULES).unwrap();
+        let add_wfc_layout = move |state: &DungeonState| add_wfc_layout_with(state, &rules);
+        let generator = DungeonGenerator::with_seed(0)
+            .add_named_step("add_wfc_layout", add_wfc_layout.clone(), 100)
+            .add_named_step("add_wfc_layout", add_wfc_layout, 1);
+
+        assert_eq!(
+            generator.generate().unwrap_err().reason,
+            StepError::Collision
+        );
+    }
+}
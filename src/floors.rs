@@ -0,0 +1,280 @@
+use crate::dungeon_generation::door::Door;
+use crate::dungeon_generation::key::Key;
+use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+use crate::dungeon_generation::stairs::Stairs;
+use crate::dungeon_generation::tile_map::{get_tile_map, TileMap};
+use crate::map::{generate_or_recover, spawn_floor, Recipes};
+use crate::movement::Movement;
+use crate::player::Player;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+/// Spreads the seeds of neighbouring floors apart, so floor 1 of one seed is not floor 0 of the
+/// next.
+const FLOOR_SEED_STRIDE: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// How deep the player is, with every floor they have left kept as it was, so that taking the
+/// stairs back up returns to it with the same keys and doors still there.
+#[derive(Resource, Default)]
+pub struct Floors {
+    depth: usize,
+    /// The seed of floor 0; deeper floors are generated from it.
+    seed: u64,
+    saved: HashMap<usize, SavedFloor>,
+    /// The tile the player was last seen on, so stairs only lead anywhere when stepped onto.
+    last_tile: Option<IVec2>,
+    requested: Option<Stairs>,
+}
+
+/// A floor as the player left it: its tiles, what is still lying on it and the player standing
+/// on the stairs they took.
+pub struct SavedFloor {
+    tile_map: TileMap,
+    spawns: Vec<Spawn>,
+}
+
+#[derive(Component)]
+pub struct DepthText;
+
+impl Floors {
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Forgets every floor and starts again at the top, on a floor generated from `seed`.
+    pub fn reset(&mut self, seed: u64) {
+        *self = Floors { seed, ..default() };
+    }
+
+    /// The seed the floor at `depth` is generated from.
+    pub fn seed_at(&self, depth: usize) -> u64 {
+        self.seed
+            .wrapping_add((depth as u64).wrapping_mul(FLOOR_SEED_STRIDE))
+    }
+
+    /// Keeps `left` as the current floor and goes a floor down or up, returning the floor there
+    /// if the player has been on it before.
+    pub fn travel(&mut self, stairs: Stairs, left: SavedFloor) -> Option<SavedFloor> {
+        self.saved.insert(self.depth, left);
+        self.depth = match stairs {
+            Stairs::Down => self.depth + 1,
+            Stairs::Up => self.depth.saturating_sub(1),
+        };
+        self.saved.remove(&self.depth)
+    }
+}
+
+/// Asks for a new floor when the player steps onto stairs.
+pub fn take_stairs_system(
+    player_query: Query<&Movement, With<Player>>,
+    stairs_query: Query<(&Stairs, &Transform)>,
+    mut floors: ResMut<Floors>,
+) {
+    let tile = match player_query.get_single() {
+        Ok(movement) => movement.position,
+        Err(_) => return,
+    };
+
+    if floors.last_tile == Some(tile) {
+        return;
+    }
+    floors.last_tile = Some(tile);
+
+    floors.requested = stairs_query
+        .iter()
+        .find(|(_, transform)| tile_of(transform) == tile)
+        .map(|(stairs, _)| *stairs);
+}
+
+pub fn run_if_floor_changed(floors: Res<Floors>) -> ShouldRun {
+    if floors.requested.is_some() {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Saves the floor being left and puts the player on the floor the stairs lead to: the saved one
+/// if they have been there, or a new one entered by stairs back up.
+///
+/// A new floor is generated within this frame. The recipe's `best_of` candidates are spread over
+/// the compute pool, but the frame waits for all of them, so large recipes or a high `best_of`
+/// show up as a hitch when the stairs are taken.
+#[allow(clippy::too_many_arguments)]
+pub fn change_floor(
+    mut commands: Commands,
+    mut floors: ResMut<Floors>,
+    mut tile_map: ResMut<TileMap>,
+    player_query: Query<&Movement, With<Player>>,
+    key_query: Query<(&Key, &Transform)>,
+    door_query: Query<(&Door, &Transform)>,
+    stairs_query: Query<(&Stairs, &Transform)>,
+    recipes: Recipes,
+) {
+    let stairs = match floors.requested.take() {
+        Some(stairs) => stairs,
+        None => return,
+    };
+
+    let player = match player_query.get_single() {
+        Ok(movement) => movement.position,
+        Err(_) => return,
+    };
+
+    let mut spawns = vec![Spawn {
+        position: player,
+        spawn_type: SpawnType::Player,
+    }];
+    spawns.extend(key_query.iter().map(|(key, transform)| Spawn {
+        position: tile_of(transform),
+        spawn_type: SpawnType::Key(key.colour),
+    }));
+    spawns.extend(door_query.iter().map(|(door, transform)| Spawn {
+        position: tile_of(transform),
+        spawn_type: SpawnType::Door(door.colour),
+    }));
+    spawns.extend(stairs_query.iter().map(|(stairs, transform)| Spawn {
+        position: tile_of(transform),
+        spawn_type: SpawnType::Stairs(*stairs),
+    }));
+
+    let left = SavedFloor {
+        tile_map: std::mem::take(&mut *tile_map),
+        spawns,
+    };
+
+    let next = floors.travel(stairs, left).unwrap_or_else(|| {
+        let generator = recipes
+            .generator()
+            .seed(floors.seed_at(floors.depth))
+            .depth(floors.depth);
+        let dungeon = generate_or_recover(generator);
+
+        let mut spawns: Vec<Spawn> = dungeon.spawns.iter().cloned().collect();
+        if let Some(player) = spawns
+            .iter()
+            .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        {
+            spawns.push(Spawn {
+                position: player.position,
+                spawn_type: SpawnType::Stairs(Stairs::Up),
+            });
+        }
+
+        SavedFloor {
+            tile_map: get_tile_map(&dungeon.layout),
+            spawns,
+        }
+    });
+
+    floors.last_tile = next
+        .spawns
+        .iter()
+        .find(|spawn| spawn.spawn_type == SpawnType::Player)
+        .map(|spawn| spawn.position);
+
+    spawn_floor(&mut commands, next.tile_map, &next.spawns);
+}
+
+fn tile_of(transform: &Transform) -> IVec2 {
+    IVec2::new(
+        (transform.translation.x as i32) / 16,
+        (transform.translation.y as i32) / 16,
+    )
+}
+
+pub fn setup_depth_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new(
+                "DEPTH - ",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-SemiBold.ttf"),
+                    font_size: 30.0,
+                    color: Color::BLACK,
+                },
+            ),
+            TextSection::from_style(TextStyle {
+                font: asset_server.load("fonts/FiraSans-SemiBold.ttf"),
+                font_size: 30.0,
+                color: Color::BLACK,
+            }),
+        ])
+        .with_text_alignment(TextAlignment::TOP_CENTER)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(5.0),
+                right: Val::Px(15.0),
+                ..default()
+            },
+            ..default()
+        }),
+        DepthText,
+    ));
+}
+
+pub fn depth_text_update_system(mut query: Query<&mut Text, With<DepthText>>, floors: Res<Floors>) {
+    for mut text in &mut query {
+        text.sections[1].value = floors.depth().to_string();
+    }
+}
+
+#[cfg(test)]
+mod floors_tests {
+    use super::*;
+    use crate::dungeon_generation::tile_map::TileType;
+
+    fn floor_with_wall_at(x: i32) -> SavedFloor {
+        let mut tile_map = TileMap::default();
+        tile_map.set(IVec2::new(x, 0), TileType::Wall);
+
+        SavedFloor {
+            tile_map,
+            spawns: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn going_down_reaches_a_new_floor() {
+        let mut floors = Floors::default();
+
+        assert!(floors.travel(Stairs::Down, floor_with_wall_at(0)).is_none());
+        assert_eq!(floors.depth(), 1);
+    }
+
+    #[test]
+    fn going_back_up_returns_the_floor_left() {
+        let mut floors = Floors::default();
+        floors.travel(Stairs::Down, floor_with_wall_at(0));
+
+        let top = floors.travel(Stairs::Up, floor_with_wall_at(1)).unwrap();
+        assert_eq!(floors.depth(), 0);
+        assert_eq!(top.tile_map.get(IVec2::new(0, 0)), TileType::Wall);
+
+        let below = floors.travel(Stairs::Down, top).unwrap();
+        assert_eq!(floors.depth(), 1);
+        assert_eq!(below.tile_map.get(IVec2::new(1, 0)), TileType::Wall);
+    }
+
+    #[test]
+    fn reset_forgets_every_floor() {
+        let mut floors = Floors::default();
+        floors.travel(Stairs::Down, floor_with_wall_at(0));
+        floors.reset(5);
+
+        assert_eq!(floors.depth(), 0);
+        assert_eq!(floors.seed_at(0), 5);
+        assert!(floors.travel(Stairs::Down, floor_with_wall_at(0)).is_none());
+    }
+
+    #[test]
+    fn each_floor_has_its_own_seed() {
+        let mut floors = Floors::default();
+        floors.reset(5);
+
+        assert_ne!(floors.seed_at(1), floors.seed_at(0));
+        assert_ne!(floors.seed_at(1), floors.seed_at(2));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::dungeon_generation::key::KeyColour;
 use crate::map::ItemMap;
 use crate::player::Player;
 use bevy::log::warn;
@@ -97,7 +98,23 @@ pub fn text_update_system(
     inventory: Res<Inventory>,
 ) {
     for mut text in &mut query {
-        text.sections[1].value = format!("{count}", count = inventory.get_item_count("key"));
+        text.sections[1].value = held_keys_text(&inventory);
+    }
+}
+
+/// "1 red, 2 gold" for the keys currently held, or "0" when there are none.
+fn held_keys_text(inventory: &Inventory) -> String {
+    let held: Vec<String> = KeyColour::ALL
+        .iter()
+        .map(|colour| (colour, inventory.get_item_count(&colour.item_name())))
+        .filter(|(_, count)| *count > 0)
+        .map(|(colour, count)| format!("{count} {name}", name = colour.name()))
+        .collect();
+
+    if held.is_empty() {
+        "0".to_string()
+    } else {
+        held.join(", ")
     }
 }
 
@@ -137,4 +154,15 @@ mod inventory_tests {
         assert_eq!(inv.items.len(), 1);
         assert_eq!(inv.get_item_count(&"foo".to_string()), 2);
     }
+
+    #[test]
+    fn held_keys_text_lists_each_colour_held() {
+        let mut inv = Inventory::default();
+        assert_eq!(held_keys_text(&inv), "0");
+
+        inv.add_item(&KeyColour::Gold.item_name());
+        inv.add_item_stack(&KeyColour::Red.item_name(), 2);
+
+        assert_eq!(held_keys_text(&inv), "2 red, 1 gold");
+    }
 }
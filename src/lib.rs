@@ -0,0 +1,14 @@
+pub mod camera;
+pub mod dungeon_generation;
+pub mod floors;
+pub mod inventory;
+pub mod map;
+pub mod movement;
+pub mod player;
+pub mod seed;
+pub mod spawns;
+pub mod step_viewer;
+pub mod text_map;
+
+pub const SCREEN_WIDTH: u32 = 1280;
+pub const SCREEN_HEIGHT: u32 = 720;
@@ -1,56 +1,79 @@
-mod camera;
-mod dungeon_generation;
-mod inventory;
-mod map;
-mod movement;
-mod player;
-mod spawns;
-
-use crate::camera::{setup_camera, PostProcessingMaterial};
-use crate::inventory::{pickup_items, setup_text, text_update_system, Inventory};
-use crate::map::{despawn_chunks_far_away, spawn_chunks_around_camera, spawn_map};
-use crate::player::spawn_player;
-use crate::spawns::{despawn_objects, remove_spawn_points, spawn_door, spawn_key};
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 use bevy::sprite::Material2dPlugin;
 use bevy::window::close_on_esc;
 use bevy::{prelude::*, time::FixedTimestep};
 use bevy_ecs_tilemap::prelude::*;
-use camera::pan_camera;
-use iyes_loopless::prelude::AppLooplessFixedTimestepExt;
-use map::{
-    create_map_spawner, despawn_map, respawn_map_input_system, run_if_map_respawned, ChunkManager,
+use dungeons::camera::pan_camera;
+use dungeons::camera::{setup_camera, PostProcessingMaterial};
+use dungeons::dungeon_generation::recipe::{GenerationRecipe, RecipeLoader, StepRegistry};
+use dungeons::dungeon_generation::tile_map::TileMap;
+use dungeons::floors::{
+    change_floor, depth_text_update_system, run_if_floor_changed, setup_depth_text,
+    take_stairs_system, Floors,
+};
+use dungeons::inventory::{pickup_items, setup_text, text_update_system, Inventory};
+use dungeons::map::{
+    create_map_spawner, despawn_all_chunks, despawn_map, load_recipe, reload_recipe_system,
+    respawn_map_input_system, run_if_map_respawned, run_if_next_step_requested, ChunkManager,
+    ItemMap, MapFile, RecipeFile,
+};
+use dungeons::map::{despawn_chunks_far_away, spawn_chunks_around_camera, spawn_map};
+use dungeons::movement::{move_entities, player_input_system};
+use dungeons::player::spawn_player;
+use dungeons::seed::{
+    seed_input_system, seed_text_update_system, setup_seed_text, DungeonSeed, SeedInput,
+};
+use dungeons::spawns::{despawn_objects, remove_spawn_points, spawn_door, spawn_key, spawn_stairs};
+use dungeons::step_viewer::{
+    setup_step_viewer_text, step_generation, step_viewer_text_update_system, StepViewer,
 };
-use movement::{move_entities, player_input_system};
+use iyes_loopless::prelude::AppLooplessFixedTimestepExt;
 use std::time::Duration;
 
 const TIME_STEP: f32 = 1.0 / 60.0;
-const SCREEN_WIDTH: u32 = 1280;
-const SCREEN_HEIGHT: u32 = 720;
 
 fn main() {
     let mut app = App::new();
 
-    app.add_plugins(DefaultPlugins)
-        .add_plugin(LogDiagnosticsPlugin::default())
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
-        .add_plugin(Material2dPlugin::<PostProcessingMaterial>::default())
-        .add_plugin(TilemapPlugin);
+    app.add_plugins(DefaultPlugins.set(AssetPlugin {
+        watch_for_changes: true,
+        ..default()
+    }))
+    .add_plugin(LogDiagnosticsPlugin::default())
+    .add_plugin(FrameTimeDiagnosticsPlugin::default())
+    .add_plugin(Material2dPlugin::<PostProcessingMaterial>::default())
+    .add_plugin(TilemapPlugin)
+    .add_asset::<GenerationRecipe>()
+    .init_asset_loader::<RecipeLoader>();
 
     let setup = SystemSet::new()
         .with_system(setup_camera)
-        .with_system(spawn_map)
+        .with_system(load_recipe)
         .with_system(create_map_spawner)
-        .with_system(setup_text);
+        .with_system(setup_text)
+        .with_system(setup_seed_text)
+        .with_system(setup_depth_text)
+        .with_system(setup_step_viewer_text);
 
     app.add_startup_system_set(setup)
         .insert_resource(ChunkManager::default())
-        .insert_resource(Inventory::default());
+        .insert_resource(Inventory::default())
+        .insert_resource(StepRegistry::default())
+        .insert_resource(DungeonSeed::default())
+        .insert_resource(Floors::default())
+        .insert_resource(SeedInput::default())
+        .insert_resource(StepViewer::default())
+        .insert_resource(MapFile::from_args(std::env::args()))
+        .insert_resource(RecipeFile::from_args(std::env::args()))
+        .init_resource::<TileMap>()
+        .init_resource::<ItemMap>();
 
     let input_system = SystemSet::new()
         .with_system(close_on_esc)
         .with_system(player_input_system)
-        .with_system(respawn_map_input_system);
+        .with_system(seed_input_system.before(respawn_map_input_system))
+        .with_system(respawn_map_input_system)
+        .with_system(reload_recipe_system);
 
     app.add_system_set(input_system);
 
@@ -63,6 +86,24 @@ fn main() {
 
     app.add_system_set(spawning_system);
 
+    let stepping_system = SystemSet::new()
+        .with_run_criteria(run_if_next_step_requested)
+        .with_system(remove_spawn_points)
+        .with_system(despawn_objects)
+        .with_system(despawn_all_chunks)
+        .with_system(step_generation);
+
+    app.add_system_set(stepping_system);
+
+    let floor_changing_system = SystemSet::new()
+        .with_run_criteria(run_if_floor_changed)
+        .with_system(remove_spawn_points)
+        .with_system(despawn_objects)
+        .with_system(despawn_all_chunks)
+        .with_system(change_floor);
+
+    app.add_system_set(floor_changing_system);
+
     let logic = SystemSet::new()
         .with_run_criteria(FixedTimestep::step(TIME_STEP as f64))
         .with_system(move_entities)
@@ -72,8 +113,13 @@ fn main() {
         .with_system(spawn_player)
         .with_system(spawn_key)
         .with_system(spawn_door)
+        .with_system(spawn_stairs)
+        .with_system(take_stairs_system)
         .with_system(pickup_items)
-        .with_system(text_update_system);
+        .with_system(text_update_system)
+        .with_system(seed_text_update_system)
+        .with_system(depth_text_update_system)
+        .with_system(step_viewer_text_update_system);
 
     app.add_fixed_timestep(Duration::from_secs_f32(TIME_STEP), "game_logic")
         .add_fixed_timestep_system_set("game_logic", 0, logic);
@@ -1,59 +1,98 @@
 use crate::camera::MainCamera;
-use crate::dungeon_generation::door::{add_door, Door};
-use crate::dungeon_generation::dungeon_generator::{
-    add_corridor_then_room, add_room, DungeonGenerator,
-};
-use crate::dungeon_generation::dungeon_state::DungeonLayout;
-use crate::dungeon_generation::key::{add_key, Key};
-use crate::dungeon_generation::spawn_generation::{place_player_spawn, SpawnType};
+use crate::dungeon_generation::door::Door;
+use crate::dungeon_generation::dungeon_generator::{fallback_dungeon, DungeonGenerator};
+use crate::dungeon_generation::dungeon_state::DungeonState;
+use crate::dungeon_generation::key::Key;
+use crate::dungeon_generation::recipe::{GenerationRecipe, StepRegistry};
+use crate::dungeon_generation::scoring::Score;
+use crate::dungeon_generation::spawn_generation::{Spawn as DungeonSpawn, SpawnType};
+use crate::dungeon_generation::tile_map::{get_tile_map, TileMap, TileType};
+use crate::floors::Floors;
 use crate::player::Player;
+use crate::seed::DungeonSeed;
 use crate::spawns::Spawn;
+use crate::step_viewer::StepViewer;
+use crate::text_map::TextMap;
 use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use bevy::asset::LoadState;
+use bevy::ecs::system::SystemParam;
+use bevy::tasks::ComputeTaskPool;
 use bevy::utils::HashMap;
 use bevy::{ecs::schedule::ShouldRun, prelude::*, utils::HashSet};
 use bevy_ecs_tilemap::prelude::*;
+use std::marker::PhantomData;
+use std::path::PathBuf;
 
 const CHUNK_SIZE: UVec2 = UVec2 { x: 8, y: 8 };
+const DEFAULT_RECIPE_PATH: &str = "recipes/default.recipe.ron";
+const MAX_GENERATION_ATTEMPTS: u32 = 10;
 
-#[derive(Component, Clone, PartialEq, Copy, Debug)]
-pub enum TileType {
-    Void,
-    Floor,
-    Wall,
+#[derive(Resource, Default)]
+pub struct ItemMap {
+    pub item_map: HashMap<IVec2, (String, Entity)>,
 }
 
-#[derive(Resource, Default)]
-pub struct TileMap {
-    tile_map: HashMap<IVec2, TileType>,
+#[derive(Default, Debug, Resource)]
+pub struct ChunkManager {
+    pub spawned_chunks: HashSet<IVec2>,
 }
 
-#[derive(Resource, Default)]
-pub struct ItemMap {
-    pub item_map: HashMap<IVec2, (String, Entity)>,
+#[derive(Resource)]
+pub struct RecipeHandle(pub Handle<GenerationRecipe>);
+
+/// Builds the generator from the loaded recipe, or from the built-in one while it is loading.
+#[derive(SystemParam)]
+pub struct Recipes<'w, 's> {
+    recipes: Res<'w, Assets<GenerationRecipe>>,
+    handle: Option<Res<'w, RecipeHandle>>,
+    registry: Res<'w, StepRegistry>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
 }
 
-impl TileMap {
-    pub fn new(map: HashMap<IVec2, TileType>) -> TileMap {
-        TileMap { tile_map: map }
+/// A text map to play instead of generating the first floor, given on the command line as
+/// `--map <path>`. It is read again whenever the map respawns, so edits to it show up on
+/// `Backspace`.
+#[derive(Resource, Default)]
+pub struct MapFile(pub Option<PathBuf>);
+
+/// The recipe floors are generated from, as an asset path, given on the command line as
+/// `--recipe <path>`. Edits to it regenerate the floor like edits to the default recipe do.
+#[derive(Resource)]
+pub struct RecipeFile(pub PathBuf);
+
+impl MapFile {
+    /// The path after `--map` in `args`, if there is one.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> MapFile {
+        MapFile(arg_after(args, "--map").map(PathBuf::from))
     }
+}
 
-    pub fn get(&self, pos: IVec2) -> TileType {
-        let tile_option = self.tile_map.get(&pos);
-        if let Some(tile) = tile_option {
-            return *tile;
+impl RecipeFile {
+    /// The path after `--recipe` in `args`, or the default recipe. Paths may start with
+    /// `assets/`, as they do from the project root, or be given inside the assets folder.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> RecipeFile {
+        let path = arg_after(args, "--recipe")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_RECIPE_PATH));
+
+        match path.strip_prefix("assets") {
+            Ok(inside) => RecipeFile(inside.to_path_buf()),
+            Err(_) => RecipeFile(path),
         }
-
-        return TileType::Void;
     }
+}
 
-    pub fn set(&mut self, pos: IVec2, tile_type: TileType) {
-        self.tile_map.insert(pos, tile_type);
+impl Default for RecipeFile {
+    fn default() -> Self {
+        RecipeFile(PathBuf::from(DEFAULT_RECIPE_PATH))
     }
 }
 
-#[derive(Default, Debug, Resource)]
-pub struct ChunkManager {
-    pub spawned_chunks: HashSet<IVec2>,
+/// The argument after `flag` in `args`, if there is one.
+fn arg_after(args: impl IntoIterator<Item = String>, flag: &str) -> Option<String> {
+    let mut args = args.into_iter();
+    args.find(|arg| arg == flag).and_then(|_| args.next())
 }
 
 #[derive(Component, Debug)]
@@ -77,44 +116,6 @@ pub fn respawn_map_input_system(
     }
 }
 
-fn get_tile_map(layout: &DungeonLayout) -> TileMap {
-    let grid: HashMap<IVec2, TileType> = HashMap::new();
-    let mut tile_map = TileMap::new(grid);
-
-    for room in &layout.rooms {
-        for y in 0..room.shape.height {
-            for x in 0..room.shape.width {
-                let on_border =
-                    y == 0 || y == room.shape.height - 1 || x == 0 || x == room.shape.width - 1;
-                let pos = IVec2::new(x as i32 + room.position.x, y as i32 + room.position.y);
-                if on_border {
-                    tile_map.set(pos, TileType::Wall);
-                } else {
-                    tile_map.set(pos, TileType::Floor);
-                }
-            }
-        }
-    }
-
-    for corridor in &layout.corridors {
-        let shape = &corridor.shape;
-
-        let dir: IVec2 = corridor.shape.orientation.into();
-
-        let perp = dir.perp();
-
-        for i in 0..shape.length {
-            let floor_pos = corridor.position + dir * (i as i32);
-
-            tile_map.set(floor_pos, TileType::Floor);
-            tile_map.set(floor_pos + perp, TileType::Wall);
-            tile_map.set(floor_pos - perp, TileType::Wall);
-        }
-    }
-
-    return tile_map;
-}
-
 pub fn spawn_chunks_around_camera(
     mut commands: Commands,
     camera_query: Query<&Transform, With<MainCamera>>,
@@ -204,6 +205,16 @@ pub fn run_if_map_respawned(spawner_query: Query<&MapSpawner>) -> ShouldRun {
     }
 }
 
+pub fn run_if_next_step_requested(spawner_query: Query<&MapSpawner>) -> ShouldRun {
+    let map_spawner = spawner_query.single();
+
+    if map_spawner.generate_next_step {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
 pub fn create_map_spawner(mut commands: Commands) {
     commands.spawn(MapSpawner {
         respawn_map: false,
@@ -211,27 +222,151 @@ pub fn create_map_spawner(mut commands: Commands) {
     });
 }
 
-pub fn spawn_map(mut commands: Commands) {
-    let generator = DungeonGenerator::default()
-        .add_step(add_room)
-        .add_retryable_step(place_player_spawn)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_corridor_then_room)
-        .add_retryable_step(add_key)
-        .add_retryable_step(add_door);
-
-    let dungeon = generator.generate().unwrap();
-
-    let tile_map = get_tile_map(&dungeon.layout);
-
-    for spawn in dungeon.spawns.iter() {
+impl<'w, 's> Recipes<'w, 's> {
+    pub fn generator(&self) -> DungeonGenerator {
+        let loaded = self
+            .handle
+            .as_ref()
+            .and_then(|handle| self.recipes.get(&handle.0));
+
+        let generator = match loaded {
+            Some(recipe) => recipe.to_generator(&self.registry),
+            None => GenerationRecipe::default().to_generator(&self.registry),
+        };
+
+        generator.unwrap_or_else(|err| {
+            warn!("{err}, falling back to the built-in recipe");
+
+            // The built-in recipe still reads prefabs from the assets folder.
+            GenerationRecipe::default()
+                .to_generator(&self.registry)
+                .unwrap_or_else(|err| {
+                    warn!("{err}, using the fallback layout");
+                    DungeonGenerator::new().add_named_step(
+                        "fallback_dungeon",
+                        |state: &DungeonState| Ok(fallback_dungeon(state.seed)),
+                        1,
+                    )
+                })
+        })
+    }
+}
+
+pub fn load_recipe(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    recipe_file: Res<RecipeFile>,
+) {
+    let path = recipe_file.0.clone();
+    commands.insert_resource(RecipeHandle(asset_server.load(path)));
+}
+
+/// Spawns the first floor once the recipe file has loaded, or with the built-in recipe if it
+/// cannot be, and regenerates the floor from the same seed whenever the recipe is edited, so
+/// the edit shows on the floor it was made for.
+pub fn reload_recipe_system(
+    mut events: EventReader<AssetEvent<GenerationRecipe>>,
+    asset_server: Res<AssetServer>,
+    recipe: Res<RecipeHandle>,
+    mut dungeon_seed: ResMut<DungeonSeed>,
+    map_file: Res<MapFile>,
+    mut spawner_query: Query<&mut MapSpawner>,
+) {
+    let first_floor = dungeon_seed.current.is_none();
+    let mut respawn = false;
+
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { .. } => respawn |= first_floor,
+            AssetEvent::Modified { .. } => respawn = true,
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    if first_floor && asset_server.get_load_state(&recipe.0) == LoadState::Failed {
+        warn!("Could not load the recipe, generating from the built-in one");
+        respawn = true;
+    }
+
+    if !respawn {
+        return;
+    }
+
+    // A map file does not depend on the recipe and is read again as it is.
+    if !first_floor && map_file.0.is_none() {
+        dungeon_seed.requested = dungeon_seed.requested.or(dungeon_seed.current);
+    }
+
+    if let Ok(mut spawner) = spawner_query.get_single_mut() {
+        spawner.respawn_map = true;
+    }
+}
+
+pub fn spawn_map(
+    mut commands: Commands,
+    mut dungeon_seed: ResMut<DungeonSeed>,
+    mut step_viewer: ResMut<StepViewer>,
+    mut floors: ResMut<Floors>,
+    map_file: Res<MapFile>,
+    recipes: Recipes,
+) {
+    step_viewer.stop();
+
+    // A typed in seed asks for a generated floor, even when playing a map file.
+    if let (Some(path), None) = (&map_file.0, dungeon_seed.requested) {
+        match TextMap::load(path) {
+            Ok(text_map) => {
+                dungeon_seed.current = Some(text_map.seed);
+                floors.reset(text_map.seed);
+                spawn_floor(&mut commands, text_map.tile_map, &text_map.spawns);
+                return;
+            }
+            Err(error) => warn!("{error}, generating a floor instead"),
+        }
+    }
+
+    let mut generator = recipes.generator();
+
+    // The seed shown is the floor kept out of the recipe's `best_of`, so a seed typed in or
+    // kept for a recipe edit builds that floor alone.
+    if let Some(seed) = dungeon_seed.requested.take() {
+        generator = generator.seed(seed).best_of(1, Score::default());
+    }
+
+    let dungeon = generate_or_recover(generator);
+    dungeon_seed.current = Some(dungeon.seed);
+    floors.reset(dungeon.seed);
+
+    spawn_dungeon(&mut commands, &dungeon);
+}
+
+/// Generates the best of the recipe's `best_of` floors on the compute pool, retrying failed
+/// generations with fresh seeds and falling back to a single safe room if none work.
+/// Blocks until the floor is done, up to `best_of` × `max_candidates` generations.
+pub fn generate_or_recover(mut generator: DungeonGenerator) -> DungeonState {
+    for _ in 0..MAX_GENERATION_ATTEMPTS {
+        match generator.generate_best_of(ComputeTaskPool::get()) {
+            Ok(dungeon) => return dungeon,
+            Err(error) => {
+                warn!("Dungeon generation failed, retrying with a new seed: {error}");
+                generator = generator.seed(rand::random());
+            }
+        }
+    }
+
+    warn!("Dungeon generation kept failing, using the fallback layout");
+    fallback_dungeon(rand::random())
+}
+
+/// Inserts the tiles of `dungeon` and queues its spawns, replacing whatever map was there.
+pub fn spawn_dungeon(commands: &mut Commands, dungeon: &DungeonState) {
+    let spawns: Vec<DungeonSpawn> = dungeon.spawns.iter().cloned().collect();
+    spawn_floor(commands, get_tile_map(&dungeon.layout), &spawns);
+}
+
+/// Inserts `tile_map` and queues `spawns` on it, replacing whatever map was there.
+pub fn spawn_floor(commands: &mut Commands, tile_map: TileMap, spawns: &[DungeonSpawn]) {
+    for spawn in spawns.iter() {
         match spawn.spawn_type {
             SpawnType::Player => {
                 commands.spawn((
@@ -242,18 +377,27 @@ pub fn spawn_map(mut commands: Commands) {
                     },
                 ));
             }
-            SpawnType::Key => {
+            SpawnType::Key(colour) => {
+                commands.spawn((
+                    Key { colour },
+                    Spawn {
+                        position: spawn.position,
+                        spawned: false,
+                    },
+                ));
+            }
+            SpawnType::Door(colour) => {
                 commands.spawn((
-                    Key,
+                    Door { colour },
                     Spawn {
                         position: spawn.position,
                         spawned: false,
                     },
                 ));
             }
-            SpawnType::Door => {
+            SpawnType::Stairs(stairs) => {
                 commands.spawn((
-                    Door,
+                    stairs,
                     Spawn {
                         position: spawn.position,
                         spawned: false,
@@ -332,3 +476,43 @@ fn get_tile_index(tile_type: TileType) -> u32 {
         TileType::Wall => 2,
     }
 }
+
+#[cfg(test)]
+mod map_tests {
+    use super::*;
+
+    #[test]
+    fn map_file_comes_from_the_command_line() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            MapFile::from_args(args(&["dungeons", "--map", "floor.txt"])).0,
+            Some(PathBuf::from("floor.txt"))
+        );
+        assert_eq!(MapFile::from_args(args(&["dungeons"])).0, None);
+        assert_eq!(MapFile::from_args(args(&["dungeons", "--map"])).0, None);
+    }
+
+    #[test]
+    fn recipe_file_comes_from_the_command_line() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            RecipeFile::from_args(args(&["dungeons", "--recipe", "recipes/cave.recipe.ron"])).0,
+            PathBuf::from("recipes/cave.recipe.ron")
+        );
+        assert_eq!(
+            RecipeFile::from_args(args(&[
+                "dungeons",
+                "--recipe",
+                "assets/recipes/bsp.recipe.ron"
+            ]))
+            .0,
+            PathBuf::from("recipes/bsp.recipe.ron")
+        );
+        assert_eq!(
+            RecipeFile::from_args(args(&["dungeons", "--map", "floor.txt"])).0,
+            PathBuf::from(DEFAULT_RECIPE_PATH)
+        );
+    }
+}
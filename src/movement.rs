@@ -1,6 +1,6 @@
 use crate::camera::MainCamera;
+use crate::dungeon_generation::tile_map::{TileMap, TileType};
 use crate::inventory::Inventory;
-use crate::map::{TileMap, TileType};
 use crate::spawns::Openable;
 use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use bevy::prelude::*;
@@ -0,0 +1,176 @@
+use crate::map::MapSpawner;
+use bevy::prelude::*;
+
+const MAX_SEED_DIGITS: usize = 20;
+
+#[derive(Resource, Default)]
+pub struct DungeonSeed {
+    pub current: Option<u64>,
+    pub requested: Option<u64>,
+}
+
+#[derive(Resource, Default)]
+pub struct SeedInput {
+    typing: bool,
+    buffer: String,
+}
+
+#[derive(Component)]
+pub struct SeedText;
+
+impl SeedInput {
+    pub fn start(&mut self) {
+        self.typing = true;
+        self.buffer.clear();
+    }
+
+    pub fn push(&mut self, c: char) {
+        if c.is_ascii_digit() && self.buffer.len() < MAX_SEED_DIGITS {
+            self.buffer.push(c);
+        }
+    }
+
+    pub fn pop(&mut self) {
+        self.buffer.pop();
+    }
+
+    /// Stops typing and returns the typed seed, if it is a valid `u64`.
+    pub fn finish(&mut self) -> Option<u64> {
+        self.typing = false;
+        let seed = self.buffer.parse().ok();
+        self.buffer.clear();
+        seed
+    }
+}
+
+/// `Return` starts typing a seed, digits fill it in, `Back` deletes and a second `Return`
+/// regenerates the dungeon from it.
+pub fn seed_input_system(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut seed_input: ResMut<SeedInput>,
+    mut dungeon_seed: ResMut<DungeonSeed>,
+    mut spawner_query: Query<&mut MapSpawner>,
+) {
+    if !seed_input.typing {
+        characters.clear();
+
+        if keyboard_input.clear_just_pressed(KeyCode::Return) {
+            seed_input.start();
+        }
+        return;
+    }
+
+    for event in characters.iter() {
+        seed_input.push(event.char);
+    }
+
+    if keyboard_input.clear_just_pressed(KeyCode::Back) {
+        seed_input.pop();
+    }
+
+    if keyboard_input.clear_just_pressed(KeyCode::Return) {
+        if let Some(seed) = seed_input.finish() {
+            dungeon_seed.requested = Some(seed);
+
+            if let Ok(mut spawner) = spawner_query.get_single_mut() {
+                spawner.respawn_map = true;
+            }
+        }
+    }
+}
+
+pub fn setup_seed_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new(
+                "SEED - ",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-SemiBold.ttf"),
+                    font_size: 30.0,
+                    color: Color::BLACK,
+                },
+            ),
+            TextSection::from_style(TextStyle {
+                font: asset_server.load("fonts/FiraSans-SemiBold.ttf"),
+                font_size: 30.0,
+                color: Color::BLACK,
+            }),
+        ])
+        .with_text_alignment(TextAlignment::TOP_CENTER)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(5.0),
+                left: Val::Px(15.0),
+                ..default()
+            },
+            ..default()
+        }),
+        SeedText,
+    ));
+}
+
+pub fn seed_text_update_system(
+    mut query: Query<&mut Text, With<SeedText>>,
+    dungeon_seed: Res<DungeonSeed>,
+    seed_input: Res<SeedInput>,
+) {
+    for mut text in &mut query {
+        if seed_input.typing {
+            text.sections[0].value = "SEED > ".to_string();
+            text.sections[1].value = format!("{buffer}_", buffer = seed_input.buffer);
+        } else {
+            text.sections[0].value = "SEED - ".to_string();
+            text.sections[1].value = dungeon_seed
+                .current
+                .map(|seed| seed.to_string())
+                .unwrap_or_default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod seed_input_tests {
+    use super::*;
+
+    #[test]
+    fn typed_digits_become_the_seed() {
+        let mut input = SeedInput::default();
+        input.start();
+        "1234".chars().for_each(|c| input.push(c));
+
+        assert_eq!(input.finish(), Some(1234));
+        assert!(!input.typing);
+    }
+
+    #[test]
+    fn non_digits_are_ignored() {
+        let mut input = SeedInput::default();
+        input.start();
+        "4a2\r".chars().for_each(|c| input.push(c));
+
+        assert_eq!(input.finish(), Some(42));
+    }
+
+    #[test]
+    fn pop_removes_last_digit() {
+        let mut input = SeedInput::default();
+        input.start();
+        "123".chars().for_each(|c| input.push(c));
+        input.pop();
+
+        assert_eq!(input.finish(), Some(12));
+    }
+
+    #[test]
+    fn empty_or_overflowing_input_is_not_a_seed() {
+        let mut input = SeedInput::default();
+        input.start();
+        assert_eq!(input.finish(), None);
+
+        input.start();
+        "99999999999999999999".chars().for_each(|c| input.push(c));
+        assert_eq!(input.finish(), None);
+    }
+}
@@ -1,12 +1,13 @@
 use crate::dungeon_generation::door::Door;
 use crate::dungeon_generation::key::Key;
+use crate::dungeon_generation::stairs::Stairs;
 use crate::map::ItemMap;
 use crate::movement::Collidable;
 use crate::player::Player;
 use bevy::math::{IVec2, Quat};
 use bevy::prelude::{
-    default, Added, Color, Commands, Component, DespawnRecursiveExt, Entity, Or, Query, ResMut,
-    Sprite, SpriteBundle, Transform, Vec2, Vec3, With,
+    default, Added, Commands, Component, DespawnRecursiveExt, Entity, Or, Query, ResMut, Sprite,
+    SpriteBundle, Transform, Vec2, Vec3, With,
 };
 
 #[derive(Component)]
@@ -28,10 +29,10 @@ pub fn remove_spawn_points(mut commands: Commands, spawns_query: Query<Entity, W
 
 pub fn spawn_key(
     mut commands: Commands,
-    mut key_spawn_query: Query<&mut Spawn, Added<Key>>,
+    mut key_spawn_query: Query<(&mut Spawn, &Key), Added<Key>>,
     mut item_map: ResMut<ItemMap>,
 ) {
-    if let Ok(mut spawn) = key_spawn_query.get_single_mut() {
+    for (mut spawn, key) in key_spawn_query.iter_mut() {
         if !spawn.spawned {
             spawn.spawned = true;
 
@@ -42,10 +43,10 @@ pub fn spawn_key(
             );
 
             let entity = commands.spawn((
-                Key,
+                Key { colour: key.colour },
                 SpriteBundle {
                     sprite: Sprite {
-                        color: Color::rgb(1.0, 1.0, 0.4),
+                        color: key.colour.key_sprite_colour(),
                         custom_size: Some(Vec2::new(16.0, 16.0)),
                         ..default()
                     },
@@ -57,13 +58,16 @@ pub fn spawn_key(
 
             item_map
                 .item_map
-                .insert(spawn.position, ("key".to_string(), entity.id()));
+                .insert(spawn.position, (key.colour.item_name(), entity.id()));
         }
     }
 }
 
-pub fn spawn_door(mut commands: Commands, mut door_spawn_query: Query<&mut Spawn, Added<Door>>) {
-    if let Ok(mut spawn) = door_spawn_query.get_single_mut() {
+pub fn spawn_door(
+    mut commands: Commands,
+    mut door_spawn_query: Query<(&mut Spawn, &Door), Added<Door>>,
+) {
+    for (mut spawn, door) in door_spawn_query.iter_mut() {
         if !spawn.spawned {
             spawn.spawned = true;
 
@@ -74,14 +78,16 @@ pub fn spawn_door(mut commands: Commands, mut door_spawn_query: Query<&mut Spawn
             );
 
             commands.spawn((
-                Door,
+                Door {
+                    colour: door.colour,
+                },
                 Openable {
-                    opened_by: "key".to_string(),
+                    opened_by: door.colour.item_name(),
                 },
                 Collidable,
                 SpriteBundle {
                     sprite: Sprite {
-                        color: Color::rgb(0.0, 0.05, 0.1),
+                        color: door.colour.door_sprite_colour(),
                         custom_size: Some(Vec2::new(16.0, 16.0)),
                         ..default()
                     },
@@ -94,10 +100,43 @@ pub fn spawn_door(mut commands: Commands, mut door_spawn_query: Query<&mut Spawn
     }
 }
 
-pub fn despawn_objects(
+/// Stairs are drawn under the player and lead elsewhere when stepped onto, see
+/// `take_stairs_system`.
+pub fn spawn_stairs(
     mut commands: Commands,
-    player_query: Query<Entity, Or<(With<Player>, With<Key>, With<Door>)>>,
+    mut stairs_spawn_query: Query<(&mut Spawn, &Stairs), Added<Stairs>>,
 ) {
+    for (mut spawn, stairs) in stairs_spawn_query.iter_mut() {
+        if !spawn.spawned {
+            spawn.spawned = true;
+
+            let translation = Vec3::new(
+                spawn.position.x as f32 * 16.0,
+                spawn.position.y as f32 * 16.0,
+                0.5,
+            );
+
+            commands.spawn((
+                *stairs,
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: stairs.sprite_colour(),
+                        custom_size: Some(Vec2::new(16.0, 16.0)),
+                        ..default()
+                    },
+                    transform: Transform::from_rotation(Quat::from_axis_angle(Vec3::Z, 0.0))
+                        .with_translation(translation),
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+/// Everything spawned for a floor that goes away when the player leaves it.
+type FloorEntities = Or<(With<Player>, With<Key>, With<Door>, With<Stairs>)>;
+
+pub fn despawn_objects(mut commands: Commands, player_query: Query<Entity, FloorEntities>) {
     for entity in player_query.iter() {
         commands.entity(entity).despawn_recursive();
     }
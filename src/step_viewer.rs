@@ -0,0 +1,272 @@
+use crate::dungeon_generation::constraints::failed_constraints;
+use crate::dungeon_generation::dungeon_generator::{DungeonGenerator, GenerationRun, StepReport};
+use crate::dungeon_generation::dungeon_state::DungeonState;
+use crate::floors::Floors;
+use crate::map::{spawn_dungeon, MapSpawner, Recipes};
+use crate::seed::DungeonSeed;
+use bevy::prelude::*;
+
+/// Debug mode that runs the dungeon generator one step per `Space` press and renders every
+/// intermediate `DungeonState`.
+#[derive(Resource, Default)]
+pub struct StepViewer {
+    session: Option<StepSession>,
+    status: Vec<String>,
+}
+
+struct StepSession {
+    generator: DungeonGenerator,
+    run: GenerationRun,
+    failed: bool,
+}
+
+#[derive(Component)]
+pub struct StepViewerText;
+
+impl StepViewer {
+    pub fn stop(&mut self) {
+        self.session = None;
+        self.status.clear();
+    }
+
+    /// Runs the next step, starting a new session first if there is none or the last one ended.
+    pub fn advance(&mut self, new_generator: impl FnOnce() -> DungeonGenerator) -> &DungeonState {
+        let session = match self.session.take() {
+            Some(session) if !session.is_done() => session,
+            _ => StepSession::new(new_generator()),
+        };
+
+        let session = self.session.insert(session);
+        let step_count = session.generator.step_count();
+        let index = session.run.next_step();
+
+        let (result, report) = session.generator.advance(&mut session.run);
+
+        let mut status = vec![
+            format!("SEED {seed}", seed = session.run.state().seed),
+            format!(
+                "STEP {step}/{step_count} {name}",
+                step = index + 1,
+                name = report.name
+            ),
+        ];
+        status.extend(attempt_lines(&report));
+
+        match result {
+            Ok(()) if report.backtracked => status.push(format!(
+                "Backtracking to step {step}",
+                step = session.run.next_step() + 1
+            )),
+            Ok(()) => {
+                if session.is_done() {
+                    status.push("Generation complete".to_string());
+                    status.extend(
+                        failed_constraints(session.generator.constraints(), session.run.state())
+                            .into_iter()
+                            .map(|constraint| format!("fails constraint: {constraint}")),
+                    );
+                }
+            }
+            Err(error) => {
+                session.failed = true;
+                status.push(format!("Generation failed: {error}"));
+            }
+        }
+
+        self.status = status;
+        session.run.state()
+    }
+
+    pub fn status(&self) -> &[String] {
+        &self.status
+    }
+}
+
+impl StepSession {
+    fn new(generator: DungeonGenerator) -> StepSession {
+        let run = GenerationRun::new(generator.initial_state(), generator.step_count());
+
+        StepSession {
+            generator,
+            run,
+            failed: false,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.failed || self.run.is_finished()
+    }
+}
+
+fn attempt_lines(report: &StepReport) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{attempts} attempt(s), {retries} retries",
+        attempts = report.attempts,
+        retries = report.attempts.saturating_sub(1)
+    )];
+
+    lines.extend(
+        report
+            .rejection_summary()
+            .into_iter()
+            .map(|reason| format!("rejected: {reason}")),
+    );
+
+    if report.skipped {
+        lines.push("skipped: optional step gave up".to_string());
+    }
+
+    lines
+}
+
+pub fn step_generation(
+    mut commands: Commands,
+    mut step_viewer: ResMut<StepViewer>,
+    mut dungeon_seed: ResMut<DungeonSeed>,
+    mut spawner_query: Query<&mut MapSpawner>,
+    mut floors: ResMut<Floors>,
+    recipes: Recipes,
+) {
+    let mut spawner = spawner_query.single_mut();
+    spawner.generate_next_step = false;
+
+    let state = step_viewer.advance(|| match dungeon_seed.requested.take() {
+        Some(seed) => recipes.generator().seed(seed),
+        None => recipes.generator(),
+    });
+
+    dungeon_seed.current = Some(state.seed);
+    floors.reset(state.seed);
+    spawn_dungeon(&mut commands, state);
+}
+
+pub fn setup_step_viewer_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/FiraSans-SemiBold.ttf"),
+                font_size: 20.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(5.0),
+                left: Val::Px(15.0),
+                ..default()
+            },
+            ..default()
+        }),
+        StepViewerText,
+    ));
+}
+
+pub fn step_viewer_text_update_system(
+    mut query: Query<&mut Text, With<StepViewerText>>,
+    step_viewer: Res<StepViewer>,
+) {
+    for mut text in &mut query {
+        text.sections[0].value = step_viewer.status().join("\n");
+    }
+}
+
+#[cfg(test)]
+mod step_viewer_tests {
+    use super::*;
+    use crate::dungeon_generation::constraints::Constraint;
+    use crate::dungeon_generation::dungeon_generator::{add_corridor_then_room, add_room};
+    use crate::dungeon_generation::retry_policy::RetryPolicy;
+
+    fn generator() -> DungeonGenerator {
+        DungeonGenerator::with_seed(7)
+            .add_step(add_room)
+            .add_retryable_step(add_corridor_then_room)
+    }
+
+    #[test]
+    fn advance_runs_one_step_at_a_time() {
+        let mut viewer = StepViewer::default();
+
+        assert_eq!(viewer.advance(generator).layout.rooms.len(), 1);
+        assert_eq!(viewer.advance(generator).layout.rooms.len(), 2);
+        assert!(viewer.status().contains(&"Generation complete".to_string()));
+    }
+
+    #[test]
+    fn advance_restarts_after_the_last_step() {
+        let mut viewer = StepViewer::default();
+        viewer.advance(generator);
+        viewer.advance(generator);
+
+        assert_eq!(viewer.advance(generator).layout.rooms.len(), 1);
+    }
+
+    #[test]
+    fn status_names_the_step_that_ran() {
+        let mut viewer = StepViewer::default();
+        viewer.advance(generator);
+
+        assert_eq!(viewer.status()[1], "STEP 1/2 add_room");
+    }
+
+    #[test]
+    fn failed_step_is_reported() {
+        let mut viewer = StepViewer::default();
+        let failing = || {
+            DungeonGenerator::with_seed(7)
+                .add_step(add_room)
+                .add_step(add_room)
+        };
+        viewer.advance(failing);
+        viewer.advance(failing);
+
+        assert!(viewer
+            .status()
+            .iter()
+            .any(|line| line == "rejected: collides with the existing layout x1"));
+        assert!(viewer
+            .status()
+            .iter()
+            .any(|line| line.starts_with("Generation failed")));
+    }
+
+    #[test]
+    fn finished_floor_lists_failed_constraints() {
+        let mut viewer = StepViewer::default();
+        let constrained = || generator().constraint(Constraint::MinRooms(3));
+        viewer.advance(constrained);
+        viewer.advance(constrained);
+
+        assert_eq!(
+            viewer.status().last().unwrap(),
+            "fails constraint: at least 3 rooms"
+        );
+    }
+
+    #[test]
+    fn backtracking_step_goes_back() {
+        let mut viewer = StepViewer::default();
+        let backtracking = || {
+            DungeonGenerator::with_seed(7)
+                .add_step(add_room)
+                .add_step_with_policy(
+                    "add_room",
+                    add_room,
+                    RetryPolicy::attempts(1).backtrack(1, 1),
+                )
+        };
+        viewer.advance(backtracking);
+
+        assert!(viewer.advance(backtracking).layout.rooms.is_empty());
+        assert_eq!(viewer.status().last().unwrap(), "Backtracking to step 1");
+
+        viewer.advance(backtracking);
+        viewer.advance(backtracking);
+        assert!(viewer
+            .status()
+            .iter()
+            .any(|line| line.starts_with("Generation failed")));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::dungeon_generation::dungeon_state::DungeonState;
+use crate::dungeon_generation::key::KeyColour;
+use crate::dungeon_generation::params::GenerationParams;
+use crate::dungeon_generation::spawn_generation::{Spawn, SpawnType};
+use crate::dungeon_generation::stairs::Stairs;
+use crate::dungeon_generation::tile_map::{get_tile_map, TileMap, TileType};
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A floor as plain text, for pasting into bug reports, checking in as test fixtures and editing
+/// by hand. A header of `name: value` lines comes first, then a blank line and the map, top row
+/// first:
+///
+/// - `#` wall, `.` floor and ` ` (space) nothing
+/// - `P` the player, `<` and `>` stairs up and down
+/// - `r`, `b`, `g` and `o` a red, blue, green or gold key, and `R`, `B`, `G` and `O` a door of
+///   that colour
+///
+/// Spawns stand on floor. The header holds the `seed`, `depth` and `params` the floor was
+/// generated with and the `origin`, the position of the map's bottom left corner. A spawn that
+/// shares its tile with another spawn, or stands anywhere but on floor, is listed in the header
+/// instead, as `spawn: <marker> <x> <y>`. Every header line may be left out, and spawns come back
+/// in map order rather than the order they were generated in.
+#[derive(Debug)]
+pub struct TextMap {
+    pub seed: u64,
+    pub params: GenerationParams,
+    pub tile_map: TileMap,
+    pub spawns: Vec<Spawn>,
+}
+
+impl TextMap {
+    pub fn from_state(state: &DungeonState) -> TextMap {
+        TextMap {
+            seed: state.seed,
+            params: state.params.clone(),
+            tile_map: get_tile_map(&state.layout),
+            spawns: state.spawns.iter().cloned().collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<TextMap, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("could not read {}: {err}", path.display()))?;
+
+        TextMap::parse(&source).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    pub fn parse(source: &str) -> Result<TextMap, String> {
+        let mut text_map = TextMap {
+            seed: 0,
+            params: GenerationParams::default(),
+            tile_map: TileMap::default(),
+            spawns: Vec::new(),
+        };
+        let mut origin = IVec2::ZERO;
+        let mut lines = source.lines().enumerate().peekable();
+
+        while let Some((number, line)) = lines.next_if(|(_, line)| is_header(line)) {
+            let (name, value) = line.split_once(':').unwrap_or_default();
+            let value = value.trim();
+            let error = |message: String| format!("line {}: {message}", number + 1);
+
+            match name {
+                "seed" => text_map.seed = value.parse().map_err(|_| error(bad_number(value)))?,
+                "depth" => {
+                    text_map.params.depth = value.parse().map_err(|_| error(bad_number(value)))?
+                }
+                "origin" => origin = parse_position(value).map_err(error)?,
+                "params" => {
+                    text_map.params = GenerationParams {
+                        depth: text_map.params.depth,
+                        ..ron::from_str(value).map_err(|err| error(err.to_string()))?
+                    }
+                }
+                "spawn" => {
+                    let (marker, position) = value.split_once(' ').unwrap_or((value, ""));
+                    let spawn_type = marker
+                        .parse::<char>()
+                        .ok()
+                        .and_then(spawn_of)
+                        .ok_or_else(|| error(format!("unknown spawn marker {marker:?}")))?;
+
+                    text_map.spawns.push(Spawn {
+                        position: parse_position(position).map_err(error)?,
+                        spawn_type,
+                    });
+                }
+                _ => return Err(error(format!("unknown header {name:?}"))),
+            }
+        }
+
+        let rows: Vec<(usize, &str)> = lines
+            .skip_while(|(_, line)| line.trim().is_empty())
+            .collect();
+        let height = rows
+            .iter()
+            .rposition(|(_, row)| !row.trim().is_empty())
+            .map_or(0, |last| last + 1);
+
+        for (row_index, (number, row)) in rows.iter().take(height).enumerate() {
+            let y = origin.y + (height - 1 - row_index) as i32;
+
+            for (column, marker) in row.chars().enumerate() {
+                let position = IVec2::new(origin.x + column as i32, y);
+
+                if let Some(tile_type) = tile_of(marker) {
+                    if tile_type != TileType::Void {
+                        text_map.tile_map.set(position, tile_type);
+                    }
+                } else if let Some(spawn_type) = spawn_of(marker) {
+                    text_map.tile_map.set(position, TileType::Floor);
+                    text_map.spawns.push(Spawn {
+                        position,
+                        spawn_type,
+                    });
+                } else {
+                    return Err(format!("line {}: unknown tile {marker:?}", number + 1));
+                }
+            }
+        }
+
+        Ok(text_map)
+    }
+}
+
+impl fmt::Display for TextMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (min, max) = self
+            .tile_map
+            .tiles()
+            .map(|(position, _)| position)
+            .fold(None, |bounds: Option<(IVec2, IVec2)>, tile| match bounds {
+                Some((min, max)) => Some((min.min(tile), max.max(tile))),
+                None => Some((tile, tile)),
+            })
+            .unwrap_or((IVec2::ZERO, -IVec2::ONE));
+
+        // Spawns go on the map where they stand alone on floor and in the header otherwise, so the
+        // map never reaches beyond the tiles and its first and last rows are never blank.
+        let mut on_map: HashMap<IVec2, char> = HashMap::default();
+        let mut in_header = Vec::new();
+        for spawn in &self.spawns {
+            let alone = !on_map.contains_key(&spawn.position)
+                && self.tile_map.get(spawn.position) == TileType::Floor;
+
+            if alone {
+                on_map.insert(spawn.position, marker_of(&spawn.spawn_type));
+            } else {
+                in_header.push(spawn);
+            }
+        }
+
+        writeln!(f, "seed: {}", self.seed)?;
+        writeln!(f, "depth: {}", self.params.depth)?;
+        writeln!(f, "origin: {} {}", min.x, min.y)?;
+        writeln!(
+            f,
+            "params: {}",
+            ron::to_string(&self.params).map_err(|_| fmt::Error)?
+        )?;
+        for spawn in in_header {
+            writeln!(
+                f,
+                "spawn: {} {} {}",
+                marker_of(&spawn.spawn_type),
+                spawn.position.x,
+                spawn.position.y
+            )?;
+        }
+        writeln!(f)?;
+
+        for y in (min.y..=max.y).rev() {
+            let row: String = (min.x..=max.x)
+                .map(|x| {
+                    let position = IVec2::new(x, y);
+                    on_map
+                        .get(&position)
+                        .copied()
+                        .unwrap_or_else(|| marker_of_tile(self.tile_map.get(position)))
+                })
+                .collect();
+
+            writeln!(f, "{}", row.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_header(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+    })
+}
+
+fn bad_number(value: &str) -> String {
+    format!("{value:?} is not a number")
+}
+
+/// An `x y` pair, such as `-3 12`.
+fn parse_position(value: &str) -> Result<IVec2, String> {
+    let numbers: Vec<i32> = value
+        .split_whitespace()
+        .map(|number| number.parse().map_err(|_| bad_number(number)))
+        .collect::<Result<_, _>>()?;
+
+    match numbers[..] {
+        [x, y] => Ok(IVec2::new(x, y)),
+        _ => Err(format!(
+            "expected a position like \"3 -2\", found {value:?}"
+        )),
+    }
+}
+
+fn marker_of_tile(tile_type: TileType) -> char {
+    match tile_type {
+        TileType::Void => ' ',
+        TileType::Floor => '.',
+        TileType::Wall => '#',
+    }
+}
+
+fn tile_of(marker: char) -> Option<TileType> {
+    match marker {
+        ' ' => Some(TileType::Void),
+        '.' => Some(TileType::Floor),
+        '#' => Some(TileType::Wall),
+        _ => None,
+    }
+}
+
+fn marker_of(spawn_type: &SpawnType) -> char {
+    let colour = |colour: KeyColour| match colour {
+        KeyColour::Red => 'r',
+        KeyColour::Blue => 'b',
+        KeyColour::Green => 'g',
+        KeyColour::Gold => 'o',
+    };
+
+    match spawn_type {
+        SpawnType::Player => 'P',
+        SpawnType::Key(key) => colour(*key),
+        SpawnType::Door(door) => colour(*door).to_ascii_uppercase(),
+        SpawnType::Stairs(Stairs::Up) => '<',
+        SpawnType::Stairs(Stairs::Down) => '>',
+    }
+}
+
+fn spawn_of(marker: char) -> Option<SpawnType> {
+    let colour = match marker.to_ascii_lowercase() {
+        'r' => Some(KeyColour::Red),
+        'b' => Some(KeyColour::Blue),
+        'g' => Some(KeyColour::Green),
+        'o' => Some(KeyColour::Gold),
+        _ => None,
+    };
+
+    match (marker, colour) {
+        ('P', _) => Some(SpawnType::Player),
+        ('<', _) => Some(SpawnType::Stairs(Stairs::Up)),
+        ('>', _) => Some(SpawnType::Stairs(Stairs::Down)),
+        (_, Some(colour)) if marker.is_ascii_lowercase() => Some(SpawnType::Key(colour)),
+        (_, Some(colour)) => Some(SpawnType::Door(colour)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod text_map_tests {
+    use super::*;
+    use crate::dungeon_generation::dungeon_generator::fallback_dungeon;
+    use crate::dungeon_generation::recipe::GenerationRecipe;
+    use crate::dungeon_generation::solvability::flood_fill;
+    use bevy::utils::HashSet;
+
+    fn sorted_tiles(tile_map: &TileMap) -> Vec<(i32, i32, char)> {
+        let mut tiles: Vec<(i32, i32, char)> = tile_map
+            .tiles()
+            .map(|(position, tile_type)| (position.x, position.y, marker_of_tile(tile_type)))
+            .collect();
+        tiles.sort_unstable();
+        tiles
+    }
+
+    fn sorted_spawns(spawns: &[Spawn]) -> Vec<(i32, i32, char)> {
+        let mut spawns: Vec<(i32, i32, char)> = spawns
+            .iter()
+            .map(|spawn| {
+                (
+                    spawn.position.x,
+                    spawn.position.y,
+                    marker_of(&spawn.spawn_type),
+                )
+            })
+            .collect();
+        spawns.sort_unstable();
+        spawns
+    }
+
+    #[test]
+    fn generated_floors_survive_the_round_trip() {
+        let state = GenerationRecipe::default()
+            .to_generator(&Default::default())
+            .unwrap()
+            .depth(2)
+            .generate_with_seed(11)
+            .unwrap();
+        let written = TextMap::from_state(&state);
+
+        let read = TextMap::parse(&written.to_string()).unwrap();
+
+        assert_eq!(read.seed, 11);
+        assert_eq!(read.params, state.params);
+        assert_eq!(
+            sorted_tiles(&read.tile_map),
+            sorted_tiles(&written.tile_map)
+        );
+        assert_eq!(sorted_spawns(&read.spawns), sorted_spawns(&written.spawns));
+    }
+
+    #[test]
+    fn draws_the_map_top_row_first() {
+        let text = TextMap::from_state(&fallback_dungeon(3)).to_string();
+        let mut lines = text.lines();
+
+        assert_eq!(lines.next(), Some("seed: 3"));
+        assert_eq!(lines.next(), Some("depth: 0"));
+        assert_eq!(lines.next(), Some("origin: 20 20"));
+
+        let rows: Vec<&str> = lines.skip_while(|line| !line.is_empty()).skip(1).collect();
+        assert_eq!(rows.len(), 12);
+        assert_eq!(rows[0], "############");
+        assert_eq!(rows[5], "#.....P....#");
+    }
+
+    #[test]
+    fn spawns_sharing_a_tile_go_in_the_header() {
+        let mut state = fallback_dungeon(0);
+        state.spawns.push_back(Spawn {
+            position: state.spawns[0].position,
+            spawn_type: SpawnType::Stairs(Stairs::Up),
+        });
+
+        let text = TextMap::from_state(&state).to_string();
+        assert!(text.contains("spawn: < 26 26\n"), "{text}");
+
+        let read = TextMap::parse(&text).unwrap();
+        assert_eq!(
+            sorted_spawns(&read.spawns),
+            vec![(26, 26, '<'), (26, 26, 'P'), (26, 29, '>')]
+        );
+    }
+
+    #[test]
+    fn reads_hand_drawn_maps_without_a_header() {
+        let read = TextMap::parse("####\n#P>#\n####\n").unwrap();
+
+        assert_eq!(read.seed, 0);
+        assert_eq!(read.tile_map.get(IVec2::new(1, 1)), TileType::Floor);
+        assert_eq!(read.tile_map.get(IVec2::new(0, 2)), TileType::Wall);
+        assert_eq!(read.tile_map.get(IVec2::new(4, 1)), TileType::Void);
+        assert_eq!(sorted_spawns(&read.spawns), vec![(1, 1, 'P'), (2, 1, '>')]);
+    }
+
+    #[test]
+    fn checked_in_maps_can_be_played_out() {
+        let read = TextMap::parse(include_str!("../assets/maps/locked_door.txt")).unwrap();
+        let player = IVec2::new(1, 2);
+        let door: HashSet<IVec2> = [IVec2::new(4, 2)].into_iter().collect();
+
+        assert_eq!(
+            sorted_spawns(&read.spawns),
+            vec![(1, 2, 'P'), (3, 2, 'o'), (4, 2, 'O'), (7, 2, '>')]
+        );
+        assert!(!flood_fill(&read.tile_map, player, &door).contains(&IVec2::new(7, 2)));
+        assert!(flood_fill(&read.tile_map, player, &HashSet::default()).contains(&IVec2::new(7, 2)));
+    }
+
+    #[test]
+    fn reports_where_the_text_is_wrong() {
+        assert_eq!(
+            TextMap::parse("seed: 1\n\n#.#\n#x#\n").unwrap_err(),
+            "line 4: unknown tile 'x'"
+        );
+        assert_eq!(
+            TextMap::parse("colour: red\n").unwrap_err(),
+            "line 1: unknown header \"colour\""
+        );
+        assert_eq!(
+            TextMap::parse("origin: 1\n").unwrap_err(),
+            "line 1: expected a position like \"3 -2\", found \"1\""
+        );
+    }
+}